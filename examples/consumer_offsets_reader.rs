@@ -9,7 +9,7 @@ extern crate rdkafka;
 use byteorder::{BigEndian, ReadBytesExt};
 use clap::{App, Arg};
 use futures::stream::Stream;
-use rdkafka::config::{ClientConfig, TopicConfig};
+use rdkafka::config::ClientConfig;
 use rdkafka::consumer::stream_consumer::StreamConsumer;
 use rdkafka::consumer::Consumer;
 use rdkafka::message::Message;
@@ -104,11 +104,7 @@ fn consume_and_print(brokers: &str) {
         .set("enable.partition.eof", "false")
         .set("session.timeout.ms", "30000")
         .set("enable.auto.commit", "false")
-        .set_default_topic_config(
-            TopicConfig::new()
-                .set("auto.offset.reset", "smallest")
-                .finalize(),
-        )
+        .set("auto.offset.reset", "smallest")
         .create::<StreamConsumer<_>>()
         .expect("Consumer creation failed");
 
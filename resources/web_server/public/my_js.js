@@ -119,6 +119,11 @@ function message_to_tailer_entry(msg) {
     var entry = $("<div>", {class: "message"});
     entry.append($("<div>", { class: "message-key", text: msg["key"] ? msg["key"] : "N/A" }));
     entry.append($("<div>", { class: "message-ts", text: ts_text }));
+    entry.append($("<div>", { class: "message-decoder", text: msg["decoder"] }));
+    if (msg["headers"] && msg["headers"].length > 0) {
+        var headers = $.map(msg["headers"], function(header) { return header[0] + ": " + header[1]; });
+        entry.append($("<div>", { class: "message-headers", text: headers.join(", ") }));
+    }
     entry.append($("<div>", { class: "message-payload", text: msg["payload"] }));
     return entry;
 }
@@ -131,7 +136,7 @@ $(document).ready(function() {
             "lengthMenu": [ [10, 50, 200, -1], [10, 50, 200, "All"] ],
             "language": { "search": "Regex search:" },
             "columnDefs": [
-                { "className": "dt-body-right", "targets": [ 2, 3 ] }
+                { "className": "dt-body-right", "targets": [ 2, 3, 5, 6 ] }
             ],
             "processing": true,
             "deferRender": true,
@@ -141,6 +146,7 @@ $(document).ready(function() {
                 // broker_to_url(cluster_id, $(row).children()[0]);
                 bytes_to_human($(row).children()[2], "/s");
                 big_num_to_human($(row).children()[3], "msg/s");
+                bytes_to_human($(row).children()[5], "");
             }
         });
     });
@@ -155,8 +161,8 @@ $(document).ready(function() {
                 { "data": "topic_name" },
                 { "data": "partition_count" },
                 { "data": "errors" },
-                { "data": "b_rate_15" },
-                { "data": "m_rate_15" }
+                { "data": "b_rate" },
+                { "data": "m_rate" }
             ],
             "columnDefs": [
                 { "className": "dt-body-right", "targets": [ 1, 2, 3, 4 ] }
@@ -220,7 +226,28 @@ $(document).ready(function() {
                 var cluster_id = $(this).attr("data-param");
                 // broker_to_url(cluster_id, $(row).children()[1]);
                 bytes_to_human($(row).children()[1], "");
-                error_to_graphic($(row).children()[5]);
+                bytes_to_human($(row).children()[5], "");
+                error_to_graphic($(row).children()[7]);
+            }
+        });
+    });
+    $('#datatable-diverging-replicas-ajax').each(function(index) {
+        $(this).DataTable({
+            "search": { "regex": true},
+            "ajax": $(this).attr("data-url"),
+            "lengthMenu": [ [10, 50, 200, -1], [10, 50, 200, "All"] ],
+            "language": { "search": "Regex search:" },
+            "columnDefs": [
+                { "className": "dt-body-right", "targets": [ 3, 5 ] }
+            ],
+            "processing": true,
+            "deferRender": true,
+            stateSave: true,
+            "createdRow": function(row, data, index) {
+                var cluster_id = $(this).attr("data-param");
+                topic_to_url(cluster_id, $(row).children()[0]);
+                bytes_to_human($(row).children()[3], "");
+                bytes_to_human($(row).children()[5], "");
             }
         });
     });
@@ -301,6 +328,61 @@ $(document).ready(function() {
             }
         });
     });
+    $('#datatable-acls-ajax').each(function(index) {
+        $(this).DataTable({
+            "search": { "regex": true},
+            "ajax": $(this).attr("data-url"),
+            "lengthMenu": [ [10, 50, 200, -1], [10, 50, 200, "All"] ],
+            "language": { "search": "Regex search:" },
+            "processing": true,
+            "deferRender": true,
+            "stateSave": true
+        });
+    });
+    $('#datatable-controller-changes-ajax').each(function(index) {
+        $(this).DataTable({
+            "ajax": $(this).attr("data-url"),
+            "lengthMenu": [ [10, 50, -1], [10, 50, "All"] ],
+            "order": [[ 0, "desc" ]],
+            "processing": true,
+            "deferRender": true,
+            "createdRow": function(row, data, index) {
+                var cluster_id = $(this).attr("data-param");
+                if (data[1] != "None") {
+                    broker_to_url(cluster_id, $(row).children()[1]);
+                }
+            }
+        });
+    });
+    $('#datatable-quotas-ajax').each(function(index) {
+        $(this).DataTable({
+            "search": { "regex": true},
+            "ajax": $(this).attr("data-url"),
+            "lengthMenu": [ [10, 50, 200, -1], [10, 50, 200, "All"] ],
+            "language": { "search": "Regex search:" },
+            "columnDefs": [
+                { "className": "dt-body-right", "targets": [ 2, 3, 4 ] }
+            ],
+            "processing": true,
+            "deferRender": true,
+            "stateSave": true
+        });
+    });
+    $('#datatable-principal-search-ajax').each(function(index) {
+        $(this).DataTable({
+            "searching": false,
+            "ajax": $(this).attr("data-url"),
+            "lengthMenu": [ [10, 50, 200, -1], [10, 50, 200, "All"] ],
+            "pageLength": 50,
+            "processing": true,
+            "deferRender": true,
+            "stateSave": true,
+            "createdRow": function(row, data, index) {
+                var row = $(row).children();
+                cluster_to_url(row[0]);
+            }
+        });
+    });
     $('#datatable-internals-cache-brokers-ajax').each(function(index) {
         var table = $(this).DataTable({
             "ajax": $(this).attr("data-url"),
@@ -327,7 +409,7 @@ $(document).ready(function() {
             table.ajax.reload();
         }, 20000 );
     });
-    $('#datatable-internals-cache-offsets-ajax').each(function(index) {
+    $('#datatable-internals-cache-offsets-ajax, #datatable-internals-cache-zk-offsets-ajax').each(function(index) {
         var table = $(this).DataTable({
             "ajax": $(this).attr("data-url"),
             "lengthMenu": [ [10, 50, 200, -1], [10, 50, 200, "All"] ],
@@ -353,6 +435,26 @@ $(document).ready(function() {
             table.ajax.reload();
         }, 20000 );
     });
+    $('#datatable-internals-offset-consumers-ajax').each(function(index) {
+        var table = $(this).DataTable({
+            "ajax": $(this).attr("data-url"),
+            "lengthMenu": [ [10, 50, -1], [10, 50, "All"] ],
+            "pageLength": 10,
+            "columnDefs": [
+                { "className": "dt-body-right", "targets": [ 2, 3 ] }
+            ],
+            "processing": true,
+            "deferRender": true,
+            "stateSave": true,
+            "createdRow": function(row, data, index) {
+                cluster_to_url($(row).children()[0]);
+                big_num_to_human($(row).children()[3], "msg");
+            }
+        });
+        setInterval( function () {
+            table.ajax.reload();
+        }, 20000 );
+    });
 });
 
 function truncate(string, max_len) {
@@ -378,59 +480,331 @@ var poll_interval = 1000;
 
 var tailer_active = true;
 
-function background_tailer(cluster_id, topic_name, tailer_id) {
+// The query of a tailer request: the start position and the header filter.
+function tailer_params(div_tailer, start, value) {
+  var params = {};
+  if (start) {
+    params.start = start;
+    params.value = value || "";
+  }
+  if (div_tailer.attr("data-header")) {
+    params.header = div_tailer.attr("data-header");
+    params.header_value = div_tailer.attr("data-header-value") || "";
+  }
+  return params;
+}
+
+// The tailer id and start position are read from the div at every request, since they change
+// when the tailer is restarted.
+function background_tailer(div_tailer) {
   if (!tailer_active) {
-    setTimeout(function(){background_tailer(cluster_id, topic_name, tailer_id)}, poll_interval);
+    setTimeout(function(){background_tailer(div_tailer)}, poll_interval);
     return
   }
-  var url = '/api/tailer/' + cluster_id + '/' + topic_name + '/' + tailer_id;
+  var tailer_id = div_tailer.attr("data-tailer");
+  var url = '/api/tailer/' + div_tailer.attr("data-cluster") + '/' + div_tailer.attr("data-topic") + '/' + tailer_id;
+  var params = tailer_params(div_tailer, div_tailer.attr("data-start"), div_tailer.attr("data-value"));
+  if (!$.isEmptyObject(params)) {
+    url += '?' + $.param(params);
+  }
   $.ajax({
     url: url,
     success: function(data) {
-      var div_tailer = $('div.topic_tailer');
-      var bottom = isScrolledToBottom(div_tailer);
-      messages = JSON.parse(data);
-      for (var i = 0; i < messages.length; i++) {
-        var message = messages[i];
-        div_tailer.append(message_to_tailer_entry(message));
+      if (div_tailer.attr("data-tailer") != tailer_id) {
+        return;  // restarted in the meantime
       }
-      if (bottom)
-          scroll_to_bottom(div_tailer);
-      var message_count = div_tailer.children().length;
-      if (message_count > max_msg_count)
-          div_tailer.children().slice(0, message_count - max_msg_count).remove();
+      append_tailer_messages(div_tailer, JSON.parse(data));
     },
     error: function(data) {
       console.log("error");
     },
     complete: function() {
       // Schedule the next request when the current one's complete
-      setTimeout(function(){background_tailer(cluster_id, topic_name, tailer_id)}, poll_interval);
+      setTimeout(function(){background_tailer(div_tailer)}, poll_interval);
     }
   });
 }
 
+function append_tailer_messages(div_tailer, messages) {
+  var bottom = isScrolledToBottom(div_tailer);
+  for (var i = 0; i < messages.length; i++) {
+    div_tailer.append(message_to_tailer_entry(messages[i]));
+  }
+  if (bottom)
+      scroll_to_bottom(div_tailer);
+  var message_count = div_tailer.children().length;
+  if (message_count > max_msg_count)
+      div_tailer.children().slice(0, message_count - max_msg_count).remove();
+}
+
+// Receives the messages as Server-Sent Events. Pausing closes the stream, and resuming opens a
+// new one from the offsets following the last received messages. Each stream gets its own
+// consumer on the server.
+function stream_tailer(div_tailer) {
+  var source = null;
+  var offsets = {};
+
+  function open() {
+    var url = location.protocol + '//' + location.hostname + ':' + div_tailer.attr("data-stream-port") +
+        '/tailer/' + encodeURIComponent(div_tailer.attr("data-cluster")) + '/' +
+        encodeURIComponent(div_tailer.attr("data-topic"));
+    var resume_offsets = $.map(offsets, function(offset, partition) { return partition + ':' + offset; });
+    var params;
+    if (resume_offsets.length > 0) {
+      params = tailer_params(div_tailer, "offsets", resume_offsets.join(','));
+    } else {
+      params = tailer_params(div_tailer, div_tailer.attr("data-start"), div_tailer.attr("data-value"));
+    }
+    if (!$.isEmptyObject(params)) {
+      url += '?' + $.param(params);
+    }
+    source = new EventSource(url);
+    source.onmessage = function(event) {
+      var messages = JSON.parse(event.data);
+      for (var i = 0; i < messages.length; i++) {
+        offsets[messages[i]["partition"]] = messages[i]["offset"] + 1;
+      }
+      append_tailer_messages(div_tailer, messages);
+    };
+    // The position of every partition, including the ones without any message received, so
+    // that resuming doesn't skip the messages produced while paused.
+    source.addEventListener("positions", function(event) {
+      $.each(JSON.parse(event.data), function(partition, offset) {
+        offsets[partition] = Math.max(offsets[partition] || 0, offset);
+      });
+    });
+    source.addEventListener("failure", function(event) {
+      close();
+      div_tailer.append($("<div>", { class: "alert alert-danger", text: event.data }));
+    });
+    source.onerror = function() {
+      // Reconnect with a new consumer, from the positions received so far
+      close();
+      setTimeout(function() {
+        if (tailer_active && source == null)
+          open();
+      }, poll_interval);
+    };
+  }
+
+  function close() {
+    if (source != null) {
+      source.close();
+      source = null;
+    }
+  }
+
+  div_tailer.on("tailer-restart", function() {
+    close();
+    offsets = {};
+    if (tailer_active)
+      open();
+  });
+  div_tailer.on("tailer-pause", close);
+  div_tailer.on("tailer-resume", function() {
+    if (source == null)
+      open();
+  });
+  if (tailer_active)
+    open();
+}
+
 // Load topic tailers
 $(document).ready(function() {
     $('.topic_tailer').each(function(index) {
-        var cluster_id = $(this).attr("data-cluster");
-        var topic_name = $(this).attr("data-topic");
-        var tailer_id = $(this).attr("data-tailer");
-        background_tailer(cluster_id, topic_name, tailer_id);
+        if ($(this).attr("data-stream-port")) {
+            stream_tailer($(this));
+        } else {
+            background_tailer($(this));
+        }
+    });
+    $('form.tailer-start').each(function(index) {
+        var form = $(this);
+        form.find('.tailer-restart').click(function(event) {
+            event.preventDefault();
+            var div_tailer = $('div.topic_tailer');
+            div_tailer.empty();
+            div_tailer.attr("data-start", form.find('[name=start]').val());
+            div_tailer.attr("data-value", form.find('[name=value]').val());
+            div_tailer.attr("data-header", form.find('[name=header]').val());
+            div_tailer.attr("data-header-value", form.find('[name=header_value]').val());
+            div_tailer.attr("data-tailer", Math.floor(Math.random() * 1000000000000));
+            div_tailer.trigger("tailer-restart");
+        });
     });
     $('#start_tailer_button').click(function(event) {
         event.preventDefault();
         $('#tailer_button_label').html("Topic tailer: active")
         tailer_active = true;
+        $('div.topic_tailer').trigger("tailer-resume");
     })
     $('#stop_tailer_button').click(function(event) {
         event.preventDefault();
         $('#tailer_button_label').html("Topic tailer: stopped")
         tailer_active = false;
+        $('div.topic_tailer').trigger("tailer-pause");
     })
 });
 
+function offset_reset_result(div, response) {
+    div.empty();
+    if (response["error"]) {
+        div.append($("<div>", { class: "alert alert-danger", text: response["error"] }));
+        return;
+    }
+    var table = $("<table>", { class: "table table-striped table-bordered" });
+    var header = $("<tr>");
+    ["Topic", "Partition", "Current offset", "New offset", "Low mark", "High mark", "Lag"].forEach(function(name) {
+        header.append($("<th>", { text: name }));
+    });
+    table.append($("<thead>").append(header));
+    var body = $("<tbody>");
+    response["data"].forEach(function(reset) {
+        var row = $("<tr>");
+        ["topic", "partition", "current_offset", "new_offset", "low", "high", "lag"].forEach(function(field) {
+            row.append($("<td>", { text: reset[field] }));
+        });
+        body.append(row);
+    });
+    div.append(table.append(body));
+}
+
+function offset_reset_request(form, method, data, result_div) {
+    $.ajax({
+        url: form.attr("data-url"),
+        method: method,
+        data: data,
+        success: function(data) {
+            var response = JSON.parse(data);
+            offset_reset_result(result_div, response);
+            // Only a successful preview can be executed, and only once
+            form.data("plan", method == "GET" && !response["error"] ? response["data"] : null);
+        },
+        error: function(data) {
+            console.log("error");
+        }
+    });
+}
+
+$(document).ready(function() {
+    $('form.offset-reset').each(function(index) {
+        var form = $(this);
+        var result_div = form.next('div.offset-reset-result');
+        form.find(':input').change(function() {
+            form.data("plan", null);
+        });
+        form.find('.offset-reset-preview').click(function(event) {
+            event.preventDefault();
+            var params = form.serializeArray().filter(function(item) { return item.value !== ""; });
+            offset_reset_request(form, "GET", $.param(params), result_div);
+        });
+        form.find('.offset-reset-execute').click(function(event) {
+            event.preventDefault();
+            var plan = form.data("plan");
+            if (!plan) {
+                alert("Preview the reset before executing it.");
+                return;
+            }
+            if (confirm("Reset the offsets of this group as previewed? This can't be undone.")) {
+                // The form didn't change since the preview, the server computes the plan again.
+                var params = form.serializeArray().filter(function(item) { return item.value !== ""; });
+                params.push({ name: "plan", value: JSON.stringify(plan) });
+                offset_reset_request(form, "POST", $.param(params), result_div);
+            }
+        });
+    });
+});
+
 $(document).ready(function(){
     $('[data-toggle="tooltip"]').tooltip();
     $(window).resize();
 });
+
+function message_search_progress(div, status) {
+    var text = "Scanned " + status["scanned"] + " of " + status["total"] + " messages in "
+        + status["partitions_started"] + "/" + status["partitions"] + " partitions ("
+        + _bytes_to_human(status["bytes_scanned"], "").text() + "), " + status["match_count"] + " matches: "
+        + status["state"].replace("_", " ");
+    if (status["message"]) {
+        text += " (" + status["message"] + ")";
+    }
+    div.text(text);
+}
+
+function message_search_poll(form, job_id) {
+    var table = form.nextAll('table.message-search-results').first();
+    var progress = form.nextAll('div.message-search-progress').first();
+    if (form.attr("data-job") != job_id) {
+        return;  // a new search was started
+    }
+    var from = table.find('tbody tr').length;
+    $.ajax({
+        url: "/api/search_jobs/" + job_id + "?from=" + from,
+        success: function(data) {
+            var status = JSON.parse(data);
+            if (status["error"]) {
+                progress.html($("<div>", { class: "alert alert-danger", text: status["error"] }));
+                return;
+            }
+            var cluster_id = form.attr("data-cluster");
+            var topic = form.attr("data-topic");
+            status["matches"].forEach(function(match) {
+                var url = "/clusters/" + cluster_id + "/topics/" + topic + "/" + match["partition"] + "/" + match["offset"];
+                var row = $("<tr>");
+                row.append($("<td>", { text: match["partition"] }));
+                row.append($("<td>").append($("<a>", { text: match["offset"], href: url })));
+                row.append($("<td>", { text: match["timestamp"] ? (new Date(match["timestamp"])).toISOString() : "N/A" }));
+                row.append($("<td>", { text: match["key"] !== null ? match["key"] : "N/A" }));
+                row.append($("<td>", { text: match["payload"], style: "word-break: break-all" }));
+                table.find('tbody').append(row);
+            });
+            message_search_progress(progress, status);
+            if (status["state"] == "running") {
+                setTimeout(function(){ message_search_poll(form, job_id) }, poll_interval);
+            }
+        },
+        error: function(data) {
+            console.log("error");
+        }
+    });
+}
+
+$(document).ready(function() {
+    $('form.message-search').each(function(index) {
+        var form = $(this);
+        var table = form.nextAll('table.message-search-results').first();
+        var progress = form.nextAll('div.message-search-progress').first();
+        form.find('.message-search-start').click(function(event) {
+            event.preventDefault();
+            var params = form.serializeArray().filter(function(item) { return item.value !== ""; });
+            $.ajax({
+                url: form.attr("data-url"),
+                method: "POST",
+                data: $.param(params),
+                success: function(data) {
+                    var response = JSON.parse(data);
+                    table.find('tbody').empty();
+                    if (response["error"]) {
+                        table.hide();
+                        progress.html($("<div>", { class: "alert alert-danger", text: response["error"] }));
+                        return;
+                    }
+                    table.show();
+                    progress.text("Starting search...");
+                    form.attr("data-job", response["job"]);
+                    message_search_poll(form, response["job"]);
+                },
+                error: function(data) {
+                    console.log("error");
+                }
+            });
+        });
+        form.find('.message-search-cancel').click(function(event) {
+            event.preventDefault();
+            var job_id = form.attr("data-job");
+            if (job_id) {
+                $.ajax({ url: "/api/search_jobs/" + job_id + "/cancel", method: "POST" });
+            }
+        });
+    });
+});
@@ -0,0 +1,133 @@
+use scheduled_executor::TaskGroup;
+
+use cache::Cache;
+use config::Config;
+use error::*;
+use metadata::ClusterId;
+use zk::ZK_SESSIONS;
+
+use std::fmt;
+
+/// How the resource name of an ACL is matched.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum PatternType {
+    Literal,
+    Prefixed,
+}
+
+impl fmt::Display for PatternType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            PatternType::Literal => "Literal",
+            PatternType::Prefixed => "Prefixed",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A single ACL entry, as stored by the Kafka authorizer.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Acl {
+    /// `Topic`, `Group`, `Cluster`, `TransactionalId` or `DelegationToken`.
+    pub resource_type: String,
+    pub resource_name: String,
+    pub pattern_type: PatternType,
+    /// For example `User:alice`.
+    pub principal: String,
+    pub operation: String,
+    /// `Allow` or `Deny`.
+    pub permission_type: String,
+    pub host: String,
+}
+
+impl Acl {
+    /// True if the ACL applies to the resource.
+    pub fn matches(&self, resource_type: &str, resource_name: &str) -> bool {
+        if self.resource_type != resource_type {
+            return false;
+        }
+        match self.pattern_type {
+            PatternType::Literal => {
+                self.resource_name == "*" || self.resource_name == resource_name
+            }
+            PatternType::Prefixed => resource_name.starts_with(&self.resource_name),
+        }
+    }
+
+    /// The resource pattern, such as `Topic:orders` or `Group:billing-*` for prefixed ACLs.
+    pub fn resource_pattern(&self) -> String {
+        match self.pattern_type {
+            PatternType::Literal => format!("{}:{}", self.resource_type, self.resource_name),
+            PatternType::Prefixed => format!("{}:{}*", self.resource_type, self.resource_name),
+        }
+    }
+}
+
+/// Periodically reads the ACLs of each cluster from ZooKeeper, and stores them in
+/// `Cache::acls`.
+pub struct AclFetchTaskGroup {
+    cache: Cache,
+    config: Config,
+}
+
+impl AclFetchTaskGroup {
+    pub fn new(cache: &Cache, config: &Config) -> AclFetchTaskGroup {
+        AclFetchTaskGroup {
+            cache: cache.alias(),
+            config: config.clone(),
+        }
+    }
+
+    fn fetch_acls(&self, cluster_id: &ClusterId) -> Result<()> {
+        let cluster_config = self.config.cluster(cluster_id).unwrap();
+        let zk = ZK_SESSIONS.get_or_init(cluster_id, cluster_config, &self.cache)?;
+        let acls = zk
+            .acls()
+            .chain_err(|| "Failed to read ACLs from ZooKeeper")?;
+        self.cache
+            .acls
+            .insert(cluster_id.clone(), acls)
+            .chain_err(|| "Failed to insert ACLs in cache")
+    }
+}
+
+impl TaskGroup for AclFetchTaskGroup {
+    type TaskId = ClusterId;
+
+    fn get_tasks(&self) -> Vec<ClusterId> {
+        self.config.zookeeper_clusters()
+    }
+
+    fn execute(&self, cluster_id: ClusterId) {
+        if let Err(e) = self.fetch_acls(&cluster_id) {
+            format_error_chain!(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn acl(resource_type: &str, resource_name: &str, pattern_type: PatternType) -> Acl {
+        Acl {
+            resource_type: resource_type.to_owned(),
+            resource_name: resource_name.to_owned(),
+            pattern_type,
+            principal: "User:alice".to_owned(),
+            operation: "Read".to_owned(),
+            permission_type: "Allow".to_owned(),
+            host: "*".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_acl_matches() {
+        assert!(acl("Topic", "orders", PatternType::Literal).matches("Topic", "orders"));
+        assert!(!acl("Topic", "orders", PatternType::Literal).matches("Topic", "orders2"));
+        assert!(!acl("Topic", "orders", PatternType::Literal).matches("Group", "orders"));
+        assert!(acl("Topic", "*", PatternType::Literal).matches("Topic", "payments"));
+        assert!(acl("Group", "billing-", PatternType::Prefixed).matches("Group", "billing-eu"));
+        assert!(!acl("Group", "billing-", PatternType::Prefixed).matches("Group", "billing"));
+    }
+}
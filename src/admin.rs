@@ -0,0 +1,249 @@
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer, DefaultConsumerContext};
+use rdkafka::{Offset, TopicPartitionList};
+
+use cache::Cache;
+use config::ClusterConfig;
+use error::*;
+use metadata::{ClusterId, CONSUMERS};
+use offsets::offset_for_timestamp;
+use utils::insert_at;
+
+use std::cmp;
+use std::time::Duration;
+
+type AdminConsumer = BaseConsumer<DefaultConsumerContext>;
+
+/// The position a consumer group offset should be reset to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResetTarget {
+    Earliest,
+    Latest,
+    /// First message with a timestamp greater or equal to the given one (millis since epoch).
+    Timestamp(i64),
+    Offset(i64),
+    /// Move the current offset by the given number of messages, back if negative.
+    Shift(i64),
+}
+
+impl ResetTarget {
+    pub fn parse(strategy: &str, value: Option<i64>) -> Result<ResetTarget> {
+        let target = match (strategy, value) {
+            ("earliest", _) => ResetTarget::Earliest,
+            ("latest", _) => ResetTarget::Latest,
+            ("timestamp", Some(ts)) => ResetTarget::Timestamp(ts),
+            ("offset", Some(offset)) => ResetTarget::Offset(offset),
+            ("shift", Some(shift)) => ResetTarget::Shift(shift),
+            ("timestamp", None) | ("offset", None) | ("shift", None) => {
+                bail!("A value is required for the '{}' strategy", strategy)
+            }
+            _ => bail!("Unknown reset strategy: {}", strategy),
+        };
+        Ok(target)
+    }
+}
+
+/// The effect of an offset reset on a single partition.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PartitionReset {
+    pub topic: String,
+    pub partition: i32,
+    pub current_offset: i64,
+    pub new_offset: i64,
+    pub low: i64,
+    pub high: i64,
+    pub lag: i64,
+}
+
+fn create_consumer(cluster_config: &ClusterConfig, group: &str) -> Result<AdminConsumer> {
+    ClientConfig::new()
+        .set("bootstrap.servers", &cluster_config.bootstrap_servers())
+        .set("group.id", group)
+        .set("enable.auto.commit", "false")
+        .set("api.version.request", "true")
+        .create::<AdminConsumer>()
+        .chain_err(|| "Consumer creation failed")
+}
+
+/// Fails if the group has active members, either in the cache or in the cluster.
+fn ensure_group_inactive(cache: &Cache, cluster_id: &ClusterId, group: &str) -> Result<()> {
+    let cached_members = cache
+        .groups
+        .get(&(cluster_id.clone(), group.to_owned()))
+        .map(|group| group.members.len())
+        .unwrap_or(0);
+    if cached_members > 0 {
+        bail!("Group {} has {} active members", group, cached_members);
+    }
+
+    let group_list = CONSUMERS
+        .get_err(cluster_id)?
+        .fetch_group_list(Some(group), Duration::from_secs(10))
+        .chain_err(|| "Failed to fetch group membership")?;
+    if let Some(rd_group) = group_list.groups().iter().find(|g| g.name() == group) {
+        if !rd_group.members().is_empty() {
+            bail!(
+                "Group {} has {} active members",
+                group,
+                rd_group.members().len()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Computes the new offsets of the group for the given topic, and optionally partition,
+/// without committing them.
+pub fn plan_reset(
+    cache: &Cache,
+    cluster_config: &ClusterConfig,
+    group: &str,
+    topic: &str,
+    partition: Option<i32>,
+    target: ResetTarget,
+) -> Result<Vec<PartitionReset>> {
+    let cluster_id = cluster_config.cluster_id.clone().unwrap();
+    let partitions = match cache.topics.get(&(cluster_id.clone(), topic.to_owned())) {
+        Some(partitions) => partitions,
+        None => bail!("Topic {} not found", topic),
+    };
+    let current_offsets = cache
+        .offsets
+        .get(&(cluster_id, group.to_owned(), topic.to_owned()))
+        .unwrap_or_default();
+    let consumer = create_consumer(cluster_config, group)?;
+
+    let mut plan = Vec::new();
+    for p in partitions {
+        if partition.map(|id| id != p.id).unwrap_or(false) {
+            continue;
+        }
+        let (low, high) = consumer
+            .fetch_watermarks(topic, p.id, Duration::from_secs(10))
+            .chain_err(|| format!("Failed to fetch watermarks for {}/{}", topic, p.id))?;
+        let current_offset = current_offsets.get(p.id as usize).cloned().unwrap_or(-1);
+        let new_offset = match target {
+            ResetTarget::Earliest => low,
+            ResetTarget::Latest => high,
+            ResetTarget::Timestamp(ts) => offset_for_timestamp(&consumer, topic, p.id, ts)?,
+            ResetTarget::Offset(offset) => offset,
+            ResetTarget::Shift(_) if current_offset < 0 => {
+                bail!("No committed offset to shift for {}/{}", topic, p.id)
+            }
+            ResetTarget::Shift(shift) => current_offset.saturating_add(shift),
+        };
+        let new_offset = cmp::min(cmp::max(new_offset, low), high);
+        plan.push(PartitionReset {
+            topic: topic.to_owned(),
+            partition: p.id,
+            current_offset,
+            new_offset,
+            low,
+            high,
+            lag: high - new_offset,
+        });
+    }
+    if plan.is_empty() {
+        bail!("Partition not found in topic {}", topic);
+    }
+    Ok(plan)
+}
+
+/// Fails if the plan doesn't reset the same partitions as the preview, or if the offsets of the
+/// group changed since the preview.
+fn ensure_plan_current(plan: &[PartitionReset], preview: &[PartitionReset]) -> Result<()> {
+    let same_partitions = plan.len() == preview.len()
+        && plan.iter().zip(preview).all(|(reset, previewed)| {
+            reset.topic == previewed.topic && reset.partition == previewed.partition
+        });
+    if !same_partitions {
+        bail!("The partitions changed since the preview, preview the reset again");
+    }
+    for (reset, previewed) in plan.iter().zip(preview) {
+        if reset.current_offset != previewed.current_offset {
+            bail!(
+                "The offset of {}/{} changed since the preview ({} instead of {}), preview the \
+                 reset again",
+                reset.topic,
+                reset.partition,
+                reset.current_offset,
+                previewed.current_offset
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Commits the offsets of a previewed reset for the group, as long as the committed offsets
+/// didn't change since the preview. The new offsets are computed again from the target, against
+/// the current watermarks, and returned. The group must not have any active member.
+pub fn execute_reset(
+    cache: &Cache,
+    cluster_config: &ClusterConfig,
+    group: &str,
+    topic: &str,
+    partition: Option<i32>,
+    target: ResetTarget,
+    preview: &[PartitionReset],
+) -> Result<Vec<PartitionReset>> {
+    let cluster_id = cluster_config.cluster_id.clone().unwrap();
+    let plan = plan_reset(cache, cluster_config, group, topic, partition, target)?;
+    ensure_plan_current(&plan, preview)?;
+    ensure_group_inactive(cache, &cluster_id, group)?;
+
+    let mut tp_list = TopicPartitionList::new();
+    for reset in &plan {
+        tp_list.add_partition_offset(
+            &reset.topic,
+            reset.partition,
+            Offset::Offset(reset.new_offset),
+        );
+    }
+    let consumer = create_consumer(cluster_config, group)?;
+    consumer
+        .commit(&tp_list, CommitMode::Sync)
+        .chain_err(|| format!("Failed to commit offsets for group {}", group))?;
+    info!(
+        "Offsets of group {} reset in {}: {:?}",
+        group, cluster_id, plan
+    );
+
+    // Update the cache right away, without waiting for the offset consumer.
+    for reset in &plan {
+        let key = (cluster_id.clone(), group.to_owned(), reset.topic.clone());
+        let mut offsets = cache.offsets.get(&key).unwrap_or_default();
+        insert_at(&mut offsets, reset.partition as usize, reset.new_offset, -1);
+        cache
+            .offsets
+            .insert(key, offsets)
+            .chain_err(|| "Failed to update offsets in cache")?;
+    }
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reset(partition: i32, current_offset: i64, new_offset: i64) -> PartitionReset {
+        PartitionReset {
+            topic: "topic".to_owned(),
+            partition,
+            current_offset,
+            new_offset,
+            low: 0,
+            high: 100,
+            lag: 100 - new_offset,
+        }
+    }
+
+    #[test]
+    fn test_ensure_plan_current() {
+        let preview = vec![reset(0, 10, 0), reset(1, 20, 0)];
+        // The new offsets are the ones computed at execution time.
+        assert!(ensure_plan_current(&[reset(0, 10, 5), reset(1, 20, 5)], &preview).is_ok());
+        assert!(ensure_plan_current(&[reset(0, 10, 0), reset(1, 25, 0)], &preview).is_err());
+        assert!(ensure_plan_current(&[reset(0, 10, 0)], &preview).is_err());
+        assert!(ensure_plan_current(&[reset(0, 10, 0), reset(2, 20, 0)], &preview).is_err());
+    }
+}
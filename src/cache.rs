@@ -1,12 +1,12 @@
 use futures::stream::Stream;
 use rand::random;
-use rdkafka::client::EmptyContext;
-use rdkafka::config::{ClientConfig, TopicConfig};
+use rdkafka::client::DefaultClientContext;
+use rdkafka::config::ClientConfig;
 use rdkafka::consumer::stream_consumer::StreamConsumer;
-use rdkafka::consumer::{Consumer, EmptyConsumerContext};
+use rdkafka::consumer::{Consumer, DefaultConsumerContext};
 use rdkafka::error::KafkaError;
 use rdkafka::message::{BorrowedMessage, Message, OwnedMessage};
-use rdkafka::producer::FutureProducer;
+use rdkafka::producer::{FutureProducer, FutureRecord};
 use rdkafka::util::{duration_to_millis, millis_to_epoch};
 use serde::de::{Deserialize, DeserializeOwned};
 use serde::ser::Serialize;
@@ -19,9 +19,13 @@ use std::hash::Hash;
 use std::sync::{Arc, RwLock};
 use std::time::{Duration, SystemTime};
 
+use acls::Acl;
+use disk::DiskUsageSample;
 use error::*;
-use metadata::{Broker, ClusterId, Group, Partition, TopicName};
+use metadata::{Broker, BrokerId, ClusterId, ClusterInfo, Group, Partition, TopicName};
 use metrics::TopicMetrics;
+use quotas::Quota;
+use zk::ZkClusterState;
 
 #[derive(Serialize, Deserialize, Debug, Hash, Eq, PartialEq)]
 struct WrappedKey(String, String);
@@ -49,7 +53,7 @@ impl WrappedKey {
 
 pub struct ReplicaWriter {
     topic_name: String,
-    producer: FutureProducer<EmptyContext>,
+    producer: FutureProducer<DefaultClientContext>,
 }
 
 impl ReplicaWriter {
@@ -88,12 +92,11 @@ impl ReplicaWriter {
             (serialized_value.len() as f64 / 1000f64)
         );
         let ts = millis_to_epoch(SystemTime::now());
-        let _f = self.producer.send_copy(
-            self.topic_name.as_str(),
-            None,
-            Some(&serialized_value),
-            Some(&serialized_key),
-            Some(ts),
+        let _f = self.producer.send(
+            FutureRecord::to(&self.topic_name)
+                .payload(&serialized_value)
+                .key(&serialized_key)
+                .timestamp(ts),
             1000,
         );
         // _f.wait();  // Uncomment to make production synchronous
@@ -113,12 +116,10 @@ impl ReplicaWriter {
     /// Writes a tombstone for the specified message key.
     fn write_tombstone(&self, message_key: &[u8]) -> Result<()> {
         let ts = millis_to_epoch(SystemTime::now());
-        let _f = self.producer.send_copy::<[u8], [u8]>(
-            self.topic_name.as_str(),
-            None,
-            None,
-            Some(&message_key),
-            Some(ts),
+        let _f = self.producer.send::<[u8], [u8]>(
+            FutureRecord::to(&self.topic_name)
+                .key(message_key)
+                .timestamp(ts),
             1000,
         );
         Ok(())
@@ -145,7 +146,7 @@ pub trait UpdateReceiver: Send + 'static {
     fn receive_update(&self, name: &str, update: ReplicaCacheUpdate) -> Result<()>;
 }
 
-type ReplicaConsumer = StreamConsumer<EmptyConsumerContext>;
+type ReplicaConsumer = StreamConsumer<DefaultConsumerContext>;
 
 pub struct ReplicaReader {
     consumer: ReplicaConsumer,
@@ -164,14 +165,11 @@ impl ReplicaReader {
             .set("bootstrap.servers", brokers)
             .set("session.timeout.ms", "6000")
             .set("enable.auto.commit", "false")
+            .set("enable.partition.eof", "true")
             .set("queued.min.messages", "10000") // Reduce memory usage
             //.set("fetch.message.max.bytes", "102400")
             .set("api.version.request", "true")
-            .set_default_topic_config(
-                TopicConfig::new()
-                    .set("auto.offset.reset", "smallest")
-                    .finalize(),
-            )
+            .set("auto.offset.reset", "smallest")
             .create()
             .chain_err(|| "Consumer creation failed")?;
 
@@ -231,7 +229,7 @@ impl ReplicaReader {
         let topic_name = &self.topic_name;
         let metadata = self
             .consumer
-            .fetch_metadata(Some(topic_name), 30000)
+            .fetch_metadata(Some(topic_name), Duration::from_secs(30))
             .chain_err(|| "Failed to fetch metadata")?;
 
         if metadata.topics().is_empty() {
@@ -548,13 +546,34 @@ pub type OffsetsCache = ReplicatedMap<(ClusterId, String, TopicName), Vec<i64>>;
 /// Offsets for the internal consumers of the __consumer_offsets topic
 pub type InternalConsumerOffsetCache = ReplicatedMap<ClusterId, Vec<i64>>;
 
+/// Controller and Kafka cluster id of each cluster
+pub type ClusterInfoCache = ReplicatedMap<ClusterId, ClusterInfo>;
+
+/// Cluster information read from ZooKeeper
+pub type ZkStateCache = ReplicatedMap<ClusterId, ZkClusterState>;
+
+/// ACLs of each cluster
+pub type AclCache = ReplicatedMap<ClusterId, Vec<Acl>>;
+
+/// User and client id quotas of each cluster
+pub type QuotaCache = ReplicatedMap<ClusterId, Vec<Quota>>;
+
+/// Disk usage history of each broker
+pub type DiskUsageCache = ReplicatedMap<(ClusterId, BrokerId), Vec<DiskUsageSample>>;
+
 pub struct Cache {
     pub metrics: MetricsCache,
     pub offsets: OffsetsCache,
+    pub zk_offsets: OffsetsCache,
     pub brokers: BrokerCache,
     pub topics: TopicCache,
     pub groups: GroupCache,
     pub internal_offsets: InternalConsumerOffsetCache,
+    pub disk_usage: DiskUsageCache,
+    pub zk_state: ZkStateCache,
+    pub acls: AclCache,
+    pub quotas: QuotaCache,
+    pub cluster_info: ClusterInfoCache,
 }
 
 impl Cache {
@@ -563,10 +582,16 @@ impl Cache {
         Cache {
             metrics: ReplicatedMap::new("metrics", replica_writer_arc.clone()),
             offsets: ReplicatedMap::new("offsets", replica_writer_arc.clone()),
+            zk_offsets: ReplicatedMap::new("zk_offsets", replica_writer_arc.clone()),
             brokers: ReplicatedMap::new("brokers", replica_writer_arc.clone()),
             topics: ReplicatedMap::new("topics", replica_writer_arc.clone()),
             groups: ReplicatedMap::new("groups", replica_writer_arc.clone()),
-            internal_offsets: ReplicatedMap::new("internal_offsets", replica_writer_arc),
+            internal_offsets: ReplicatedMap::new("internal_offsets", replica_writer_arc.clone()),
+            disk_usage: ReplicatedMap::new("disk_usage", replica_writer_arc.clone()),
+            zk_state: ReplicatedMap::new("zk_state", replica_writer_arc.clone()),
+            acls: ReplicatedMap::new("acls", replica_writer_arc.clone()),
+            quotas: ReplicatedMap::new("quotas", replica_writer_arc.clone()),
+            cluster_info: ReplicatedMap::new("cluster_info", replica_writer_arc),
         }
     }
 
@@ -574,10 +599,16 @@ impl Cache {
         Cache {
             metrics: self.metrics.alias(),
             offsets: self.offsets.alias(),
+            zk_offsets: self.zk_offsets.alias(),
             brokers: self.brokers.alias(),
             topics: self.topics.alias(),
             groups: self.groups.alias(),
             internal_offsets: self.internal_offsets.alias(),
+            disk_usage: self.disk_usage.alias(),
+            zk_state: self.zk_state.alias(),
+            acls: self.acls.alias(),
+            quotas: self.quotas.alias(),
+            cluster_info: self.cluster_info.alias(),
         }
     }
 }
@@ -587,10 +618,16 @@ impl UpdateReceiver for Cache {
         match cache_name {
             "metrics" => self.metrics.receive_update(update),
             "offsets" => self.offsets.receive_update(update),
+            "zk_offsets" => self.zk_offsets.receive_update(update),
             "brokers" => self.brokers.receive_update(update),
             "topics" => self.topics.receive_update(update),
             "groups" => self.groups.receive_update(update),
             "internal_offsets" => self.internal_offsets.receive_update(update),
+            "disk_usage" => self.disk_usage.receive_update(update),
+            "zk_state" => self.zk_state.receive_update(update),
+            "acls" => self.acls.receive_update(update),
+            "quotas" => self.quotas.receive_update(update),
+            "cluster_info" => self.cluster_info.receive_update(update),
             _ => bail!("Unknown cache name: {}", cache_name),
         }
     }
@@ -1,9 +1,11 @@
+use regex::Regex;
 use serde_yaml;
 
 use error::*;
 use metadata::ClusterId;
 
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
 
@@ -11,23 +13,351 @@ fn default_true() -> bool {
     true
 }
 
+/// Where the consumer group offsets of a cluster are read from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OffsetsSource {
+    /// Consume the `__consumer_offsets` topic.
+    ConsumerOffsets,
+    /// Periodically fetch the committed offsets of each known group (OffsetFetch request).
+    OffsetFetch,
+}
+
+impl Default for OffsetsSource {
+    fn default() -> OffsetsSource {
+        OffsetsSource::ConsumerOffsets
+    }
+}
+
+/// How the broker metrics of a cluster are collected.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum MetricsSource {
+    /// Read the JMX metrics through Jolokia, on `jolokia_port`.
+    Jolokia,
+    /// Scrape the Prometheus JMX exporter, on `prometheus_port`.
+    Prometheus,
+}
+
+impl Default for MetricsSource {
+    fn default() -> MetricsSource {
+        MetricsSource::Jolokia
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JolokiaScheme {
+    Http,
+    Https,
+}
+
+impl JolokiaScheme {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JolokiaScheme::Http => "http",
+            JolokiaScheme::Https => "https",
+        }
+    }
+}
+
+/// How to connect to the Jolokia agents of a cluster.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct JolokiaConfig {
+    pub scheme: JolokiaScheme,
+    /// Username and password for basic authentication.
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// PEM file of an additional CA to trust, for HTTPS.
+    pub ca_file: Option<String>,
+    /// Path the agent is mounted on.
+    pub path: String,
+    pub connect_timeout_ms: u64,
+    pub read_timeout_ms: u64,
+}
+
+// The configuration is logged at startup: don't show the password.
+impl fmt::Debug for JolokiaConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("JolokiaConfig")
+            .field("scheme", &self.scheme)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<hidden>"))
+            .field("ca_file", &self.ca_file)
+            .field("path", &self.path)
+            .field("connect_timeout_ms", &self.connect_timeout_ms)
+            .field("read_timeout_ms", &self.read_timeout_ms)
+            .finish()
+    }
+}
+
+impl Default for JolokiaConfig {
+    fn default() -> JolokiaConfig {
+        JolokiaConfig {
+            scheme: JolokiaScheme::Http,
+            username: None,
+            password: None,
+            ca_file: None,
+            path: "/jolokia".to_owned(),
+            connect_timeout_ms: 5000,
+            read_timeout_ms: 10000,
+        }
+    }
+}
+
+/// How the values of the MBeans matched by a `JolokiaMetricConfig` are combined, for each topic.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MetricAggregation {
+    Sum,
+    Max,
+    Min,
+    Avg,
+}
+
+impl Default for MetricAggregation {
+    fn default() -> MetricAggregation {
+        MetricAggregation::Sum
+    }
+}
+
+/// A metric read from Jolokia. The MBean can be a pattern: the matched MBeans are grouped by
+/// their `topic` property, or into the broker total if they don't have one. The
+/// `bytes_in`, `messages_in` and `partition_size` metrics are used by the web UI, all the
+/// others are stored and exported as they are. `bytes_in` and `messages_in` are meters:
+/// their count and rates are always read, and they don't need an attribute.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JolokiaMetricConfig {
+    pub name: String,
+    pub mbean: String,
+    #[serde(default)]
+    pub attribute: Option<String>,
+    #[serde(default)]
+    pub aggregation: MetricAggregation,
+}
+
+fn jolokia_metric(
+    name: &str,
+    mbean: &str,
+    attribute: Option<&str>,
+    aggregation: MetricAggregation,
+) -> JolokiaMetricConfig {
+    JolokiaMetricConfig {
+        name: name.to_owned(),
+        mbean: mbean.to_owned(),
+        attribute: attribute.map(str::to_owned),
+        aggregation,
+    }
+}
+
+fn default_disk_usage_threshold() -> f64 {
+    85f64
+}
+
+fn default_replica_size_divergence() -> f64 {
+    10f64
+}
+
+fn default_search_max_bytes() -> u64 {
+    1024 * 1024 * 1024
+}
+
+fn default_search_max_jobs() -> usize {
+    2
+}
+
+fn default_stream_max_rate() -> usize {
+    100
+}
+
+fn default_stream_max_connections() -> usize {
+    32
+}
+
+fn default_zk_browser_denylist() -> Vec<String> {
+    // SCRAM credentials and dynamic broker configs, which might contain passwords
+    vec!["/config/users".to_owned(), "/config/brokers".to_owned()]
+}
+
+fn default_jolokia_metrics() -> Vec<JolokiaMetricConfig> {
+    use self::MetricAggregation::*;
+    vec![
+        jolokia_metric(
+            "bytes_in",
+            "kafka.server:type=BrokerTopicMetrics,name=BytesInPerSec,*",
+            None,
+            Sum,
+        ),
+        jolokia_metric(
+            "messages_in",
+            "kafka.server:type=BrokerTopicMetrics,name=MessagesInPerSec,*",
+            None,
+            Sum,
+        ),
+        jolokia_metric(
+            "partition_size",
+            "kafka.log:type=Log,name=Size,*",
+            Some("Value"),
+            Sum,
+        ),
+        jolokia_metric(
+            "bytes_out",
+            "kafka.server:type=BrokerTopicMetrics,name=BytesOutPerSec,*",
+            Some("FifteenMinuteRate"),
+            Sum,
+        ),
+        jolokia_metric(
+            "failed_produce_requests",
+            "kafka.server:type=BrokerTopicMetrics,name=FailedProduceRequestsPerSec,*",
+            Some("FifteenMinuteRate"),
+            Sum,
+        ),
+        jolokia_metric(
+            "failed_fetch_requests",
+            "kafka.server:type=BrokerTopicMetrics,name=FailedFetchRequestsPerSec,*",
+            Some("FifteenMinuteRate"),
+            Sum,
+        ),
+        jolokia_metric(
+            "isr_shrinks",
+            "kafka.server:type=ReplicaManager,name=IsrShrinksPerSec",
+            Some("FifteenMinuteRate"),
+            Sum,
+        ),
+        jolokia_metric(
+            "under_replicated_partitions",
+            "kafka.server:type=ReplicaManager,name=UnderReplicatedPartitions",
+            Some("Value"),
+            Sum,
+        ),
+        jolokia_metric(
+            "produce_time_ms_p99",
+            "kafka.network:type=RequestMetrics,name=TotalTimeMs,request=Produce",
+            Some("99thPercentile"),
+            Max,
+        ),
+        jolokia_metric(
+            "fetch_consumer_time_ms_p99",
+            "kafka.network:type=RequestMetrics,name=TotalTimeMs,request=FetchConsumer",
+            Some("99thPercentile"),
+            Max,
+        ),
+    ]
+}
+
+/// How the payloads of a topic are shown by the tailer and the message browser.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadDecoder {
+    /// Avro for the Confluent wire format if a schema registry is configured, then JSON, then
+    /// UTF-8, then a hex dump.
+    Auto,
+    Text,
+    Json,
+    Hex,
+    Base64,
+    Msgpack,
+    /// Confluent wire format, with the schemas read from `schema_registry_url`.
+    Avro,
+    /// Messages of the `message_type` of the rule, from `protobuf_descriptor_set`.
+    Protobuf,
+}
+
+impl Default for PayloadDecoder {
+    fn default() -> PayloadDecoder {
+        PayloadDecoder::Auto
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PayloadDecoderRule {
+    /// Regex matching the whole topic name.
+    pub topic: String,
+    pub decoder: PayloadDecoder,
+    /// Fully qualified name of the protobuf message, such as `com.example.Order`.
+    pub message_type: Option<String>,
+    /// The compiled `topic` regex, always available after load.
+    #[serde(skip)]
+    topic_regex: Option<Regex>,
+}
+
+impl PayloadDecoderRule {
+    /// Compiles the topic regex, which is required before matching topics.
+    pub fn compile(&mut self) -> Result<()> {
+        let regex = Regex::new(&format!("^(?:{})$", self.topic))
+            .chain_err(|| format!("Invalid payload decoder topic regex: {}", self.topic))?;
+        self.topic_regex = Some(regex);
+        Ok(())
+    }
+
+    pub fn matches(&self, topic: &str) -> bool {
+        self.topic_regex
+            .as_ref()
+            .map(|regex| regex.is_match(topic))
+            .unwrap_or(false)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ClusterConfig {
     pub cluster_id: Option<ClusterId>, // This will always be available after load
     pub broker_list: Vec<String>,
-    pub zookeeper: String,
+    /// Not set for KRaft clusters: the features based on ZooKeeper are then disabled.
+    pub zookeeper: Option<String>,
     pub jolokia_port: Option<i32>,
+    #[serde(default)]
+    pub jolokia: JolokiaConfig,
+    pub prometheus_port: Option<i32>,
+    #[serde(default)]
+    pub metrics_source: MetricsSource,
     pub graph_url: Option<String>,
     #[serde(default = "default_true")]
     pub enable_tailing: bool,
     #[serde(default = "default_true")]
     pub show_zk_reassignments: bool,
+    #[serde(default)]
+    pub offsets_source: OffsetsSource,
+    #[serde(default)]
+    pub zk_consumer_offsets: bool,
+    #[serde(default)]
+    pub enable_admin: bool,
+    /// Disk space available to each broker, used when the log dir MBeans are not available.
+    pub disk_capacity_bytes: Option<u64>,
+    /// Disk usage percentage the capacity forecast refers to.
+    #[serde(default = "default_disk_usage_threshold")]
+    pub disk_usage_threshold: f64,
+    /// Follower replicas differing in size from the leader by more than this percentage are
+    /// flagged.
+    #[serde(default = "default_replica_size_divergence")]
+    pub replica_size_divergence: f64,
+    /// Maximum number of bytes read by a message search.
+    #[serde(default = "default_search_max_bytes")]
+    pub search_max_bytes: u64,
+    /// Maximum number of message searches running at the same time.
+    #[serde(default = "default_search_max_jobs")]
+    pub search_max_jobs: usize,
+    /// Used to decode Avro payloads in the Confluent wire format.
+    pub schema_registry_url: Option<String>,
+    /// File generated by `protoc --include_imports --descriptor_set_out`.
+    pub protobuf_descriptor_set: Option<String>,
+    /// The decoder of each topic is the one of the first matching rule, `auto` if none matches.
+    #[serde(default)]
+    pub payload_decoders: Vec<PayloadDecoderRule>,
 }
 
 impl ClusterConfig {
     pub fn bootstrap_servers(&self) -> String {
         self.broker_list.join(",")
     }
+
+    /// The port metrics should be read from, if metrics are enabled for the cluster.
+    pub fn metrics_port(&self) -> Option<i32> {
+        match self.metrics_source {
+            MetricsSource::Jolokia => self.jolokia_port,
+            MetricsSource::Prometheus => self.prometheus_port,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -44,6 +374,20 @@ pub struct Config {
     pub metrics_refresh: u64,
     pub offsets_store_duration: u64,
     pub consumer_offsets_group_id: String,
+    #[serde(default = "default_jolokia_metrics")]
+    pub jolokia_metrics: Vec<JolokiaMetricConfig>,
+    /// ZooKeeper paths hidden by the ZooKeeper browser, with all their descendants.
+    #[serde(default = "default_zk_browser_denylist")]
+    pub zk_browser_denylist: Vec<String>,
+    /// Port of the server streaming the messages to the topic tailers. Streaming is disabled if
+    /// not set, and the tailers poll the API instead.
+    pub stream_port: Option<u16>,
+    /// Maximum number of messages per second sent to each streaming tailer.
+    #[serde(default = "default_stream_max_rate")]
+    pub stream_max_rate: usize,
+    /// Maximum number of streaming tailers open at the same time.
+    #[serde(default = "default_stream_max_connections")]
+    pub stream_max_connections: usize,
     pub clusters: HashMap<ClusterId, ClusterConfig>,
     pub caching: CachingConfig,
 }
@@ -52,10 +396,19 @@ impl Config {
     pub fn cluster(&self, cluster_id: &ClusterId) -> Option<&ClusterConfig> {
         self.clusters.get(cluster_id)
     }
+
+    /// The clusters with a ZooKeeper connection configured.
+    pub fn zookeeper_clusters(&self) -> Vec<ClusterId> {
+        self.clusters
+            .iter()
+            .filter(|&(_, cluster_config)| cluster_config.zookeeper.is_some())
+            .map(|(cluster_id, _)| cluster_id.clone())
+            .collect()
+    }
 }
 
 pub fn read_config(path: &str) -> Result<Config> {
-    let mut f = File::open(path).chain_err(|| "Unable to open configuration file")?;;
+    let mut f = File::open(path).chain_err(|| "Unable to open configuration file")?;
     let mut s = String::new();
     f.read_to_string(&mut s)
         .chain_err(|| "Unable to read configuration file")?;
@@ -65,6 +418,10 @@ pub fn read_config(path: &str) -> Result<Config> {
 
     for (cluster_id, cluster) in &mut config.clusters {
         cluster.cluster_id = Some(cluster_id.clone());
+        for rule in &mut cluster.payload_decoders {
+            rule.compile()
+                .chain_err(|| format!("Invalid configuration of cluster {}", cluster_id))?;
+        }
     }
 
     info!("Configuration: {:?}", config);
@@ -0,0 +1,476 @@
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt};
+use hyper::status::StatusCode;
+use serde_json::{self, Map, Value};
+
+use super::{
+    bytes_value, check_fully_read, float_value, read_slice, read_varint, unexpected_end, zigzag,
+    MAX_DEPTH,
+};
+use error::*;
+use http_client::https_client;
+
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+const REGISTRY_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const REGISTRY_READ_TIMEOUT: Duration = Duration::from_secs(10);
+/// A schema that couldn't be fetched isn't requested again before this interval.
+const REGISTRY_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+/// Maximum number of items of an array whose items can be encoded with no bytes.
+const MAX_EMPTY_ITEMS: i64 = 10_000;
+
+type SchemaKey = (String, u32);
+
+lazy_static! {
+    /// Schemas never change once registered, so they are cached forever.
+    static ref SCHEMAS: RwLock<HashMap<SchemaKey, Arc<AvroSchema>>> =
+        RwLock::new(HashMap::new());
+    /// Failed lookups, with the time of the failure and the error.
+    static ref FAILED_SCHEMAS: RwLock<HashMap<SchemaKey, (Instant, String)>> =
+        RwLock::new(HashMap::new());
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Schema {
+    Null,
+    Boolean,
+    Int,
+    Long,
+    Float,
+    Double,
+    Bytes,
+    String,
+    Record(Vec<(String, Schema)>),
+    Enum(Vec<String>),
+    Array(Box<Schema>),
+    Map(Box<Schema>),
+    Union(Vec<Schema>),
+    Fixed(usize),
+    /// Reference to a named type, by full name.
+    Named(String),
+}
+
+/// A parsed Avro schema, with the named types it defines.
+#[derive(Debug)]
+struct AvroSchema {
+    root: Schema,
+    names: HashMap<String, Schema>,
+}
+
+fn full_name(name: &str, namespace: Option<&str>) -> String {
+    match namespace {
+        Some(namespace) if !name.contains('.') && !namespace.is_empty() => {
+            format!("{}.{}", namespace, name)
+        }
+        _ => name.to_owned(),
+    }
+}
+
+impl AvroSchema {
+    fn parse(schema: &str) -> Result<AvroSchema> {
+        let json: Value = serde_json::from_str(schema).chain_err(|| "Invalid schema JSON")?;
+        let mut names = HashMap::new();
+        let root = parse_schema(&json, None, &mut names)?;
+        Ok(AvroSchema { root, names })
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<Value> {
+        let mut reader = Cursor::new(payload);
+        let value = self.read_value(&self.root, &mut reader, 0)?;
+        check_fully_read(&reader)?;
+        Ok(value)
+    }
+
+    fn read_value(
+        &self,
+        schema: &Schema,
+        reader: &mut Cursor<&[u8]>,
+        depth: usize,
+    ) -> Result<Value> {
+        if depth > MAX_DEPTH {
+            bail!("Too many nested values");
+        }
+        let value = match *schema {
+            Schema::Null => Value::Null,
+            Schema::Boolean => Value::Bool(unexpected_end(reader.read_u8())? != 0),
+            Schema::Int | Schema::Long => Value::from(read_long(reader)?),
+            Schema::Float => float_value(f64::from(unexpected_end(
+                reader.read_f32::<LittleEndian>(),
+            )?)),
+            Schema::Double => float_value(unexpected_end(reader.read_f64::<LittleEndian>())?),
+            Schema::Bytes => {
+                let len = read_len(reader)?;
+                bytes_value(read_slice(reader, len)?)
+            }
+            Schema::String => {
+                let len = read_len(reader)?;
+                Value::String(String::from_utf8_lossy(read_slice(reader, len)?).into_owned())
+            }
+            Schema::Record(ref fields) => {
+                let mut record = Map::new();
+                for &(ref name, ref field_schema) in fields {
+                    record.insert(
+                        name.clone(),
+                        self.read_value(field_schema, reader, depth + 1)?,
+                    );
+                }
+                Value::Object(record)
+            }
+            Schema::Enum(ref symbols) => {
+                let index = read_long(reader)?;
+                match symbols.get(index as usize) {
+                    Some(symbol) => Value::String(symbol.clone()),
+                    None => bail!("Invalid enum index {}", index),
+                }
+            }
+            Schema::Array(ref items) => {
+                let mut array = Vec::new();
+                let can_be_empty = self.can_be_empty(items, 0);
+                loop {
+                    // Every item takes at least a byte, unless it can be empty: the count of
+                    // those has to be capped explicitly.
+                    let max_count = if can_be_empty {
+                        MAX_EMPTY_ITEMS - array.len() as i64
+                    } else {
+                        remaining(reader)
+                    };
+                    let count = match read_block_count(reader, max_count)? {
+                        Some(count) => count,
+                        None => break,
+                    };
+                    for _ in 0..count {
+                        array.push(self.read_value(items, reader, depth + 1)?);
+                    }
+                }
+                Value::Array(array)
+            }
+            Schema::Map(ref values) => {
+                let mut map = Map::new();
+                // Keys are prefixed by their length, so entries take at least a byte.
+                while let Some(count) = read_block_count(reader, remaining(reader))? {
+                    for _ in 0..count {
+                        let len = read_len(reader)?;
+                        let key = String::from_utf8_lossy(read_slice(reader, len)?).into_owned();
+                        map.insert(key, self.read_value(values, reader, depth + 1)?);
+                    }
+                }
+                Value::Object(map)
+            }
+            Schema::Union(ref branches) => {
+                let index = read_long(reader)?;
+                match branches.get(index as usize) {
+                    Some(branch) => self.read_value(branch, reader, depth + 1)?,
+                    None => bail!("Invalid union index {}", index),
+                }
+            }
+            Schema::Fixed(size) => bytes_value(read_slice(reader, size)?),
+            Schema::Named(ref name) => match self.names.get(name) {
+                Some(named) => self.read_value(named, reader, depth + 1)?,
+                None => bail!("Unknown type {}", name),
+            },
+        };
+        Ok(value)
+    }
+
+    /// Whether values of the schema can be encoded with no bytes at all, like nulls.
+    fn can_be_empty(&self, schema: &Schema, depth: usize) -> bool {
+        if depth > MAX_DEPTH {
+            return true;
+        }
+        match *schema {
+            Schema::Null => true,
+            Schema::Fixed(size) => size == 0,
+            Schema::Record(ref fields) => fields
+                .iter()
+                .all(|&(_, ref field_schema)| self.can_be_empty(field_schema, depth + 1)),
+            Schema::Named(ref name) => self
+                .names
+                .get(name)
+                .map_or(false, |named| self.can_be_empty(named, depth + 1)),
+            _ => false,
+        }
+    }
+}
+
+fn parse_schema(
+    json: &Value,
+    namespace: Option<&str>,
+    names: &mut HashMap<String, Schema>,
+) -> Result<Schema> {
+    let object = match *json {
+        Value::String(ref name) => return Ok(primitive_schema(name, namespace)),
+        Value::Array(ref branches) => {
+            let branches = branches
+                .iter()
+                .map(|branch| parse_schema(branch, namespace, names))
+                .collect::<Result<Vec<_>>>()?;
+            return Ok(Schema::Union(branches));
+        }
+        Value::Object(ref object) => object,
+        _ => bail!("Invalid schema: {}", json),
+    };
+    let schema_type = match object.get("type") {
+        Some(&Value::String(ref schema_type)) => schema_type.as_str(),
+        // The type itself is a schema, as in {"type": {"type": "array", ...}}
+        Some(schema_type) => return parse_schema(schema_type, namespace, names),
+        None => bail!("Missing type in schema: {}", json),
+    };
+    let name = object.get("name").and_then(Value::as_str);
+    let name = match name {
+        Some(name) => Some(full_name(
+            name,
+            object
+                .get("namespace")
+                .and_then(Value::as_str)
+                .or(namespace),
+        )),
+        None => None,
+    };
+    // Names defined inside a named type are relative to its namespace.
+    let inner_namespace = match name {
+        Some(ref name) if name.contains('.') => Some(name[..name.rfind('.').unwrap()].to_owned()),
+        _ => namespace.map(str::to_owned),
+    };
+    let inner_namespace = inner_namespace.as_ref().map(String::as_str);
+
+    let schema = match schema_type {
+        "record" | "error" => {
+            let fields = match object.get("fields").and_then(Value::as_array) {
+                Some(fields) => fields,
+                None => bail!("Missing fields in record schema: {}", json),
+            };
+            let mut record_fields = Vec::new();
+            for field in fields {
+                let field_name = match field.get("name").and_then(Value::as_str) {
+                    Some(field_name) => field_name.to_owned(),
+                    None => bail!("Missing field name in record schema: {}", json),
+                };
+                let field_type = match field.get("type") {
+                    Some(field_type) => parse_schema(field_type, inner_namespace, names)?,
+                    None => bail!("Missing type of field {}", field_name),
+                };
+                record_fields.push((field_name, field_type));
+            }
+            Schema::Record(record_fields)
+        }
+        "enum" => match object.get("symbols").and_then(Value::as_array) {
+            Some(symbols) => Schema::Enum(
+                symbols
+                    .iter()
+                    .map(|symbol| symbol.as_str().unwrap_or("").to_owned())
+                    .collect(),
+            ),
+            None => bail!("Missing symbols in enum schema: {}", json),
+        },
+        "array" => match object.get("items") {
+            Some(items) => Schema::Array(Box::new(parse_schema(items, namespace, names)?)),
+            None => bail!("Missing items in array schema: {}", json),
+        },
+        "map" => match object.get("values") {
+            Some(values) => Schema::Map(Box::new(parse_schema(values, namespace, names)?)),
+            None => bail!("Missing values in map schema: {}", json),
+        },
+        "fixed" => match object.get("size").and_then(Value::as_u64) {
+            Some(size) => Schema::Fixed(size as usize),
+            None => bail!("Missing size in fixed schema: {}", json),
+        },
+        // Primitive types with attributes, such as logical types, are decoded as the
+        // underlying type.
+        primitive => primitive_schema(primitive, namespace),
+    };
+
+    match name {
+        Some(name) => {
+            names.insert(name.clone(), schema);
+            Ok(Schema::Named(name))
+        }
+        None => Ok(schema),
+    }
+}
+
+fn primitive_schema(name: &str, namespace: Option<&str>) -> Schema {
+    match name {
+        "null" => Schema::Null,
+        "boolean" => Schema::Boolean,
+        "int" => Schema::Int,
+        "long" => Schema::Long,
+        "float" => Schema::Float,
+        "double" => Schema::Double,
+        "bytes" => Schema::Bytes,
+        "string" => Schema::String,
+        name => Schema::Named(full_name(name, namespace)),
+    }
+}
+
+fn read_long(reader: &mut Cursor<&[u8]>) -> Result<i64> {
+    Ok(zigzag(read_varint(reader)?))
+}
+
+fn read_len(reader: &mut Cursor<&[u8]>) -> Result<usize> {
+    let len = read_long(reader)?;
+    if len < 0 {
+        bail!("Invalid length {}", len);
+    }
+    Ok(len as usize)
+}
+
+/// Number of bytes left to read.
+fn remaining(reader: &Cursor<&[u8]>) -> i64 {
+    reader.get_ref().len() as i64 - reader.position() as i64
+}
+
+/// Reads the item count of the next block of an array or map, none for the last block. Counts
+/// above `max_count` are rejected.
+fn read_block_count(reader: &mut Cursor<&[u8]>, max_count: i64) -> Result<Option<i64>> {
+    let count = match read_long(reader)? {
+        0 => return Ok(None),
+        count if count < 0 => {
+            // Negative counts are followed by the size of the block in bytes.
+            read_long(reader)?;
+            match count.checked_neg() {
+                Some(count) => count,
+                None => bail!("Invalid block count {}", count),
+            }
+        }
+        count => count,
+    };
+    if count > max_count {
+        bail!("Block count {} exceeds the maximum of {}", count, max_count);
+    }
+    Ok(Some(count))
+}
+
+fn fetch_schema(registry_url: &str, id: u32) -> Result<AvroSchema> {
+    let url = format!("{}/schemas/ids/{}", registry_url.trim_end_matches('/'), id);
+    let client = https_client(REGISTRY_CONNECT_TIMEOUT, REGISTRY_READ_TIMEOUT, None)?;
+    let mut response = client
+        .get(&url)
+        .send()
+        .chain_err(|| format!("Failed to connect to {}", url))?;
+    if response.status != StatusCode::Ok {
+        bail!("Unexpected HTTP status from {}: {}", url, response.status);
+    }
+    let mut body = String::new();
+    response
+        .read_to_string(&mut body)
+        .chain_err(|| "Could not read response to string")?;
+    let body: Value = serde_json::from_str(&body).chain_err(|| "Failed to parse JSON")?;
+    match body.get("schemaType").and_then(Value::as_str) {
+        None | Some("AVRO") => {}
+        Some(schema_type) => bail!("Schema {} is a {} schema", id, schema_type),
+    }
+    match body.get("schema").and_then(Value::as_str) {
+        Some(schema) => AvroSchema::parse(schema),
+        None => bail!("Missing schema in the response of {}", url),
+    }
+}
+
+fn schema(registry_url: &str, id: u32) -> Result<Arc<AvroSchema>> {
+    let key = (registry_url.to_owned(), id);
+    if let Some(schema) = SCHEMAS.read().unwrap().get(&key) {
+        return Ok(schema.clone());
+    }
+    if let Some(&(failed_at, ref error)) = FAILED_SCHEMAS.read().unwrap().get(&key) {
+        if failed_at.elapsed() < REGISTRY_RETRY_INTERVAL {
+            bail!("Schema {} is not available: {}", id, error);
+        }
+    }
+    match fetch_schema(registry_url, id) {
+        Ok(schema) => {
+            let schema = Arc::new(schema);
+            FAILED_SCHEMAS.write().unwrap().remove(&key);
+            SCHEMAS.write().unwrap().insert(key, schema.clone());
+            Ok(schema)
+        }
+        Err(e) => {
+            let e = Error::with_chain(
+                e,
+                format!("Failed to fetch schema {} from the registry", id),
+            );
+            FAILED_SCHEMAS
+                .write()
+                .unwrap()
+                .insert(key, (Instant::now(), e.to_string()));
+            Err(e)
+        }
+    }
+}
+
+/// Whether the payload starts with the magic byte and schema id of the Confluent wire format.
+pub fn is_wire_format(payload: &[u8]) -> bool {
+    payload.len() >= 5 && payload[0] == 0
+}
+
+/// Decodes a payload in the Confluent wire format, with the schema read from the registry.
+pub fn decode(registry_url: &str, payload: &[u8]) -> Result<Value> {
+    if !is_wire_format(payload) {
+        bail!("The payload is not in the Confluent wire format");
+    }
+    let id = BigEndian::read_u32(&payload[1..5]);
+    schema(registry_url, id)?.decode(&payload[5..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_avro() {
+        let schema = AvroSchema::parse(
+            r#"{
+                "type": "record", "name": "Order", "namespace": "com.example",
+                "fields": [
+                    {"name": "id", "type": "long"},
+                    {"name": "status", "type": {"type": "enum", "name": "Status", "symbols": ["NEW", "PAID"]}},
+                    {"name": "items", "type": {"type": "array", "items": "string"}},
+                    {"name": "note", "type": ["null", "string"]},
+                    {"name": "previous", "type": ["null", "Order"]}
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(schema.root, Schema::Named("com.example.Order".to_owned()));
+
+        // id=-3, status=PAID, items=["ab"], note=null, previous={id=1, NEW, [], "x", null}
+        let payload = [
+            0x05, 0x02, 0x02, 0x04, b'a', b'b', 0x00, 0x00, 0x02, 0x02, 0x00, 0x00, 0x02, 0x02,
+            b'x', 0x00,
+        ];
+        assert_eq!(
+            schema.decode(&payload).unwrap(),
+            json!({
+                "id": -3, "status": "PAID", "items": ["ab"], "note": null,
+                "previous": {"id": 1, "status": "NEW", "items": [], "note": "x", "previous": null}
+            })
+        );
+        assert!(schema.decode(&payload[..5]).is_err());
+    }
+
+    #[test]
+    fn test_decode_avro_block_counts() {
+        let strings = AvroSchema::parse(r#"{"type": "array", "items": "string"}"#).unwrap();
+        // A block of i64::MAX strings, or of -i64::MIN with a byte size
+        assert!(strings
+            .decode(&[0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01])
+            .is_err());
+        assert!(strings
+            .decode(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00])
+            .is_err());
+        // Two strings, with a negative count and a byte size
+        assert_eq!(
+            strings
+                .decode(&[0x03, 0x04, 0x00, 0x02, b'a', 0x00])
+                .unwrap(),
+            json!(["", "a"])
+        );
+
+        let nulls = AvroSchema::parse(r#"{"type": "array", "items": "null"}"#).unwrap();
+        assert_eq!(
+            nulls.decode(&[0x06, 0x00]).unwrap(),
+            json!([null, null, null])
+        );
+        assert!(nulls.decode(&[0xa2, 0x9c, 0x01, 0x00]).is_err());
+    }
+}
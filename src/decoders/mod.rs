@@ -0,0 +1,296 @@
+//! Decoders turning message payloads into text for the tailer and the message browser.
+
+mod avro;
+mod msgpack;
+mod protobuf;
+
+use base64;
+use byteorder::ReadBytesExt;
+use serde_json::{self, Number, Value};
+
+use config::{ClusterConfig, PayloadDecoder};
+use error::*;
+
+use std::io::{self, Cursor};
+use std::str;
+
+/// Maximum nesting of the decoded values, to protect the stack from malformed payloads.
+const MAX_DEPTH: usize = 64;
+
+/// A payload converted to text, with the name of the decoder that produced it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedPayload {
+    pub decoder: &'static str,
+    pub text: String,
+}
+
+impl DecodedPayload {
+    fn new(decoder: &'static str, text: String) -> DecodedPayload {
+        DecodedPayload { decoder, text }
+    }
+}
+
+fn decoder_name(decoder: PayloadDecoder) -> &'static str {
+    match decoder {
+        PayloadDecoder::Auto => "auto",
+        PayloadDecoder::Text => "text",
+        PayloadDecoder::Json => "json",
+        PayloadDecoder::Hex => "hex",
+        PayloadDecoder::Base64 => "base64",
+        PayloadDecoder::Msgpack => "msgpack",
+        PayloadDecoder::Avro => "avro",
+        PayloadDecoder::Protobuf => "protobuf",
+    }
+}
+
+/// The decoder of a topic, as selected by the `payload_decoders` rules of its cluster.
+pub struct TopicDecoder<'a> {
+    cluster_config: &'a ClusterConfig,
+    decoder: PayloadDecoder,
+    message_type: Option<String>,
+}
+
+impl<'a> TopicDecoder<'a> {
+    pub fn new(cluster_config: &'a ClusterConfig, topic: &str) -> TopicDecoder<'a> {
+        let rule = cluster_config
+            .payload_decoders
+            .iter()
+            .find(|rule| rule.matches(topic));
+        TopicDecoder {
+            cluster_config,
+            decoder: rule.map(|rule| rule.decoder).unwrap_or_default(),
+            message_type: rule.and_then(|rule| rule.message_type.clone()),
+        }
+    }
+
+    /// Decodes the payload. If the configured decoder fails, the error is reported together with
+    /// a hex dump of the payload.
+    pub fn decode(&self, payload: &[u8]) -> DecodedPayload {
+        if self.decoder == PayloadDecoder::Auto {
+            return self.decode_auto(payload);
+        }
+        match self.decode_as(self.decoder, payload) {
+            Ok(text) => DecodedPayload::new(decoder_name(self.decoder), text),
+            Err(e) => DecodedPayload::new(
+                "hex",
+                format!(
+                    "Failed to decode as {}: {}\n{}",
+                    decoder_name(self.decoder),
+                    e,
+                    hex_dump(payload)
+                ),
+            ),
+        }
+    }
+
+    fn decode_as(&self, decoder: PayloadDecoder, payload: &[u8]) -> Result<String> {
+        let text = match decoder {
+            PayloadDecoder::Auto | PayloadDecoder::Text => {
+                String::from_utf8_lossy(payload).into_owned()
+            }
+            PayloadDecoder::Json => {
+                let value =
+                    serde_json::from_slice::<Value>(payload).chain_err(|| "Invalid JSON")?;
+                pretty_json(&value)
+            }
+            PayloadDecoder::Hex => hex_dump(payload),
+            PayloadDecoder::Base64 => base64::encode(payload),
+            PayloadDecoder::Msgpack => pretty_json(&msgpack::decode(payload)?),
+            PayloadDecoder::Avro => match self.cluster_config.schema_registry_url {
+                Some(ref url) => pretty_json(&avro::decode(url, payload)?),
+                None => bail!("No schema registry configured"),
+            },
+            PayloadDecoder::Protobuf => {
+                let descriptor_set = match self.cluster_config.protobuf_descriptor_set {
+                    Some(ref path) => path,
+                    None => bail!("No protobuf descriptor set configured"),
+                };
+                let message_type = match self.message_type {
+                    Some(ref message_type) => message_type,
+                    None => bail!("No protobuf message type configured for the topic"),
+                };
+                pretty_json(&protobuf::decode(descriptor_set, message_type, payload)?)
+            }
+        };
+        Ok(text)
+    }
+
+    /// Avro in the Confluent wire format if a schema registry is configured, then JSON, then
+    /// UTF-8 text without control characters, and a hex dump for everything else.
+    fn decode_auto(&self, payload: &[u8]) -> DecodedPayload {
+        if avro::is_wire_format(payload) && self.cluster_config.schema_registry_url.is_some() {
+            match self.decode_as(PayloadDecoder::Avro, payload) {
+                Ok(text) => return DecodedPayload::new("avro", text),
+                Err(e) => debug!("Payload is not Avro: {}", e),
+            }
+        }
+        match str::from_utf8(payload) {
+            Ok(text) if is_printable(text) => {
+                let trimmed = text.trim_start();
+                if trimmed.starts_with('{') || trimmed.starts_with('[') {
+                    if let Ok(value) = serde_json::from_str::<Value>(text) {
+                        return DecodedPayload::new("json", pretty_json(&value));
+                    }
+                }
+                DecodedPayload::new("text", text.to_owned())
+            }
+            _ => DecodedPayload::new("hex", hex_dump(payload)),
+        }
+    }
+}
+
+fn is_printable(text: &str) -> bool {
+    text.chars()
+        .all(|c| !c.is_control() || c == '\n' || c == '\r' || c == '\t')
+}
+
+fn pretty_json(value: &Value) -> String {
+    serde_json::to_string_pretty(value).unwrap_or_else(|_| value.to_string())
+}
+
+/// Offset, hexadecimal bytes and printable characters, 16 bytes per line.
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut lines = Vec::new();
+    for (index, chunk) in bytes.chunks(16).enumerate() {
+        let hex = chunk
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let ascii = chunk
+            .iter()
+            .map(|&byte| {
+                if byte >= 0x20 && byte < 0x7f {
+                    byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect::<String>();
+        lines.push(format!("{:08x}  {:<47}  |{}|", index * 16, hex, ascii));
+    }
+    lines.join("\n")
+}
+
+// Helpers for the binary decoders.
+
+fn unexpected_end<T>(result: io::Result<T>) -> Result<T> {
+    result.chain_err(|| "Unexpected end of data")
+}
+
+fn read_varint(reader: &mut Cursor<&[u8]>) -> Result<u64> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let byte = unexpected_end(reader.read_u8())?;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+        if shift > 63 {
+            bail!("Invalid varint");
+        }
+    }
+}
+
+fn zigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+fn read_slice<'a>(reader: &mut Cursor<&'a [u8]>, len: usize) -> Result<&'a [u8]> {
+    let bytes: &'a [u8] = *reader.get_ref();
+    let start = reader.position() as usize;
+    if len > bytes.len() - start {
+        bail!("Unexpected end of data");
+    }
+    reader.set_position((start + len) as u64);
+    Ok(&bytes[start..start + len])
+}
+
+fn check_fully_read(reader: &Cursor<&[u8]>) -> Result<()> {
+    if (reader.position() as usize) < reader.get_ref().len() {
+        bail!("Unexpected data after byte {}", reader.position());
+    }
+    Ok(())
+}
+
+fn float_value(value: f64) -> Value {
+    Number::from_f64(value)
+        .map(Value::Number)
+        .unwrap_or(Value::Null)
+}
+
+/// Binary data in JSON values is shown as base64.
+fn bytes_value(bytes: &[u8]) -> Value {
+    Value::String(base64::encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::PayloadDecoderRule;
+    use serde_yaml;
+
+    fn rule(topic: &str, decoder: PayloadDecoder) -> Result<PayloadDecoderRule> {
+        let mut rule = serde_json::from_value::<PayloadDecoderRule>(
+            json!({ "topic": topic, "decoder": decoder }),
+        )
+        .unwrap();
+        rule.compile()?;
+        Ok(rule)
+    }
+
+    fn cluster_config(rules: &[(&str, PayloadDecoder)]) -> ClusterConfig {
+        let mut config: ClusterConfig =
+            serde_yaml::from_str("broker_list: [localhost:9092]").unwrap();
+        config.payload_decoders = rules
+            .iter()
+            .map(|&(topic, decoder)| rule(topic, decoder).unwrap())
+            .collect();
+        config
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        assert_eq!(
+            hex_dump(b"{\"a\":1}\x00"),
+            "00000000  7b 22 61 22 3a 31 7d 00                          |{\"a\":1}.|"
+        );
+        assert_eq!(hex_dump(&[0; 17]).lines().count(), 2);
+        assert_eq!(hex_dump(&[]), "");
+    }
+
+    #[test]
+    fn test_decoder_selection() {
+        let config = cluster_config(&[("audit\\..*", PayloadDecoder::Base64)]);
+        let audit = TopicDecoder::new(&config, "audit.logins");
+        assert_eq!(
+            audit.decode(b"hi"),
+            DecodedPayload::new("base64", "aGk=".to_owned())
+        );
+
+        let auto = TopicDecoder::new(&config, "audit");
+        assert_eq!(auto.decode(b"{\"a\":1}").text, "{\n  \"a\": 1\n}");
+        assert_eq!(auto.decode(b"{not json").decoder, "text");
+        assert_eq!(auto.decode(&[0, 0, 0, 0, 1, 2]).decoder, "hex");
+
+        assert!(rule("audit.(", PayloadDecoder::Json).is_err());
+    }
+
+    #[test]
+    fn test_decoder_failure() {
+        let config = cluster_config(&[(".*", PayloadDecoder::Avro)]);
+        let decoded = TopicDecoder::new(&config, "events").decode(b"x");
+        assert_eq!(decoded.decoder, "hex");
+        assert!(decoded.text.starts_with("Failed to decode as avro"));
+    }
+
+    #[test]
+    fn test_varint() {
+        let mut reader = Cursor::new(&[0xac, 0x02, 0x03][..]);
+        assert_eq!(read_varint(&mut reader).unwrap(), 300);
+        assert_eq!(zigzag(read_varint(&mut reader).unwrap()), -2);
+        assert!(read_varint(&mut reader).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+use byteorder::{BigEndian, ReadBytesExt};
+use serde_json::{Map, Value};
+
+use super::{bytes_value, check_fully_read, float_value, read_slice, unexpected_end, MAX_DEPTH};
+use error::*;
+
+use std::io::Cursor;
+
+/// Converts a MessagePack payload to JSON. Binary data is shown as base64, and map keys that
+/// are not strings are converted to their JSON representation.
+pub fn decode(payload: &[u8]) -> Result<Value> {
+    let mut reader = Cursor::new(payload);
+    let value = read_value(&mut reader, 0)?;
+    check_fully_read(&reader)?;
+    Ok(value)
+}
+
+fn read_value(reader: &mut Cursor<&[u8]>, depth: usize) -> Result<Value> {
+    if depth > MAX_DEPTH {
+        bail!("Too many nested values");
+    }
+    let marker = unexpected_end(reader.read_u8())?;
+    let value = match marker {
+        0x00..=0x7f => Value::from(marker),
+        0x80..=0x8f => read_map(reader, usize::from(marker & 0x0f), depth)?,
+        0x90..=0x9f => read_array(reader, usize::from(marker & 0x0f), depth)?,
+        0xa0..=0xbf => read_str(reader, usize::from(marker & 0x1f))?,
+        0xc0 => Value::Null,
+        0xc1 => bail!("Invalid MessagePack marker 0xc1"),
+        0xc2 => Value::Bool(false),
+        0xc3 => Value::Bool(true),
+        0xc4 => {
+            let len = unexpected_end(reader.read_u8())?;
+            bytes_value(read_slice(reader, usize::from(len))?)
+        }
+        0xc5 => {
+            let len = unexpected_end(reader.read_u16::<BigEndian>())?;
+            bytes_value(read_slice(reader, usize::from(len))?)
+        }
+        0xc6 => {
+            let len = unexpected_end(reader.read_u32::<BigEndian>())?;
+            bytes_value(read_slice(reader, len as usize)?)
+        }
+        0xc7 => {
+            let len = unexpected_end(reader.read_u8())?;
+            read_ext(reader, usize::from(len))?
+        }
+        0xc8 => {
+            let len = unexpected_end(reader.read_u16::<BigEndian>())?;
+            read_ext(reader, usize::from(len))?
+        }
+        0xc9 => {
+            let len = unexpected_end(reader.read_u32::<BigEndian>())?;
+            read_ext(reader, len as usize)?
+        }
+        0xca => float_value(f64::from(unexpected_end(reader.read_f32::<BigEndian>())?)),
+        0xcb => float_value(unexpected_end(reader.read_f64::<BigEndian>())?),
+        0xcc => Value::from(unexpected_end(reader.read_u8())?),
+        0xcd => Value::from(unexpected_end(reader.read_u16::<BigEndian>())?),
+        0xce => Value::from(unexpected_end(reader.read_u32::<BigEndian>())?),
+        0xcf => Value::from(unexpected_end(reader.read_u64::<BigEndian>())?),
+        0xd0 => Value::from(unexpected_end(reader.read_i8())?),
+        0xd1 => Value::from(unexpected_end(reader.read_i16::<BigEndian>())?),
+        0xd2 => Value::from(unexpected_end(reader.read_i32::<BigEndian>())?),
+        0xd3 => Value::from(unexpected_end(reader.read_i64::<BigEndian>())?),
+        0xd4 => read_ext(reader, 1)?,
+        0xd5 => read_ext(reader, 2)?,
+        0xd6 => read_ext(reader, 4)?,
+        0xd7 => read_ext(reader, 8)?,
+        0xd8 => read_ext(reader, 16)?,
+        0xd9 => {
+            let len = unexpected_end(reader.read_u8())?;
+            read_str(reader, usize::from(len))?
+        }
+        0xda => {
+            let len = unexpected_end(reader.read_u16::<BigEndian>())?;
+            read_str(reader, usize::from(len))?
+        }
+        0xdb => {
+            let len = unexpected_end(reader.read_u32::<BigEndian>())?;
+            read_str(reader, len as usize)?
+        }
+        0xdc => {
+            let len = unexpected_end(reader.read_u16::<BigEndian>())?;
+            read_array(reader, usize::from(len), depth)?
+        }
+        0xdd => {
+            let len = unexpected_end(reader.read_u32::<BigEndian>())?;
+            read_array(reader, len as usize, depth)?
+        }
+        0xde => {
+            let len = unexpected_end(reader.read_u16::<BigEndian>())?;
+            read_map(reader, usize::from(len), depth)?
+        }
+        0xdf => {
+            let len = unexpected_end(reader.read_u32::<BigEndian>())?;
+            read_map(reader, len as usize, depth)?
+        }
+        0xe0..=0xff => Value::from(marker as i8),
+    };
+    Ok(value)
+}
+
+fn read_str(reader: &mut Cursor<&[u8]>, len: usize) -> Result<Value> {
+    let bytes = read_slice(reader, len)?;
+    Ok(Value::String(String::from_utf8_lossy(bytes).into_owned()))
+}
+
+fn read_ext(reader: &mut Cursor<&[u8]>, len: usize) -> Result<Value> {
+    let ext_type = unexpected_end(reader.read_i8())?;
+    let data = read_slice(reader, len)?;
+    Ok(json!({"ext_type": ext_type, "data": bytes_value(data)}))
+}
+
+fn read_array(reader: &mut Cursor<&[u8]>, len: usize, depth: usize) -> Result<Value> {
+    // The length is not trusted for the allocation, as the payload might not be MessagePack.
+    let mut array = Vec::new();
+    for _ in 0..len {
+        array.push(read_value(reader, depth + 1)?);
+    }
+    Ok(Value::Array(array))
+}
+
+fn read_map(reader: &mut Cursor<&[u8]>, len: usize, depth: usize) -> Result<Value> {
+    let mut map = Map::new();
+    for _ in 0..len {
+        let key = match read_value(reader, depth + 1)? {
+            Value::String(key) => key,
+            key => key.to_string(),
+        };
+        map.insert(key, read_value(reader, depth + 1)?);
+    }
+    Ok(Value::Object(map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_msgpack() {
+        // {"id": 300, "tags": ["a", -1], "ok": true, 1: 1.5, "raw": bin(ff)}
+        let payload = [
+            0x85, 0xa2, b'i', b'd', 0xcd, 0x01, 0x2c, 0xa4, b't', b'a', b'g', b's', 0x92, 0xa1,
+            b'a', 0xff, 0xa2, b'o', b'k', 0xc3, 0x01, 0xcb, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0, 0xa3,
+            b'r', b'a', b'w', 0xc4, 0x01, 0xff,
+        ];
+        assert_eq!(
+            decode(&payload).unwrap(),
+            json!({"id": 300, "tags": ["a", -1], "ok": true, "1": 1.5, "raw": "/w=="})
+        );
+        assert!(decode(&payload[..10]).is_err());
+        assert!(decode(&[0xc0, 0xc0]).is_err());
+        assert!(decode(&[0xdd, 0xff, 0xff, 0xff, 0xff]).is_err());
+    }
+}
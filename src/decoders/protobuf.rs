@@ -0,0 +1,569 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use serde_json::{Map, Value};
+
+use super::{bytes_value, float_value, read_slice, read_varint, unexpected_end, zigzag, MAX_DEPTH};
+use error::*;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// A descriptor set that couldn't be loaded is only read again after this long.
+const LOAD_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    static ref DESCRIPTOR_SETS: RwLock<HashMap<String, Arc<DescriptorPool>>> =
+        RwLock::new(HashMap::new());
+    /// Failed loads, with the time of the failure and the error.
+    static ref FAILED_DESCRIPTOR_SETS: RwLock<HashMap<String, (Instant, String)>> =
+        RwLock::new(HashMap::new());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FieldType {
+    Double,
+    Float,
+    Int64,
+    Uint64,
+    Int32,
+    Fixed64,
+    Fixed32,
+    Bool,
+    String,
+    Group,
+    Message,
+    Bytes,
+    Uint32,
+    Enum,
+    Sfixed32,
+    Sfixed64,
+    Sint32,
+    Sint64,
+}
+
+impl FieldType {
+    /// From the `FieldDescriptorProto.Type` enum.
+    fn from_number(number: u64) -> Option<FieldType> {
+        let field_type = match number {
+            1 => FieldType::Double,
+            2 => FieldType::Float,
+            3 => FieldType::Int64,
+            4 => FieldType::Uint64,
+            5 => FieldType::Int32,
+            6 => FieldType::Fixed64,
+            7 => FieldType::Fixed32,
+            8 => FieldType::Bool,
+            9 => FieldType::String,
+            10 => FieldType::Group,
+            11 => FieldType::Message,
+            12 => FieldType::Bytes,
+            13 => FieldType::Uint32,
+            14 => FieldType::Enum,
+            15 => FieldType::Sfixed32,
+            16 => FieldType::Sfixed64,
+            17 => FieldType::Sint32,
+            18 => FieldType::Sint64,
+            _ => return None,
+        };
+        Some(field_type)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum WireValue<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+fn read_fields(bytes: &[u8]) -> Result<Vec<(u32, WireValue)>> {
+    let mut reader = Cursor::new(bytes);
+    let mut fields = Vec::new();
+    while (reader.position() as usize) < bytes.len() {
+        let key = read_varint(&mut reader)?;
+        let number = (key >> 3) as u32;
+        let value = match key & 0x07 {
+            0 => WireValue::Varint(read_varint(&mut reader)?),
+            1 => WireValue::Fixed64(unexpected_end(reader.read_u64::<LittleEndian>())?),
+            2 => {
+                let len = read_varint(&mut reader)? as usize;
+                WireValue::Bytes(read_slice(&mut reader, len)?)
+            }
+            5 => WireValue::Fixed32(unexpected_end(reader.read_u32::<LittleEndian>())?),
+            wire_type => bail!("Unsupported wire type {} for field {}", wire_type, number),
+        };
+        fields.push((number, value));
+    }
+    Ok(fields)
+}
+
+fn string_field(value: WireValue) -> String {
+    match value {
+        WireValue::Bytes(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+        _ => String::new(),
+    }
+}
+
+fn qualified_name(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}.{}", scope, name)
+    }
+}
+
+#[derive(Debug)]
+struct FieldDescriptor {
+    name: String,
+    field_type: FieldType,
+    repeated: bool,
+    /// Full name of the message or enum type, without the leading dot.
+    type_name: String,
+}
+
+#[derive(Debug, Default)]
+struct MessageDescriptor {
+    fields: HashMap<u32, FieldDescriptor>,
+    /// Generated for the entries of map fields.
+    map_entry: bool,
+}
+
+/// The message and enum types of a `FileDescriptorSet`, by full name.
+#[derive(Debug, Default)]
+struct DescriptorPool {
+    messages: HashMap<String, MessageDescriptor>,
+    enums: HashMap<String, HashMap<i32, String>>,
+}
+
+impl DescriptorPool {
+    fn parse(bytes: &[u8]) -> Result<DescriptorPool> {
+        let mut pool = DescriptorPool::default();
+        for (number, value) in read_fields(bytes)? {
+            if let (1, WireValue::Bytes(file)) = (number, value) {
+                pool.add_file(file)?;
+            }
+        }
+        Ok(pool)
+    }
+
+    fn add_file(&mut self, bytes: &[u8]) -> Result<()> {
+        let fields = read_fields(bytes)?;
+        let package = fields
+            .iter()
+            .find(|&&(number, _)| number == 2)
+            .map(|&(_, value)| string_field(value))
+            .unwrap_or_default();
+        for (number, value) in fields {
+            match (number, value) {
+                (4, WireValue::Bytes(message)) => self.add_message(&package, message)?,
+                (5, WireValue::Bytes(enum_type)) => self.add_enum(&package, enum_type)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn add_message(&mut self, scope: &str, bytes: &[u8]) -> Result<()> {
+        let fields = read_fields(bytes)?;
+        let name = fields
+            .iter()
+            .find(|&&(number, _)| number == 1)
+            .map(|&(_, value)| qualified_name(scope, &string_field(value)))
+            .unwrap_or_default();
+        let mut descriptor = MessageDescriptor::default();
+        for (number, value) in fields {
+            match (number, value) {
+                (2, WireValue::Bytes(field)) => {
+                    let (field_number, field) = parse_field(field)?;
+                    descriptor.fields.insert(field_number, field);
+                }
+                (3, WireValue::Bytes(nested)) => self.add_message(&name, nested)?,
+                (4, WireValue::Bytes(enum_type)) => self.add_enum(&name, enum_type)?,
+                (7, WireValue::Bytes(options)) => {
+                    descriptor.map_entry =
+                        read_fields(options)?
+                            .into_iter()
+                            .any(|option| match option {
+                                (7, WireValue::Varint(value)) => value != 0,
+                                _ => false,
+                            });
+                }
+                _ => {}
+            }
+        }
+        self.messages.insert(name, descriptor);
+        Ok(())
+    }
+
+    fn add_enum(&mut self, scope: &str, bytes: &[u8]) -> Result<()> {
+        let mut name = String::new();
+        let mut values = HashMap::new();
+        for (number, value) in read_fields(bytes)? {
+            match (number, value) {
+                (1, value) => name = qualified_name(scope, &string_field(value)),
+                (2, WireValue::Bytes(enum_value)) => {
+                    let mut value_name = String::new();
+                    let mut value_number = 0;
+                    for (number, value) in read_fields(enum_value)? {
+                        match (number, value) {
+                            (1, value) => value_name = string_field(value),
+                            (2, WireValue::Varint(n)) => value_number = n as i32,
+                            _ => {}
+                        }
+                    }
+                    values.insert(value_number, value_name);
+                }
+                _ => {}
+            }
+        }
+        self.enums.insert(name, values);
+        Ok(())
+    }
+
+    fn is_map(&self, field: &FieldDescriptor) -> bool {
+        field.field_type == FieldType::Message
+            && self
+                .messages
+                .get(&field.type_name)
+                .map(|message| message.map_entry)
+                .unwrap_or(false)
+    }
+
+    fn decode_message(&self, message_type: &str, bytes: &[u8], depth: usize) -> Result<Value> {
+        if depth > MAX_DEPTH {
+            bail!("Too many nested messages");
+        }
+        let descriptor = match self.messages.get(message_type) {
+            Some(descriptor) => descriptor,
+            None => bail!("Unknown message type {}", message_type),
+        };
+        let mut object = Map::new();
+        for (number, value) in read_fields(bytes)? {
+            let field = match descriptor.fields.get(&number) {
+                Some(field) => field,
+                None => {
+                    // Unknown fields are shown by number, with their raw value.
+                    object.insert(number.to_string(), raw_value(value));
+                    continue;
+                }
+            };
+            let values = self.decode_field(field, value, depth)?;
+            if field.repeated {
+                let entry = object
+                    .entry(field.name.clone())
+                    .or_insert_with(|| Value::Array(Vec::new()));
+                if let Value::Array(ref mut array) = *entry {
+                    array.extend(values);
+                }
+            } else if let Some(value) = values.into_iter().last() {
+                object.insert(field.name.clone(), value);
+            }
+        }
+        // The entries of map fields are converted to an object.
+        for field in descriptor
+            .fields
+            .values()
+            .filter(|field| self.is_map(field))
+        {
+            if let Some(Value::Array(entries)) = object.remove(&field.name) {
+                let map = entries
+                    .into_iter()
+                    .map(|entry| {
+                        let key = match entry.get("key") {
+                            Some(&Value::String(ref key)) => key.clone(),
+                            Some(key) => key.to_string(),
+                            None => String::new(),
+                        };
+                        (key, entry.get("value").cloned().unwrap_or(Value::Null))
+                    })
+                    .collect();
+                object.insert(field.name.clone(), Value::Object(map));
+            }
+        }
+        Ok(Value::Object(object))
+    }
+
+    /// Decodes a field, which can contain multiple values for packed repeated fields.
+    fn decode_field(
+        &self,
+        field: &FieldDescriptor,
+        value: WireValue,
+        depth: usize,
+    ) -> Result<Vec<Value>> {
+        let bytes = match value {
+            WireValue::Bytes(bytes) => bytes,
+            value => return Ok(vec![self.scalar_value(field, value)?]),
+        };
+        let value = match field.field_type {
+            FieldType::String => Value::String(String::from_utf8_lossy(bytes).into_owned()),
+            FieldType::Bytes => bytes_value(bytes),
+            FieldType::Message => self.decode_message(&field.type_name, bytes, depth + 1)?,
+            FieldType::Group => bail!("Groups are not supported"),
+            field_type => {
+                let mut reader = Cursor::new(bytes);
+                let mut values = Vec::new();
+                while (reader.position() as usize) < bytes.len() {
+                    let value = match field_type {
+                        FieldType::Double | FieldType::Fixed64 | FieldType::Sfixed64 => {
+                            WireValue::Fixed64(unexpected_end(reader.read_u64::<LittleEndian>())?)
+                        }
+                        FieldType::Float | FieldType::Fixed32 | FieldType::Sfixed32 => {
+                            WireValue::Fixed32(unexpected_end(reader.read_u32::<LittleEndian>())?)
+                        }
+                        _ => WireValue::Varint(read_varint(&mut reader)?),
+                    };
+                    values.push(self.scalar_value(field, value)?);
+                }
+                return Ok(values);
+            }
+        };
+        Ok(vec![value])
+    }
+
+    fn scalar_value(&self, field: &FieldDescriptor, value: WireValue) -> Result<Value> {
+        let value = match (field.field_type, value) {
+            (FieldType::Int32, WireValue::Varint(v)) => Value::from(v as i32),
+            (FieldType::Int64, WireValue::Varint(v)) => Value::from(v as i64),
+            (FieldType::Uint32, WireValue::Varint(v)) => Value::from(v as u32),
+            (FieldType::Uint64, WireValue::Varint(v)) => Value::from(v),
+            (FieldType::Sint32, WireValue::Varint(v))
+            | (FieldType::Sint64, WireValue::Varint(v)) => Value::from(zigzag(v)),
+            (FieldType::Bool, WireValue::Varint(v)) => Value::Bool(v != 0),
+            (FieldType::Enum, WireValue::Varint(v)) => {
+                match self
+                    .enums
+                    .get(&field.type_name)
+                    .and_then(|values| values.get(&(v as i32)))
+                {
+                    Some(name) => Value::String(name.clone()),
+                    None => Value::from(v as i32),
+                }
+            }
+            (FieldType::Fixed64, WireValue::Fixed64(v)) => Value::from(v),
+            (FieldType::Sfixed64, WireValue::Fixed64(v)) => Value::from(v as i64),
+            (FieldType::Double, WireValue::Fixed64(v)) => float_value(f64::from_bits(v)),
+            (FieldType::Fixed32, WireValue::Fixed32(v)) => Value::from(v),
+            (FieldType::Sfixed32, WireValue::Fixed32(v)) => Value::from(v as i32),
+            (FieldType::Float, WireValue::Fixed32(v)) => float_value(f64::from(f32::from_bits(v))),
+            (field_type, _) => bail!(
+                "Unexpected wire type for field {} of type {:?}",
+                field.name,
+                field_type
+            ),
+        };
+        Ok(value)
+    }
+}
+
+fn parse_field(bytes: &[u8]) -> Result<(u32, FieldDescriptor)> {
+    let mut name = String::new();
+    let mut number = 0;
+    let mut repeated = false;
+    let mut field_type = None;
+    let mut type_name = String::new();
+    for (field_number, value) in read_fields(bytes)? {
+        match (field_number, value) {
+            (1, value) => name = string_field(value),
+            (3, WireValue::Varint(n)) => number = n as u32,
+            (4, WireValue::Varint(label)) => repeated = label == 3,
+            (5, WireValue::Varint(n)) => field_type = FieldType::from_number(n),
+            (6, value) => type_name = string_field(value).trim_start_matches('.').to_owned(),
+            _ => {}
+        }
+    }
+    match field_type {
+        Some(field_type) => Ok((
+            number,
+            FieldDescriptor {
+                name,
+                field_type,
+                repeated,
+                type_name,
+            },
+        )),
+        None => bail!("Missing or unknown type for field {}", name),
+    }
+}
+
+fn raw_value(value: WireValue) -> Value {
+    match value {
+        WireValue::Varint(v) | WireValue::Fixed64(v) => Value::from(v),
+        WireValue::Fixed32(v) => Value::from(v),
+        WireValue::Bytes(bytes) => bytes_value(bytes),
+    }
+}
+
+fn load_descriptor_set(path: &str) -> Result<DescriptorPool> {
+    let mut bytes = Vec::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .chain_err(|| format!("Unable to read descriptor set {}", path))?;
+    DescriptorPool::parse(&bytes).chain_err(|| format!("Invalid descriptor set {}", path))
+}
+
+fn descriptor_set(path: &str) -> Result<Arc<DescriptorPool>> {
+    if let Some(pool) = DESCRIPTOR_SETS.read().unwrap().get(path) {
+        return Ok(pool.clone());
+    }
+    if let Some(&(failed_at, ref error)) = FAILED_DESCRIPTOR_SETS.read().unwrap().get(path) {
+        if failed_at.elapsed() < LOAD_RETRY_INTERVAL {
+            bail!("Descriptor set {} is not available: {}", path, error);
+        }
+    }
+    match load_descriptor_set(path) {
+        Ok(pool) => {
+            let pool = Arc::new(pool);
+            FAILED_DESCRIPTOR_SETS.write().unwrap().remove(path);
+            DESCRIPTOR_SETS
+                .write()
+                .unwrap()
+                .insert(path.to_owned(), pool.clone());
+            Ok(pool)
+        }
+        Err(e) => {
+            FAILED_DESCRIPTOR_SETS
+                .write()
+                .unwrap()
+                .insert(path.to_owned(), (Instant::now(), e.to_string()));
+            Err(e)
+        }
+    }
+}
+
+/// Skips the header of the Confluent wire format if present: a zero magic byte, the schema id,
+/// and the indexes of the message type in the schema. A plain protobuf message can't start with
+/// a zero byte, as zero is not a valid field number. The message type of the topic is used
+/// regardless of the indexes.
+fn strip_wire_format(payload: &[u8]) -> Result<&[u8]> {
+    if payload.len() < 5 || payload[0] != 0 {
+        return Ok(payload);
+    }
+    let mut reader = Cursor::new(&payload[5..]);
+    let count = zigzag(read_varint(&mut reader)?);
+    for _ in 0..count {
+        read_varint(&mut reader)?;
+    }
+    Ok(&payload[5 + reader.position() as usize..])
+}
+
+/// Decodes a protobuf message of the given type, defined in the descriptor set file.
+pub fn decode(descriptor_set_path: &str, message_type: &str, payload: &[u8]) -> Result<Value> {
+    let pool = descriptor_set(descriptor_set_path)?;
+    pool.decode_message(
+        message_type.trim_start_matches('.'),
+        strip_wire_format(payload)?,
+        0,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint(mut value: u64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        loop {
+            if value < 0x80 {
+                bytes.push(value as u8);
+                return bytes;
+            }
+            bytes.push((value & 0x7f) as u8 | 0x80);
+            value >>= 7;
+        }
+    }
+
+    fn varint_field(number: u64, value: u64) -> Vec<u8> {
+        let mut bytes = varint(number << 3);
+        bytes.extend(varint(value));
+        bytes
+    }
+
+    fn bytes_field(number: u64, value: &[u8]) -> Vec<u8> {
+        let mut bytes = varint(number << 3 | 2);
+        bytes.extend(varint(value.len() as u64));
+        bytes.extend(value);
+        bytes
+    }
+
+    fn field_descriptor(
+        name: &str,
+        number: u64,
+        label: u64,
+        field_type: u64,
+        type_name: &str,
+    ) -> Vec<u8> {
+        let mut bytes = bytes_field(1, name.as_bytes());
+        bytes.extend(varint_field(3, number));
+        bytes.extend(varint_field(4, label));
+        bytes.extend(varint_field(5, field_type));
+        if !type_name.is_empty() {
+            bytes.extend(bytes_field(6, type_name.as_bytes()));
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_decode_protobuf() {
+        // package shop; enum Status { NEW = 0; PAID = 1; }
+        // message Order { int64 id = 1; Status status = 2; repeated sint32 deltas = 3; map<string, string> labels = 4; }
+        let mut status = bytes_field(1, b"Status");
+        status.extend(bytes_field(
+            2,
+            &[bytes_field(1, b"NEW"), varint_field(2, 0)].concat(),
+        ));
+        status.extend(bytes_field(
+            2,
+            &[bytes_field(1, b"PAID"), varint_field(2, 1)].concat(),
+        ));
+        let mut entry = bytes_field(1, b"LabelsEntry");
+        entry.extend(bytes_field(2, &field_descriptor("key", 1, 1, 9, "")));
+        entry.extend(bytes_field(2, &field_descriptor("value", 2, 1, 9, "")));
+        entry.extend(bytes_field(7, &varint_field(7, 1)));
+        let mut order = bytes_field(1, b"Order");
+        order.extend(bytes_field(2, &field_descriptor("id", 1, 1, 3, "")));
+        order.extend(bytes_field(
+            2,
+            &field_descriptor("status", 2, 1, 14, ".shop.Status"),
+        ));
+        order.extend(bytes_field(2, &field_descriptor("deltas", 3, 3, 17, "")));
+        order.extend(bytes_field(
+            2,
+            &field_descriptor("labels", 4, 3, 11, ".shop.Order.LabelsEntry"),
+        ));
+        order.extend(bytes_field(3, &entry));
+        let mut file = bytes_field(2, b"shop");
+        file.extend(bytes_field(4, &order));
+        file.extend(bytes_field(5, &status));
+        let pool = DescriptorPool::parse(&bytes_field(1, &file)).unwrap();
+
+        let mut message = varint_field(1, 42);
+        message.extend(varint_field(2, 1));
+        message.extend(bytes_field(3, &[0x03, 0x04]));
+        message.extend(bytes_field(
+            4,
+            &[bytes_field(1, b"env"), bytes_field(2, b"prod")].concat(),
+        ));
+        message.extend(varint_field(9, 7));
+        assert_eq!(
+            pool.decode_message("shop.Order", &message, 0).unwrap(),
+            json!({"id": 42, "status": "PAID", "deltas": [-2, 2], "labels": {"env": "prod"}, "9": 7})
+        );
+
+        let mut wire_format = vec![0, 0, 0, 0, 1, 0];
+        wire_format.extend(&message);
+        assert_eq!(strip_wire_format(&wire_format).unwrap(), &message[..]);
+        assert!(pool.decode_message("shop.Missing", &message, 0).is_err());
+    }
+
+    #[test]
+    fn test_descriptor_set_failure_is_remembered() {
+        let path = "/nonexistent/kafka-view-test.desc";
+        assert!(decode(path, "shop.Order", b"").is_err());
+        assert!(FAILED_DESCRIPTOR_SETS.read().unwrap().contains_key(path));
+        let error = decode(path, "shop.Order", b"").unwrap_err().to_string();
+        assert!(error.contains("is not available"));
+    }
+}
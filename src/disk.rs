@@ -0,0 +1,306 @@
+use chrono::Utc;
+use scheduled_executor::TaskGroup;
+
+use cache::Cache;
+use config::{ClusterConfig, Config};
+use error::*;
+use metadata::{BrokerId, ClusterId};
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// Broker level metrics of the Jolokia catalogue, reporting the space of the log directories.
+pub const LOG_DIR_TOTAL_BYTES: &str = "log_dir_total_bytes";
+pub const LOG_DIR_USABLE_BYTES: &str = "log_dir_usable_bytes";
+
+/// Minimum time between two samples of the disk usage history.
+const SAMPLE_INTERVAL_SECS: i64 = 3600;
+/// How far back the history used for the forecast goes.
+const HISTORY_SECS: i64 = 7 * 24 * 3600;
+/// The forecast needs at least this much history.
+const MIN_FORECAST_HISTORY_SECS: i64 = 6 * 3600;
+
+/// A point of the disk usage history of a broker.
+#[derive(PartialEq, Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct DiskUsageSample {
+    /// Unix timestamp in seconds.
+    pub timestamp: i64,
+    pub used_bytes: f64,
+}
+
+/// Disk usage of a broker, as known from the metrics.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct BrokerDiskUsage {
+    /// Sum of the sizes of all the partition replicas stored on the broker.
+    pub partitions_bytes: Option<f64>,
+    /// Used space of the log directories, if the MBeans are available.
+    pub log_dirs_used_bytes: Option<f64>,
+    pub capacity_bytes: Option<f64>,
+}
+
+impl BrokerDiskUsage {
+    /// The space used on disk. The usage of the log directories is preferred, since it also
+    /// includes indexes and any other file.
+    pub fn used_bytes(&self) -> Option<f64> {
+        self.log_dirs_used_bytes.or(self.partitions_bytes)
+    }
+
+    pub fn used_percent(&self) -> Option<f64> {
+        match (self.used_bytes(), self.capacity_bytes) {
+            (Some(used), Some(capacity)) if capacity > 0f64 => Some(used * 100f64 / capacity),
+            _ => None,
+        }
+    }
+}
+
+/// Rolls up the partition sizes of the cluster by broker, and reads the log dir usage of each
+/// broker if available.
+pub fn cluster_disk_usage(
+    cache: &Cache,
+    cluster_id: &ClusterId,
+    cluster_config: Option<&ClusterConfig>,
+) -> HashMap<BrokerId, BrokerDiskUsage> {
+    let mut usage: HashMap<BrokerId, BrokerDiskUsage> = cache
+        .brokers
+        .get(cluster_id)
+        .unwrap_or_default()
+        .iter()
+        .map(|broker| (broker.id, BrokerDiskUsage::default()))
+        .collect();
+
+    for ((_, topic), topic_metrics) in cache.metrics.filter_clone(|&(ref c, _)| c == cluster_id) {
+        for (broker_id, broker_metrics) in topic_metrics.brokers {
+            let broker_usage = usage.entry(broker_id).or_insert_with(Default::default);
+            if topic == "__TOTAL__" {
+                let total = broker_metrics.extra.get(LOG_DIR_TOTAL_BYTES);
+                let usable = broker_metrics.extra.get(LOG_DIR_USABLE_BYTES);
+                if let (Some(&total), Some(&usable)) = (total, usable) {
+                    broker_usage.log_dirs_used_bytes = Some(total - usable);
+                    broker_usage.capacity_bytes = Some(total);
+                }
+                continue;
+            }
+            for partition in broker_metrics
+                .partitions
+                .iter()
+                .filter(|p| p.size_bytes >= 0f64)
+            {
+                broker_usage.partitions_bytes =
+                    Some(broker_usage.partitions_bytes.unwrap_or(0f64) + partition.size_bytes);
+            }
+        }
+    }
+
+    let configured_capacity = cluster_config
+        .and_then(|config| config.disk_capacity_bytes)
+        .map(|capacity| capacity as f64);
+    for broker_usage in usage.values_mut() {
+        if broker_usage.capacity_bytes.is_none() {
+            broker_usage.capacity_bytes = configured_capacity;
+        }
+    }
+    usage
+}
+
+/// When the disk usage of a broker is expected to reach the threshold.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiskForecast {
+    CapacityUnknown,
+    NotEnoughHistory,
+    NotGrowing,
+    AboveThreshold(f64),
+    /// The threshold in percent, and the seconds left before reaching it.
+    ReachesThreshold(f64, f64),
+}
+
+impl DiskForecast {
+    /// Extrapolates the linear growth of the disk usage of the broker, computed on its history.
+    pub fn new(
+        usage: &BrokerDiskUsage,
+        history: &[DiskUsageSample],
+        threshold_percent: f64,
+    ) -> DiskForecast {
+        let (used, capacity) = match (usage.used_bytes(), usage.capacity_bytes) {
+            (Some(used), Some(capacity)) if capacity > 0f64 => (used, capacity),
+            _ => return DiskForecast::CapacityUnknown,
+        };
+        let threshold_bytes = capacity * threshold_percent / 100f64;
+        if used >= threshold_bytes {
+            return DiskForecast::AboveThreshold(threshold_percent);
+        }
+        let growth = match growth_rate(history) {
+            Some(growth) => growth,
+            None => return DiskForecast::NotEnoughHistory,
+        };
+        if growth <= 0f64 {
+            DiskForecast::NotGrowing
+        } else {
+            DiskForecast::ReachesThreshold(threshold_percent, (threshold_bytes - used) / growth)
+        }
+    }
+}
+
+impl fmt::Display for DiskForecast {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DiskForecast::CapacityUnknown => write!(f, "Disk capacity unknown"),
+            DiskForecast::NotEnoughHistory => write!(f, "Not enough history"),
+            DiskForecast::NotGrowing => write!(f, "Not growing"),
+            DiskForecast::AboveThreshold(threshold) => write!(f, "Above {:.0}%", threshold),
+            DiskForecast::ReachesThreshold(threshold, secs) => {
+                let hours = (secs / 3600f64).round().max(1f64);
+                if hours < 48f64 {
+                    write!(f, "Reaches {:.0}% in ~{:.0} hours", threshold, hours)
+                } else {
+                    write!(f, "Reaches {:.0}% in ~{:.0} days", threshold, hours / 24f64)
+                }
+            }
+        }
+    }
+}
+
+/// Growth of the disk usage in bytes per second, as the least squares slope of the history.
+fn growth_rate(history: &[DiskUsageSample]) -> Option<f64> {
+    let first = history.first()?.timestamp;
+    let last = history.last()?.timestamp;
+    if history.len() < 2 || last - first < MIN_FORECAST_HISTORY_SECS {
+        return None;
+    }
+    let n = history.len() as f64;
+    let mean_t = history
+        .iter()
+        .map(|s| (s.timestamp - first) as f64)
+        .sum::<f64>()
+        / n;
+    let mean_used = history.iter().map(|s| s.used_bytes).sum::<f64>() / n;
+    let (cov, var) = history.iter().fold((0f64, 0f64), |(cov, var), s| {
+        let dt = (s.timestamp - first) as f64 - mean_t;
+        (cov + dt * (s.used_bytes - mean_used), var + dt * dt)
+    });
+    if var > 0f64 {
+        Some(cov / var)
+    } else {
+        None
+    }
+}
+
+/// Periodically appends the disk usage of each broker to its history.
+pub struct DiskUsageTaskGroup {
+    cache: Cache,
+    config: Config,
+}
+
+impl DiskUsageTaskGroup {
+    pub fn new(cache: &Cache, config: &Config) -> DiskUsageTaskGroup {
+        DiskUsageTaskGroup {
+            cache: cache.alias(),
+            config: config.clone(),
+        }
+    }
+
+    fn record_usage(&self, cluster_id: &ClusterId) -> Result<()> {
+        let now = Utc::now().timestamp();
+        let usage = cluster_disk_usage(&self.cache, cluster_id, self.config.cluster(cluster_id));
+        for (broker_id, broker_usage) in usage {
+            let used_bytes = match broker_usage.used_bytes() {
+                Some(used_bytes) => used_bytes,
+                None => continue,
+            };
+            let key = (cluster_id.clone(), broker_id);
+            let mut history = self.cache.disk_usage.get(&key).unwrap_or_default();
+            if history
+                .last()
+                .map(|sample| now - sample.timestamp < SAMPLE_INTERVAL_SECS)
+                .unwrap_or(false)
+            {
+                continue;
+            }
+            history.retain(|sample| now - sample.timestamp <= HISTORY_SECS);
+            history.push(DiskUsageSample {
+                timestamp: now,
+                used_bytes,
+            });
+            self.cache
+                .disk_usage
+                .insert(key, history)
+                .chain_err(|| "Failed to insert to disk usage")?;
+        }
+        Ok(())
+    }
+}
+
+impl TaskGroup for DiskUsageTaskGroup {
+    type TaskId = ClusterId;
+
+    fn get_tasks(&self) -> Vec<ClusterId> {
+        self.config.clusters.keys().cloned().collect()
+    }
+
+    fn execute(&self, cluster_id: ClusterId) {
+        if let Err(e) = self.record_usage(&cluster_id) {
+            format_error_chain!(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(points: &[(i64, f64)]) -> Vec<DiskUsageSample> {
+        points
+            .iter()
+            .map(|&(timestamp, used_bytes)| DiskUsageSample {
+                timestamp,
+                used_bytes,
+            })
+            .collect()
+    }
+
+    fn usage(used: f64, capacity: Option<f64>) -> BrokerDiskUsage {
+        BrokerDiskUsage {
+            partitions_bytes: Some(used),
+            log_dirs_used_bytes: None,
+            capacity_bytes: capacity,
+        }
+    }
+
+    #[test]
+    fn test_disk_forecast() {
+        // Growing 100 bytes per hour, for 12 hours.
+        let history = history(&[(0, 0f64), (6 * 3600, 600f64), (12 * 3600, 1200f64)]);
+        let forecast = DiskForecast::new(&usage(1200f64, Some(10000f64)), &history, 85f64);
+
+        match forecast {
+            DiskForecast::ReachesThreshold(threshold, secs) => {
+                assert!((threshold - 85f64).abs() < std::f64::EPSILON);
+                assert!((secs - 73f64 * 3600f64).abs() < 1f64);
+            }
+            _ => panic!("Unexpected forecast: {:?}", forecast),
+        }
+        assert_eq!(forecast.to_string(), "Reaches 85% in ~3 days");
+    }
+
+    #[test]
+    fn test_disk_forecast_without_growth() {
+        let short = history(&[(0, 0f64), (3600, 100f64)]);
+        let flat = history(&[(0, 100f64), (6 * 3600, 100f64), (12 * 3600, 100f64)]);
+
+        assert_eq!(
+            DiskForecast::new(&usage(100f64, None), &flat, 85f64),
+            DiskForecast::CapacityUnknown
+        );
+        assert_eq!(
+            DiskForecast::new(&usage(100f64, Some(1000f64)), &short, 85f64),
+            DiskForecast::NotEnoughHistory
+        );
+        assert_eq!(
+            DiskForecast::new(&usage(100f64, Some(1000f64)), &flat, 85f64),
+            DiskForecast::NotGrowing
+        );
+        assert_eq!(
+            DiskForecast::new(&usage(900f64, Some(1000f64)), &flat, 85f64),
+            DiskForecast::AboveThreshold(85f64)
+        );
+    }
+}
@@ -0,0 +1,88 @@
+use hyper;
+use hyper::net::{HttpStream, HttpsConnector, NetworkConnector};
+use hyper::Client;
+use hyper_native_tls::NativeTlsClient;
+use native_tls::{Certificate, TlsConnector};
+
+use error::*;
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Like hyper's `HttpConnector`, but with a connection timeout.
+struct TimeoutConnector {
+    timeout: Duration,
+}
+
+impl NetworkConnector for TimeoutConnector {
+    type Stream = HttpStream;
+
+    fn connect(&self, host: &str, port: u16, scheme: &str) -> hyper::Result<HttpStream> {
+        if scheme != "http" {
+            return Err(
+                io::Error::new(io::ErrorKind::InvalidInput, "Invalid scheme for Http").into(),
+            );
+        }
+        let mut last_error = None;
+        for addr in (host, port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, self.timeout) {
+                Ok(stream) => return Ok(HttpStream(stream)),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        let error = last_error.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "Host didn't resolve to any address",
+            )
+        });
+        Err(error.into())
+    }
+}
+
+fn set_timeouts(client: &mut Client, read_timeout: Duration) {
+    client.set_read_timeout(Some(read_timeout));
+    client.set_write_timeout(Some(read_timeout));
+}
+
+/// Creates a plain HTTP client. Connecting, reading and writing will fail after the timeouts.
+pub fn http_client(connect_timeout: Duration, read_timeout: Duration) -> Client {
+    let mut client = Client::with_connector(TimeoutConnector {
+        timeout: connect_timeout,
+    });
+    set_timeouts(&mut client, read_timeout);
+    client
+}
+
+/// Creates a client supporting both HTTP and HTTPS. If a CA file is provided, its PEM
+/// certificate is trusted in addition to the system ones.
+pub fn https_client(
+    connect_timeout: Duration,
+    read_timeout: Duration,
+    ca_file: Option<&str>,
+) -> Result<Client> {
+    let mut builder = TlsConnector::builder();
+    if let Some(path) = ca_file {
+        let mut pem = Vec::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut pem))
+            .chain_err(|| format!("Unable to read CA file {}", path))?;
+        let certificate =
+            Certificate::from_pem(&pem).chain_err(|| format!("Invalid certificate in {}", path))?;
+        builder.add_root_certificate(certificate);
+    }
+    let tls_connector = builder
+        .build()
+        .chain_err(|| "Failed to create TLS connector")?;
+    let connector = HttpsConnector::with_connector(
+        NativeTlsClient::from(tls_connector),
+        TimeoutConnector {
+            timeout: connect_timeout,
+        },
+    );
+    let mut client = Client::with_connector(connector);
+    set_timeouts(&mut client, read_timeout);
+    Ok(client)
+}
@@ -1,33 +1,155 @@
+use rand::random;
 use rdkafka::config::ClientConfig;
-use rdkafka::consumer::{BaseConsumer, Consumer, EmptyConsumerContext};
-use rdkafka::message::BorrowedMessage;
+use rdkafka::consumer::{BaseConsumer, Consumer, DefaultConsumerContext};
 use rdkafka::message::Timestamp::*;
-use rdkafka::Message;
+use rdkafka::message::{BorrowedMessage, Headers};
+use rdkafka::metadata::MetadataPartition;
+use rdkafka::{Message, Offset, TopicPartitionList};
 use rocket::http::RawStr;
+use rocket::request::Form;
 use rocket::State;
 use scheduled_executor::ThreadPoolExecutor;
 
 use config::{ClusterConfig, Config};
+use decoders::{DecodedPayload, TopicDecoder};
 use error::*;
 use metadata::ClusterId;
+use offsets::offset_for_timestamp;
 
-use std::borrow::Cow;
+use std::cmp;
 use std::collections::HashMap;
+use std::io;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, RwLock};
+use std::thread;
 use std::time::{Duration, Instant};
 
+/// Where a live consumer starts reading each partition from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TailStart {
+    Earliest,
+    Latest,
+    /// The last N messages of each partition.
+    LastMessages(i64),
+    /// The given offset for each listed partition, the end for the others.
+    Offsets(HashMap<i32, i64>),
+    /// First message with a timestamp greater or equal to the given one (millis since epoch).
+    Timestamp(i64),
+}
+
+impl TailStart {
+    /// Parses the start position of a tailer request. The value is the number of messages for
+    /// `last`, the timestamp for `timestamp`, and a list such as `0:1200,1:1350` for `offsets`.
+    pub fn parse(start: Option<&str>, value: Option<&str>) -> Result<TailStart> {
+        let number = |value: Option<&str>| -> Result<i64> {
+            match value.map(|value| value.trim().parse::<i64>()) {
+                Some(Ok(number)) => Ok(number),
+                Some(Err(_)) => bail!("Invalid value: {}", value.unwrap()),
+                None => bail!("A value is required for the '{}' start", start.unwrap()),
+            }
+        };
+        let tail_start = match start {
+            None | Some("latest") => TailStart::Latest,
+            Some("earliest") => TailStart::Earliest,
+            Some("last") => match number(value)? {
+                count if count > 0 => TailStart::LastMessages(count),
+                count => bail!("The number of messages must be positive: {}", count),
+            },
+            Some("timestamp") => TailStart::Timestamp(number(value)?),
+            Some("offsets") => {
+                let mut offsets = HashMap::new();
+                for entry in value
+                    .unwrap_or("")
+                    .split(',')
+                    .filter(|e| !e.trim().is_empty())
+                {
+                    let mut parts = entry.splitn(2, ':');
+                    let partition = parts.next().and_then(|p| p.trim().parse::<i32>().ok());
+                    let offset = parts.next().and_then(|o| o.trim().parse::<i64>().ok());
+                    match (partition, offset) {
+                        (Some(partition), Some(offset)) => offsets.insert(partition, offset),
+                        _ => bail!("Invalid partition offset: {}", entry),
+                    };
+                }
+                if offsets.is_empty() {
+                    bail!("A value is required for the 'offsets' start");
+                }
+                TailStart::Offsets(offsets)
+            }
+            Some(other) => bail!("Unknown tailer start: {}", other),
+        };
+        Ok(tail_start)
+    }
+}
+
+/// Keeps the messages with a header of the given name and, if specified, value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeaderFilter {
+    name: String,
+    value: Option<String>,
+}
+
+impl HeaderFilter {
+    /// Parses the header filter of a tailer request, none if no header name is specified.
+    pub fn parse(name: Option<&str>, value: Option<&str>) -> Option<HeaderFilter> {
+        fn non_empty(text: Option<&str>) -> Option<&str> {
+            text.map(str::trim).filter(|text| !text.is_empty())
+        }
+        non_empty(name).map(|name| HeaderFilter {
+            name: name.to_owned(),
+            value: non_empty(value).map(str::to_owned),
+        })
+    }
+
+    fn matches(&self, headers: &[(String, String)]) -> bool {
+        headers.iter().any(|&(ref name, ref value)| {
+            *name == self.name && self.value.as_ref().map_or(true, |v| v == value)
+        })
+    }
+}
+
+/// What a live consumer reads: where it starts from and which messages it keeps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TailOptions {
+    pub start: TailStart,
+    pub header_filter: Option<HeaderFilter>,
+}
+
+impl TailOptions {
+    pub fn parse(
+        start: Option<&str>,
+        value: Option<&str>,
+        header: Option<&str>,
+        header_value: Option<&str>,
+    ) -> Result<TailOptions> {
+        Ok(TailOptions {
+            start: TailStart::parse(start, value)?,
+            header_filter: HeaderFilter::parse(header, header_value),
+        })
+    }
+}
+
 pub struct LiveConsumer {
     id: u64,
     cluster_id: ClusterId,
     topic: String,
+    start: TailStart,
+    header_filter: Option<HeaderFilter>,
     last_poll: RwLock<Instant>,
-    consumer: BaseConsumer<EmptyConsumerContext>,
+    consumer: BaseConsumer<DefaultConsumerContext>,
     active: AtomicBool,
+    activation_error: RwLock<Option<String>>,
+    /// The offsets the partitions were assigned from.
+    start_offsets: RwLock<HashMap<i32, i64>>,
 }
 
 impl LiveConsumer {
-    fn new(id: u64, cluster_config: &ClusterConfig, topic: &str) -> Result<LiveConsumer> {
+    fn new(
+        id: u64,
+        cluster_config: &ClusterConfig,
+        topic: &str,
+        options: TailOptions,
+    ) -> Result<LiveConsumer> {
         let consumer = ClientConfig::new()
             .set("bootstrap.servers", &cluster_config.bootstrap_servers())
             .set("group.id", &format!("kafka_view_live_consumer_{}", id))
@@ -47,27 +169,150 @@ impl LiveConsumer {
             cluster_id: cluster_config.cluster_id.clone().unwrap(),
             consumer,
             active: AtomicBool::new(false),
+            activation_error: RwLock::new(None),
+            start_offsets: RwLock::new(HashMap::new()),
             last_poll: RwLock::new(Instant::now()),
             topic: topic.to_owned(),
+            start: options.start,
+            header_filter: options.header_filter,
         })
     }
 
+    /// The offset the partition should be read from, according to the start position.
+    fn start_offset(&self, partition: i32) -> Result<Offset> {
+        let offset = match self.start {
+            TailStart::Earliest => Offset::Beginning,
+            TailStart::Latest => Offset::End,
+            TailStart::LastMessages(count) => {
+                let (low, high) = self
+                    .consumer
+                    .fetch_watermarks(&self.topic, partition, Duration::from_secs(10))
+                    .chain_err(|| {
+                        format!(
+                            "Failed to fetch watermarks for {}/{}",
+                            self.topic, partition
+                        )
+                    })?;
+                Offset::Offset(cmp::max(high.saturating_sub(count), low))
+            }
+            TailStart::Offsets(ref offsets) => offsets
+                .get(&partition)
+                .map(|&offset| Offset::Offset(offset))
+                .unwrap_or(Offset::End),
+            TailStart::Timestamp(timestamp) => Offset::Offset(offset_for_timestamp(
+                &self.consumer,
+                &self.topic,
+                partition,
+                timestamp,
+            )?),
+        };
+        Ok(offset)
+    }
+
     fn activate(&self) -> Result<()> {
-        // TODO: start from the past
-        debug!("Activating live consumer for {}", self.topic);
+        debug!(
+            "Activating live consumer for {} from {:?}",
+            self.topic, self.start
+        );
+
+        let metadata = self
+            .consumer
+            .fetch_metadata(Some(&self.topic), Duration::from_secs(10))
+            .chain_err(|| format!("Failed to fetch metadata for {}", self.topic))?;
+        let partitions = match metadata.topics().iter().find(|t| t.name() == self.topic) {
+            Some(topic) => topic
+                .partitions()
+                .iter()
+                .map(MetadataPartition::id)
+                .collect::<Vec<_>>(),
+            None => bail!("Topic {} not found", self.topic),
+        };
 
-        // TODO: use assign instead
+        let mut offsets = Vec::with_capacity(partitions.len());
+        for partition in partitions {
+            offsets.push((partition, self.start_offset(partition)?));
+        }
+        let start_offsets = self.resolve_offsets(&offsets)?;
+        let mut tp_list = TopicPartitionList::new();
+        for (&partition, &offset) in &start_offsets {
+            tp_list.add_partition_offset(&self.topic, partition, Offset::Offset(offset));
+        }
         self.consumer
-            .subscribe(vec![self.topic.as_str()].as_slice())
-            .chain_err(|| "Can't subscribe to specified topics")?;
+            .assign(&tp_list)
+            .chain_err(|| "Failure during consumer assignment")?;
+        *self.start_offsets.write().unwrap() = start_offsets;
         self.active.store(true, Ordering::Relaxed);
         Ok(())
     }
 
+    /// Replaces the beginning and end of the partitions with their actual offsets, looked up
+    /// with a single request per broker, so that the position of each partition is known before
+    /// any message is received.
+    fn resolve_offsets(&self, offsets: &[(i32, Offset)]) -> Result<HashMap<i32, i64>> {
+        let mut resolved = HashMap::with_capacity(offsets.len());
+        let mut tp_list = TopicPartitionList::new();
+        for &(partition, offset) in offsets {
+            match offset {
+                Offset::Offset(offset) => {
+                    resolved.insert(partition, offset);
+                }
+                offset => tp_list.add_partition_offset(&self.topic, partition, offset),
+            }
+        }
+        if tp_list.count() == 0 {
+            return Ok(resolved);
+        }
+        let tp_list = self
+            .consumer
+            .offsets_for_times(tp_list, Duration::from_secs(10))
+            .chain_err(|| format!("Failed to fetch the watermarks of {}", self.topic))?;
+        for element in tp_list.elements() {
+            match (element.error(), element.offset()) {
+                (Ok(()), Offset::Offset(offset)) => {
+                    resolved.insert(element.partition(), offset);
+                }
+                (Err(e), _) => bail!(
+                    "Failed to fetch the watermarks of {}/{}: {}",
+                    self.topic,
+                    element.partition(),
+                    e
+                ),
+                (Ok(()), offset) => bail!(
+                    "Unexpected watermark for {}/{}: {:?}",
+                    self.topic,
+                    element.partition(),
+                    offset
+                ),
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// The offset of the next message of each partition: the start offset of the partitions
+    /// without any message received yet.
+    fn positions(&self) -> Result<HashMap<i32, i64>> {
+        let mut positions = self.start_offsets.read().unwrap().clone();
+        let tp_list = self
+            .consumer
+            .position()
+            .chain_err(|| "Failed to read the consumer position")?;
+        for element in tp_list.elements() {
+            if let Offset::Offset(offset) = element.offset() {
+                positions.insert(element.partition(), offset);
+            }
+        }
+        Ok(positions)
+    }
+
     pub fn is_active(&self) -> bool {
         self.active.load(Ordering::Relaxed)
     }
 
+    /// The error that prevented the consumer from starting, if any.
+    pub fn activation_error(&self) -> Option<String> {
+        self.activation_error.read().unwrap().clone()
+    }
+
     pub fn last_poll(&self) -> Instant {
         *self.last_poll.read().unwrap()
     }
@@ -84,13 +329,30 @@ impl LiveConsumer {
         &self.topic
     }
 
+    /// Converts the polled messages, keeping the ones that match the header filter.
+    fn tailed_messages(
+        &self,
+        messages: &[BorrowedMessage],
+        decoder: &TopicDecoder,
+    ) -> Vec<TailedMessage> {
+        messages
+            .iter()
+            .map(|message| tailed_message(message, decoder))
+            .filter(|message| {
+                self.header_filter
+                    .as_ref()
+                    .map_or(true, |filter| filter.matches(&message.headers))
+            })
+            .collect()
+    }
+
     fn poll(&self, max_msg: usize, timeout: Duration) -> Vec<BorrowedMessage> {
         let start_time = Instant::now();
         let mut buffer = Vec::new();
         *self.last_poll.write().unwrap() = Instant::now();
 
         while Instant::elapsed(&start_time) < timeout && buffer.len() < max_msg {
-            match self.consumer.poll(100) {
+            match self.consumer.poll(Duration::from_millis(100)) {
                 None => {}
                 Some(Ok(m)) => buffer.push(m),
                 Some(Err(e)) => {
@@ -120,6 +382,7 @@ fn remove_idle_consumers(consumers: &mut LiveConsumerMap) {
     consumers.retain(|_, ref consumer| consumer.last_poll().elapsed() < Duration::from_secs(20));
 }
 
+#[derive(Clone)]
 pub struct LiveConsumerStore {
     consumers: Arc<RwLock<LiveConsumerMap>>,
     _executor: ThreadPoolExecutor,
@@ -148,13 +411,14 @@ impl LiveConsumerStore {
         (*consumers).get(&id).cloned()
     }
 
-    fn add_consumer(
+    fn insert_consumer(
         &self,
         id: u64,
         cluster_config: &ClusterConfig,
         topic: &str,
+        options: TailOptions,
     ) -> Result<Arc<LiveConsumer>> {
-        let live_consumer = LiveConsumer::new(id, cluster_config, topic)
+        let live_consumer = LiveConsumer::new(id, cluster_config, topic, options)
             .chain_err(|| "Failed to create live consumer")?;
 
         let live_consumer_arc = Arc::new(live_consumer);
@@ -164,12 +428,105 @@ impl LiveConsumerStore {
             Ok(mut consumers) => (*consumers).insert(id, live_consumer_arc.clone()),
             Err(_) => panic!("Poison error while writing consumer to cache"),
         };
+        Ok(live_consumer_arc)
+    }
 
-        live_consumer_arc
+    fn add_consumer(
+        &self,
+        id: u64,
+        cluster_config: &ClusterConfig,
+        topic: &str,
+        options: TailOptions,
+    ) -> Result<Arc<LiveConsumer>> {
+        let live_consumer = self.insert_consumer(id, cluster_config, topic, options)?;
+        live_consumer
             .activate()
             .chain_err(|| "Failed to activate live consumer")?;
+        Ok(live_consumer)
+    }
 
-        Ok(live_consumer_arc)
+    /// Like `add_consumer`, but the consumer is activated by a separate thread, since looking up
+    /// the start offsets can take a while. The consumer is active once it's done.
+    fn add_consumer_in_background(
+        &self,
+        id: u64,
+        cluster_config: &ClusterConfig,
+        topic: &str,
+        options: TailOptions,
+    ) -> Result<Arc<LiveConsumer>> {
+        let live_consumer = self.insert_consumer(id, cluster_config, topic, options)?;
+        let live_consumer_clone = live_consumer.clone();
+        thread::Builder::new()
+            .name("live-consumer-activation".to_owned())
+            .spawn(move || {
+                let result = live_consumer_clone
+                    .activate()
+                    .chain_err(|| "Failed to activate live consumer");
+                if let Err(e) = result {
+                    format_error_chain!(e);
+                    *live_consumer_clone.activation_error.write().unwrap() = Some(e.to_string());
+                }
+            })
+            .chain_err(|| "Failed to start live consumer activation thread")?;
+        Ok(live_consumer)
+    }
+
+    fn remove_consumer(&self, id: u64) {
+        self.consumers.write().unwrap().remove(&id);
+    }
+
+    /// Creates a live consumer and pushes its messages to `send` as JSON arrays, at most
+    /// `max_rate` messages per second, together with the position of each partition as a JSON
+    /// object. `send` is called every second even when no message arrived, with no batch, so
+    /// that closed connections are detected. Returns when `send` fails.
+    /// The consumer id is generated here, since no other request needs to find the consumer.
+    pub fn stream_messages<F>(
+        &self,
+        cluster_config: &ClusterConfig,
+        topic: &str,
+        options: TailOptions,
+        max_rate: usize,
+        mut send: F,
+    ) -> Result<()>
+    where
+        F: FnMut(Option<String>, String) -> io::Result<()>,
+    {
+        let id = random::<u64>();
+        let consumer = self
+            .add_consumer(id, cluster_config, topic, options)
+            .chain_err(|| {
+                format!(
+                    "Error while creating live consumer for {} {}",
+                    cluster_config.cluster_id.as_ref().unwrap(),
+                    topic
+                )
+            })?;
+        let decoder = TopicDecoder::new(cluster_config, topic);
+        let result = loop {
+            let window_start = Instant::now();
+            let messages = consumer.poll(max_rate, Duration::from_secs(1));
+            let batch = consumer.tailed_messages(&messages, &decoder);
+            let batch = if batch.is_empty() {
+                None
+            } else {
+                Some(json!(batch).to_string())
+            };
+            let positions = match consumer.positions() {
+                Ok(positions) => json!(positions).to_string(),
+                Err(e) => break Err(e),
+            };
+            if let Err(e) = send(batch, positions) {
+                debug!("Stream of live consumer {} closed: {}", id, e);
+                break Ok(());
+            }
+            // The messages over the rate stay in the consumer queue until the next second.
+            let elapsed = window_start.elapsed();
+            if messages.len() >= max_rate && elapsed < Duration::from_secs(1) {
+                thread::sleep(Duration::from_secs(1) - elapsed);
+            }
+        };
+        self.remove_consumer(id);
+        result
     }
 
     pub fn consumers(&self) -> Vec<Arc<LiveConsumer>> {
@@ -184,6 +541,9 @@ impl LiveConsumerStore {
 
 // TODO: check log in case of error
 
+/// Decoded payloads longer than this are truncated.
+const MAX_PAYLOAD_LENGTH: usize = 4096;
+
 #[derive(Serialize)]
 struct TailedMessage {
     partition: i32,
@@ -191,14 +551,81 @@ struct TailedMessage {
     key: Option<String>,
     created_at: Option<i64>,
     appended_at: Option<i64>,
+    decoder: &'static str,
     payload: String,
+    /// Names and values of the record headers, decoded as UTF-8.
+    headers: Vec<(String, String)>,
+}
+
+fn tailed_message(message: &BorrowedMessage, decoder: &TopicDecoder) -> TailedMessage {
+    let key = message
+        .key()
+        .map(|bytes| String::from_utf8_lossy(bytes).into_owned());
+    let headers = match message.headers() {
+        Some(headers) => (0..headers.count())
+            .filter_map(|i| headers.get(i))
+            .map(|(name, value)| (name.to_owned(), String::from_utf8_lossy(value).into_owned()))
+            .collect(),
+        None => Vec::new(),
+    };
+
+    let mut created_at = None;
+    let mut appended_at = None;
+    match message.timestamp() {
+        CreateTime(ctime) => created_at = Some(ctime),
+        LogAppendTime(atime) => appended_at = Some(atime),
+        NotAvailable => (),
+    }
+
+    let payload = match message.payload() {
+        Some(bytes) => decoder.decode(bytes),
+        None => DecodedPayload {
+            decoder: "none",
+            text: String::new(),
+        },
+    };
+    let text = if payload.text.chars().count() > MAX_PAYLOAD_LENGTH {
+        format!(
+            "{}...",
+            payload
+                .text
+                .chars()
+                .take(MAX_PAYLOAD_LENGTH)
+                .collect::<String>()
+        )
+    } else {
+        payload.text
+    };
+
+    TailedMessage {
+        partition: message.partition(),
+        offset: message.offset(),
+        key,
+        created_at,
+        appended_at,
+        decoder: payload.decoder,
+        payload: text,
+        headers,
+    }
 }
 
-#[get("/api/tailer/<cluster_id>/<topic>/<id>")]
+/// The query of a tailer request, parsed into `TailOptions`.
+#[derive(FromForm)]
+pub struct TailerParams {
+    start: Option<String>,
+    value: Option<String>,
+    header: Option<String>,
+    header_value: Option<String>,
+}
+
+/// The start position and the header filter are only used by the first request of each tailer,
+/// which creates its consumer.
+#[get("/api/tailer/<cluster_id>/<topic>/<id>?<params..>")]
 pub fn topic_tailer_api(
     cluster_id: ClusterId,
     topic: &RawStr,
     id: u64,
+    params: Form<TailerParams>,
     config: State<Config>,
     live_consumers_store: State<LiveConsumerStore>,
 ) -> Result<String> {
@@ -212,7 +639,17 @@ pub fn topic_tailer_api(
     let consumer = match live_consumers_store.get_consumer(id) {
         Some(consumer) => consumer,
         None => live_consumers_store
-            .add_consumer(id, cluster_config, topic)
+            .add_consumer_in_background(
+                id,
+                cluster_config,
+                topic,
+                TailOptions::parse(
+                    params.start.as_ref().map(String::as_str),
+                    params.value.as_ref().map(String::as_str),
+                    params.header.as_ref().map(String::as_str),
+                    params.header_value.as_ref().map(String::as_str),
+                )?,
+            )
             .chain_err(|| {
                 format!(
                     "Error while creating live consumer for {} {}",
@@ -222,47 +659,56 @@ pub fn topic_tailer_api(
     };
 
     if !consumer.is_active() {
+        if let Some(error) = consumer.activation_error() {
+            live_consumers_store.remove_consumer(id);
+            bail!(error);
+        }
         // Consumer is still being activated, no results for now.
         return Ok("[]".to_owned());
     }
 
-    let mut output = Vec::new();
-    for message in consumer.poll(100, Duration::from_secs(3)) {
-        let key = message
-            .key()
-            .map(|bytes| String::from_utf8_lossy(bytes))
-            .map(|cow_str| cow_str.into_owned());
+    let decoder = TopicDecoder::new(cluster_config, topic);
+    let messages = consumer.poll(100, Duration::from_secs(3));
+    let output = consumer.tailed_messages(&messages, &decoder);
 
-        let mut created_at = None;
-        let mut appended_at = None;
-        match message.timestamp() {
-            CreateTime(ctime) => created_at = Some(ctime),
-            LogAppendTime(atime) => appended_at = Some(atime),
-            NotAvailable => (),
-        }
+    Ok(json!(output).to_string())
+}
 
-        let original_payload = message
-            .payload()
-            .map(|bytes| String::from_utf8_lossy(bytes))
-            .unwrap_or(Cow::Borrowed(""));
-        let payload = if original_payload.len() > 1024 {
-            format!(
-                "{}...",
-                original_payload.chars().take(1024).collect::<String>()
-            )
-        } else {
-            original_payload.into_owned()
-        };
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        output.push(TailedMessage {
-            partition: message.partition(),
-            offset: message.offset(),
-            key,
-            created_at,
-            appended_at,
-            payload,
-        })
+    #[test]
+    fn test_tail_start_parse() {
+        assert_eq!(TailStart::parse(None, None).unwrap(), TailStart::Latest);
+        assert_eq!(
+            TailStart::parse(Some("last"), Some("100")).unwrap(),
+            TailStart::LastMessages(100)
+        );
+        assert_eq!(
+            TailStart::parse(Some("offsets"), Some("0:1200, 2:5")).unwrap(),
+            TailStart::Offsets(vec![(0, 1200), (2, 5)].into_iter().collect())
+        );
+        assert!(TailStart::parse(Some("last"), Some("0")).is_err());
+        assert!(TailStart::parse(Some("last"), Some("-9223372036854775808")).is_err());
+        assert!(TailStart::parse(Some("timestamp"), None).is_err());
+        assert!(TailStart::parse(Some("offsets"), Some("0:abc")).is_err());
+        assert!(TailStart::parse(Some("middle"), None).is_err());
     }
 
-    Ok(json!(output).to_string())
+    #[test]
+    fn test_header_filter() {
+        let headers = vec![
+            ("trace-id".to_owned(), "abc".to_owned()),
+            ("content-type".to_owned(), "json".to_owned()),
+        ];
+        assert_eq!(HeaderFilter::parse(Some(" "), Some("abc")), None);
+        let name_only = HeaderFilter::parse(Some("trace-id"), Some("")).unwrap();
+        assert!(name_only.matches(&headers));
+        assert!(!name_only.matches(&[]));
+        let name_and_value = HeaderFilter::parse(Some("content-type"), Some("json")).unwrap();
+        assert!(name_and_value.matches(&headers));
+        let other_value = HeaderFilter::parse(Some("content-type"), Some("avro")).unwrap();
+        assert!(!other_value.matches(&headers));
+    }
 }
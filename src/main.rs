@@ -10,6 +10,7 @@ extern crate serde_derive;
 extern crate serde_json;
 #[macro_use]
 extern crate lazy_static;
+extern crate base64;
 extern crate brotli;
 extern crate byteorder;
 extern crate chrono;
@@ -19,9 +20,12 @@ extern crate flate2;
 extern crate futures;
 extern crate futures_cpupool;
 extern crate hyper;
+extern crate hyper_native_tls;
 extern crate maud;
+extern crate native_tls;
 extern crate rand;
 extern crate rdkafka;
+extern crate rdkafka_sys;
 extern crate regex;
 #[macro_use]
 extern crate rocket;
@@ -32,13 +36,23 @@ extern crate zookeeper;
 
 #[macro_use]
 mod utils;
+mod acls;
+mod admin;
 mod cache;
 mod config;
+mod decoders;
+mod disk;
 mod error;
+mod http_client;
 mod live_consumer;
+mod messages;
 mod metadata;
 mod metrics;
 mod offsets;
+mod quotas;
+mod search;
+mod stats;
+mod throughput;
 mod web_server;
 mod zk;
 
@@ -46,11 +60,17 @@ use clap::{App, Arg, ArgMatches};
 use scheduled_executor::{TaskGroupScheduler, ThreadPoolExecutor};
 use std::time::Duration;
 
+use acls::AclFetchTaskGroup;
 use cache::{Cache, ReplicaReader, ReplicaWriter};
+use config::OffsetsSource;
+use disk::DiskUsageTaskGroup;
 use error::*;
 use metadata::MetadataFetchTaskGroup;
 use metrics::MetricsFetchTaskGroup;
-use offsets::run_offset_consumer;
+use offsets::{run_offset_consumer, OffsetFetchTaskGroup, ZkOffsetsFetchTaskGroup};
+use quotas::QuotaFetchTaskGroup;
+use throughput::WatermarkRatesTaskGroup;
+use zk::ZkSessionTaskGroup;
 
 include!(concat!(env!("OUT_DIR"), "/rust_version.rs"));
 
@@ -109,6 +129,27 @@ fn run_kafka_web(config_path: &str) -> Result<()> {
         Duration::from_secs(config.metadata_refresh),
     );
 
+    // ZooKeeper sessions and watches
+    executor.schedule(
+        ZkSessionTaskGroup::new(&cache, &config),
+        Duration::from_secs(0),
+        Duration::from_secs(config.metadata_refresh),
+    );
+
+    // ACLs
+    executor.schedule(
+        AclFetchTaskGroup::new(&cache, &config),
+        Duration::from_secs(0),
+        Duration::from_secs(config.metadata_refresh),
+    );
+
+    // Quotas
+    executor.schedule(
+        QuotaFetchTaskGroup::new(&cache, &config),
+        Duration::from_secs(0),
+        Duration::from_secs(config.metadata_refresh),
+    );
+
     // Metrics fetch
     executor.schedule(
         MetricsFetchTaskGroup::new(&cache, &config),
@@ -116,12 +157,39 @@ fn run_kafka_web(config_path: &str) -> Result<()> {
         Duration::from_secs(config.metrics_refresh),
     );
 
+    // Watermarks, for the consumer lag and the rates of clusters without metrics
+    executor.schedule(
+        WatermarkRatesTaskGroup::new(&cache, &config),
+        Duration::from_secs(0),
+        Duration::from_secs(config.metrics_refresh),
+    );
+
+    // Disk usage history
+    executor.schedule(
+        DiskUsageTaskGroup::new(&cache, &config),
+        Duration::from_secs(0),
+        Duration::from_secs(config.metrics_refresh),
+    );
+
     // Consumer offsets
     for (cluster_id, cluster_config) in &config.clusters {
+        if cluster_config.offsets_source != OffsetsSource::ConsumerOffsets {
+            continue;
+        }
         if let Err(e) = run_offset_consumer(cluster_id, cluster_config, &config, &cache) {
             format_error_chain!(e);
         }
     }
+    executor.schedule(
+        OffsetFetchTaskGroup::new(&cache, &config),
+        Duration::from_secs(10),
+        Duration::from_secs(config.metadata_refresh),
+    );
+    executor.schedule(
+        ZkOffsetsFetchTaskGroup::new(&cache, &config),
+        Duration::from_secs(10),
+        Duration::from_secs(config.metadata_refresh),
+    );
 
     // CACHE EXPIRATION
     let cache_clone = cache.alias();
@@ -163,6 +231,9 @@ fn run_kafka_web(config_path: &str) -> Result<()> {
             cache_clone
                 .offsets
                 .remove_expired(Duration::from_secs(offsets_store_duration));
+            cache_clone
+                .zk_offsets
+                .remove_expired(Duration::from_secs(offsets_store_duration));
         },
     );
 
@@ -0,0 +1,204 @@
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{BaseConsumer, Consumer, DefaultConsumerContext};
+use rdkafka::error::KafkaError;
+use rdkafka::message::{Headers, Timestamp};
+use rdkafka::{Message, Offset, TopicPartitionList};
+
+use config::ClusterConfig;
+use error::*;
+
+use std::cmp;
+use std::time::{Duration, Instant};
+
+/// Number of messages in a page of the message browser.
+pub const PAGE_SIZE: i64 = 20;
+
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+type BrowserConsumer = BaseConsumer<DefaultConsumerContext>;
+
+/// A message read by the message browser.
+#[derive(Debug, Clone)]
+pub struct BrowsedMessage {
+    pub offset: i64,
+    pub key: Option<Vec<u8>>,
+    pub payload: Option<Vec<u8>>,
+    /// Milliseconds since epoch.
+    pub timestamp: Option<i64>,
+    pub timestamp_type: &'static str,
+    /// Record headers, in the order they were produced.
+    pub headers: Vec<(String, Vec<u8>)>,
+}
+
+/// A window of consecutive messages of a partition.
+#[derive(Debug)]
+pub struct MessagePage {
+    pub low: i64,
+    pub high: i64,
+    /// The first offset of the page, within the watermarks.
+    pub start: i64,
+    pub messages: Vec<BrowsedMessage>,
+}
+
+impl MessagePage {
+    /// Start of the previous page, if the page isn't the first one.
+    pub fn previous_offset(&self) -> Option<i64> {
+        if self.start > self.low {
+            Some(cmp::max(self.start - PAGE_SIZE, self.low))
+        } else {
+            None
+        }
+    }
+
+    /// Start of the next page, if the page isn't the last one.
+    pub fn next_offset(&self) -> Option<i64> {
+        let next = match self.messages.last() {
+            Some(message) => message.offset + 1,
+            None => self.start + PAGE_SIZE,
+        };
+        if next < self.high {
+            Some(next)
+        } else {
+            None
+        }
+    }
+}
+
+fn create_consumer(cluster_config: &ClusterConfig) -> Result<BrowserConsumer> {
+    ClientConfig::new()
+        .set("bootstrap.servers", &cluster_config.bootstrap_servers())
+        .set("group.id", "kafka_view_message_browser")
+        .set("enable.partition.eof", "true")
+        .set("enable.auto.commit", "false")
+        .set("api.version.request", "true")
+        .create::<BrowserConsumer>()
+        .chain_err(|| "Consumer creation failed")
+}
+
+fn to_browsed_message<M: Message>(message: &M) -> BrowsedMessage {
+    let (timestamp, timestamp_type) = match message.timestamp() {
+        Timestamp::CreateTime(ts) => (Some(ts), "CreateTime"),
+        Timestamp::LogAppendTime(ts) => (Some(ts), "LogAppendTime"),
+        Timestamp::NotAvailable => (None, "NotAvailable"),
+    };
+    let headers = match message.headers() {
+        Some(headers) => (0..headers.count())
+            .filter_map(|i| headers.get(i))
+            .map(|(name, value)| (name.to_owned(), value.to_vec()))
+            .collect(),
+        None => Vec::new(),
+    };
+    BrowsedMessage {
+        offset: message.offset(),
+        key: message.key().map(|key| key.to_vec()),
+        payload: message.payload().map(|payload| payload.to_vec()),
+        timestamp,
+        timestamp_type,
+        headers,
+    }
+}
+
+/// Reads up to `count` messages of the partition starting from `offset`, or the last `count`
+/// messages if no offset is specified. Offsets outside of the watermarks are moved within them.
+pub fn read_messages(
+    cluster_config: &ClusterConfig,
+    topic: &str,
+    partition: i32,
+    offset: Option<i64>,
+    count: i64,
+) -> Result<MessagePage> {
+    let consumer = create_consumer(cluster_config)?;
+    let (low, high) = consumer
+        .fetch_watermarks(topic, partition, Duration::from_secs(10))
+        .chain_err(|| format!("Failed to fetch watermarks for {}/{}", topic, partition))?;
+    let start = match offset {
+        Some(offset) => cmp::min(cmp::max(offset, low), high),
+        None => cmp::max(high - count, low),
+    };
+    let mut page = MessagePage {
+        low,
+        high,
+        start,
+        messages: Vec::new(),
+    };
+    if start >= high {
+        return Ok(page);
+    }
+
+    let mut tp_list = TopicPartitionList::new();
+    tp_list.add_partition_offset(topic, partition, Offset::Offset(start));
+    consumer
+        .assign(&tp_list)
+        .chain_err(|| "Failure during consumer assignment")?;
+
+    let start_time = Instant::now();
+    while (page.messages.len() as i64) < count && start_time.elapsed() < READ_TIMEOUT {
+        match consumer.poll(Duration::from_millis(100)) {
+            Some(Ok(message)) => {
+                page.messages.push(to_browsed_message(&message));
+                if message.offset() >= high - 1 {
+                    break;
+                }
+            }
+            Some(Err(KafkaError::PartitionEOF(_))) => break,
+            Some(Err(e)) => bail!("Error while reading from {}/{}: {}", topic, partition, e),
+            None => {}
+        }
+    }
+    Ok(page)
+}
+
+/// Reads the message at the given offset, if it exists.
+pub fn read_message(
+    cluster_config: &ClusterConfig,
+    topic: &str,
+    partition: i32,
+    offset: i64,
+) -> Result<Option<BrowsedMessage>> {
+    let page = read_messages(cluster_config, topic, partition, Some(offset), 1)?;
+    Ok(page
+        .messages
+        .into_iter()
+        .next()
+        .filter(|message| message.offset == offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(low: i64, high: i64, start: i64, offsets: &[i64]) -> MessagePage {
+        MessagePage {
+            low,
+            high,
+            start,
+            messages: offsets
+                .iter()
+                .map(|&offset| BrowsedMessage {
+                    offset,
+                    key: None,
+                    payload: None,
+                    timestamp: None,
+                    timestamp_type: "NotAvailable",
+                    headers: Vec::new(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_page_navigation() {
+        let first = page(0, 100, 0, &(0..20).collect::<Vec<_>>());
+        assert_eq!(first.previous_offset(), None);
+        assert_eq!(first.next_offset(), Some(20));
+
+        let middle = page(0, 100, 30, &(30..50).collect::<Vec<_>>());
+        assert_eq!(middle.previous_offset(), Some(10));
+        assert_eq!(middle.next_offset(), Some(50));
+
+        // Compacted partition, with gaps between the offsets
+        let compacted = page(10, 100, 15, &[15, 40, 99]);
+        assert_eq!(compacted.previous_offset(), Some(10));
+        assert_eq!(compacted.next_offset(), None);
+    }
+}
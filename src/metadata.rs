@@ -1,21 +1,29 @@
 use byteorder::{BigEndian, ReadBytesExt};
+use chrono::Utc;
+use rdkafka::client::{Client, DefaultClientContext};
 use rdkafka::config::ClientConfig;
-use rdkafka::consumer::{BaseConsumer, Consumer, EmptyConsumerContext};
-use rdkafka::error as rderror;
+use rdkafka::consumer::{BaseConsumer, Consumer, DefaultConsumerContext};
+use rdkafka::error::{self as rderror, KafkaResult};
+use rdkafka::types::RDKafkaType;
+use rdkafka_sys as rdsys;
 use scheduled_executor::TaskGroup;
 
 use cache::Cache;
 use config::{ClusterConfig, Config};
 use error::*;
+use stats::STATS;
 use utils::read_str;
 
 use std::collections::HashMap;
 use std::error::Error;
+use std::ffi::CStr;
 use std::fmt;
 use std::io::Cursor;
+use std::os::raw::{c_int, c_void};
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
-pub type MetadataConsumer = BaseConsumer<EmptyConsumerContext>;
+pub type MetadataConsumer = BaseConsumer<DefaultConsumerContext>;
 
 lazy_static! {
     pub static ref CONSUMERS: MetadataConsumerCache = MetadataConsumerCache::new();
@@ -70,6 +78,89 @@ impl MetadataConsumerCache {
     }
 }
 
+lazy_static! {
+    static ref CLUSTER_INFO_CLIENTS: ClusterInfoClientCache = ClusterInfoClientCache::new();
+}
+
+/// A Kafka client reading what the metadata API of rust-rdkafka doesn't expose: the controller
+/// and the Kafka cluster id, for the clusters without ZooKeeper.
+struct ClusterInfoClient {
+    client: Client,
+}
+
+impl ClusterInfoClient {
+    fn new(config: &ClusterConfig) -> KafkaResult<ClusterInfoClient> {
+        let mut client_config = ClientConfig::new();
+        client_config
+            .set("bootstrap.servers", &config.bootstrap_servers())
+            .set("api.version.request", "true");
+        let native_config = client_config.create_native_config()?;
+        let client = Client::new(
+            &client_config,
+            native_config,
+            RDKafkaType::RD_KAFKA_PRODUCER,
+            DefaultClientContext,
+        )?;
+        Ok(ClusterInfoClient { client })
+    }
+
+    fn controller(&self, timeout: Duration) -> Option<BrokerId> {
+        let timeout_ms = timeout.as_millis() as c_int;
+        let controller =
+            unsafe { rdsys::rd_kafka_controllerid(self.client.native_ptr(), timeout_ms) };
+        if controller < 0 {
+            None
+        } else {
+            Some(controller)
+        }
+    }
+
+    fn kafka_cluster_id(&self, timeout: Duration) -> Option<String> {
+        let timeout_ms = timeout.as_millis() as c_int;
+        let native_ptr = self.client.native_ptr();
+        unsafe {
+            let id_ptr = rdsys::rd_kafka_clusterid(native_ptr, timeout_ms);
+            if id_ptr.is_null() {
+                return None;
+            }
+            let id = CStr::from_ptr(id_ptr).to_string_lossy().into_owned();
+            rdsys::rd_kafka_mem_free(native_ptr, id_ptr as *mut c_void);
+            Some(id)
+        }
+    }
+}
+
+struct ClusterInfoClientCache {
+    clients: RwLock<HashMap<ClusterId, Arc<ClusterInfoClient>>>,
+}
+
+impl ClusterInfoClientCache {
+    fn new() -> ClusterInfoClientCache {
+        ClusterInfoClientCache {
+            clients: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn get_or_init(
+        &self,
+        cluster_id: &ClusterId,
+        config: &ClusterConfig,
+    ) -> Result<Arc<ClusterInfoClient>> {
+        if let Some(client) = self.clients.read().unwrap().get(cluster_id) {
+            return Ok(client.clone());
+        }
+        debug!("Creating cluster information client for {}", cluster_id);
+        let client = ClusterInfoClient::new(config)
+            .map(Arc::new)
+            .chain_err(|| format!("Client creation failed for {}", cluster_id))?;
+        self.clients
+            .write()
+            .unwrap()
+            .insert(cluster_id.clone(), client.clone());
+        Ok(client)
+    }
+}
+
 // TODO: Use structs?
 pub type BrokerId = i32;
 pub type TopicName = String;
@@ -148,16 +239,70 @@ impl Broker {
     }
 }
 
+//
+// ********** CLUSTER **********
+//
+
+/// Number of controller changes kept for each cluster.
+const MAX_CONTROLLER_CHANGES: usize = 100;
+
+#[derive(PartialEq, Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct ControllerChange {
+    /// Unix timestamp in seconds.
+    pub timestamp: i64,
+    /// The new controller, or none if no broker was elected yet.
+    pub controller: Option<BrokerId>,
+}
+
+/// Cluster wide information, read at every metadata refresh.
+#[derive(PartialEq, Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ClusterInfo {
+    /// The id generated by Kafka for the cluster, not to be confused with `ClusterId`.
+    pub kafka_cluster_id: Option<String>,
+    pub controller: Option<BrokerId>,
+    /// The most recent changes of controller, oldest first. The first controller seen is also
+    /// recorded.
+    pub controller_changes: Vec<ControllerChange>,
+}
+
+impl ClusterInfo {
+    /// Sets the controller, recording the change if it's a different one.
+    pub fn update_controller(&mut self, controller: Option<BrokerId>, timestamp: i64) {
+        if !self.controller_changes.is_empty() && self.controller == controller {
+            return;
+        }
+        self.controller = controller;
+        self.controller_changes.push(ControllerChange {
+            timestamp,
+            controller,
+        });
+        if self.controller_changes.len() > MAX_CONTROLLER_CHANGES {
+            let excess = self.controller_changes.len() - MAX_CONTROLLER_CHANGES;
+            self.controller_changes.drain(..excess);
+        }
+    }
+
+    /// The number of controller changes since the timestamp, excluding the first controller
+    /// seen.
+    pub fn controller_changes_since(&self, timestamp: i64) -> usize {
+        self.controller_changes
+            .iter()
+            .skip(1)
+            .filter(|change| change.timestamp >= timestamp)
+            .count()
+    }
+}
+
 //
 // ********** REASSIGNMENT **********
 //
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct Reassignment {
     pub partitions: Vec<PartitionReassignment>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct PartitionReassignment {
     pub topic: String,
     pub partition: i32,
@@ -218,9 +363,9 @@ fn parse_member_assignment(payload_rdr: &mut Cursor<&[u8]>) -> Result<Vec<Member
     Ok(assigns)
 }
 
-fn fetch_groups(consumer: &MetadataConsumer, timeout_ms: i32) -> Result<Vec<Group>> {
+fn fetch_groups(consumer: &MetadataConsumer, timeout: Duration) -> Result<Vec<Group>> {
     let group_list = consumer
-        .fetch_group_list(None, timeout_ms)
+        .fetch_group_list(None, timeout)
         .chain_err(|| "Failed to fetch consumer group list")?;
 
     let mut groups = Vec::new();
@@ -269,7 +414,7 @@ impl MetadataFetchTaskGroup {
 
     fn fetch_data(&self, consumer: Arc<MetadataConsumer>, cluster_id: &ClusterId) -> Result<()> {
         let metadata = consumer
-            .fetch_metadata(None, 120_000)
+            .fetch_metadata(None, Duration::from_secs(120))
             .chain_err(|| format!("Failed to fetch metadata from {}", cluster_id))?;
 
         // Brokers
@@ -311,14 +456,62 @@ impl MetadataFetchTaskGroup {
         }
 
         // Groups
-        for group in fetch_groups(consumer.as_ref(), 30000)? {
+        for group in fetch_groups(consumer.as_ref(), Duration::from_secs(30))? {
             self.cache
                 .groups
                 .insert((cluster_id.clone(), group.name.to_owned()), group)?;
         }
 
+        // Controller and cluster id, which are not needed by the rest of the metadata
+        if let Err(e) = self.fetch_cluster_info(cluster_id) {
+            format_error_chain!(e);
+        }
+
         Ok(())
     }
+
+    /// Reads the controller and the Kafka cluster id from the brokers, for clusters without
+    /// ZooKeeper. With ZooKeeper, they are updated by the watch of the controller node.
+    fn fetch_cluster_info(&self, cluster_id: &ClusterId) -> Result<()> {
+        let cluster_config = self.config.cluster(cluster_id).unwrap();
+        if cluster_config.zookeeper.is_some() {
+            return Ok(());
+        }
+        let client = CLUSTER_INFO_CLIENTS.get_or_init(cluster_id, cluster_config)?;
+        let timeout = Duration::from_secs(10);
+        let controller = match client.controller(timeout) {
+            Some(controller) => controller,
+            None => bail!("The controller of {} is unknown", cluster_id),
+        };
+        let kafka_cluster_id = client.kafka_cluster_id(timeout);
+        update_cluster_info(&self.cache, cluster_id, Some(controller), kafka_cluster_id)
+    }
+}
+
+/// Stores the controller and the Kafka cluster id, recording the controller changes.
+pub fn update_cluster_info(
+    cache: &Cache,
+    cluster_id: &ClusterId,
+    controller: Option<BrokerId>,
+    kafka_cluster_id: Option<String>,
+) -> Result<()> {
+    let mut info = cache.cluster_info.get(cluster_id).unwrap_or_default();
+    let previous = info.clone();
+    info.kafka_cluster_id = kafka_cluster_id;
+    info.update_controller(controller, Utc::now().timestamp());
+    if info == previous {
+        return Ok(());
+    }
+    if previous.controller != info.controller && !previous.controller_changes.is_empty() {
+        warn!(
+            "Controller of {} changed from {:?} to {:?}",
+            cluster_id, previous.controller, info.controller
+        );
+    }
+    cache
+        .cluster_info
+        .insert(cluster_id.clone(), info)
+        .chain_err(|| "Failed to insert cluster information in cache")
 }
 
 impl TaskGroup for MetadataFetchTaskGroup {
@@ -329,13 +522,38 @@ impl TaskGroup for MetadataFetchTaskGroup {
     }
 
     fn execute(&self, cluster_id: ClusterId) {
-        match CONSUMERS.get_or_init(&cluster_id, self.config.cluster(&cluster_id).unwrap()) {
-            Ok(consumer) => {
-                if let Err(e) = self.fetch_data(consumer, &cluster_id) {
-                    format_error_chain!(e);
-                }
-            }
-            Err(e) => format_error_chain!(e),
+        let start = Instant::now();
+        let result = CONSUMERS
+            .get_or_init(&cluster_id, self.config.cluster(&cluster_id).unwrap())
+            .and_then(|consumer| self.fetch_data(consumer, &cluster_id));
+        STATS.record_fetch("metadata", &cluster_id, None, start.elapsed(), &result);
+        if let Err(e) = result {
+            format_error_chain!(e);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_controller() {
+        let mut info = ClusterInfo::default();
+        info.update_controller(Some(1), 100);
+        info.update_controller(Some(1), 200);
+        info.update_controller(None, 300);
+        info.update_controller(Some(2), 400);
+
+        assert_eq!(info.controller, Some(2));
+        assert_eq!(
+            info.controller_changes
+                .iter()
+                .map(|change| (change.timestamp, change.controller))
+                .collect::<Vec<_>>(),
+            vec![(100, Some(1)), (300, None), (400, Some(2))]
+        );
+        assert_eq!(info.controller_changes_since(0), 2);
+        assert_eq!(info.controller_changes_since(350), 1);
+    }
+}
@@ -1,36 +1,192 @@
 use chrono::{DateTime, Utc};
+use hyper::client::Response;
+use hyper::header::{Authorization, Basic, ContentType};
+use hyper::status::StatusCode;
 use hyper::Client;
-use regex::Regex;
 use scheduled_executor::TaskGroup;
 use serde_json;
 use serde_json::Value;
 
 use std::collections::{HashMap, HashSet};
 use std::f64;
+use std::fmt;
 use std::io::Read;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use cache::Cache;
-use config::Config;
+use config::{
+    Config, JolokiaConfig, JolokiaMetricConfig, JolokiaScheme, MetricAggregation, MetricsSource,
+};
 use error::*;
-use metadata::{Broker, ClusterId, TopicName};
+use http_client::{http_client, https_client};
+use metadata::{Broker, BrokerId, ClusterId, Partition, TopicName};
+use stats::STATS;
 use utils::insert_at;
 
 #[derive(PartialEq, Serialize, Deserialize, Debug, Copy, Clone)]
 pub struct PartitionMetrics {
     pub size_bytes: f64,
+    /// Message rates of the partition, only known when estimated from the watermarks.
+    #[serde(default = "unknown_metric")]
+    pub m_rate_1: f64,
+    #[serde(default = "unknown_metric")]
+    pub m_rate_5: f64,
+    #[serde(default = "unknown_metric")]
+    pub m_rate_15: f64,
+}
+
+impl PartitionMetrics {
+    pub fn with_size(size_bytes: f64) -> PartitionMetrics {
+        PartitionMetrics {
+            size_bytes,
+            ..PartitionMetrics::default()
+        }
+    }
 }
 
 impl Default for PartitionMetrics {
     fn default() -> PartitionMetrics {
-        PartitionMetrics { size_bytes: 0f64 }
+        PartitionMetrics {
+            size_bytes: 0f64,
+            m_rate_1: -1f64,
+            m_rate_5: -1f64,
+            m_rate_15: -1f64,
+        }
     }
 }
 
+/// The window rates are averaged on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateWindow {
+    OneMinute,
+    FiveMinutes,
+    FifteenMinutes,
+}
+
+impl RateWindow {
+    pub fn all() -> [RateWindow; 3] {
+        [
+            RateWindow::OneMinute,
+            RateWindow::FiveMinutes,
+            RateWindow::FifteenMinutes,
+        ]
+    }
+
+    pub fn parse(window: &str) -> Option<RateWindow> {
+        match window {
+            "1m" => Some(RateWindow::OneMinute),
+            "5m" => Some(RateWindow::FiveMinutes),
+            "15m" => Some(RateWindow::FifteenMinutes),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RateWindow::OneMinute => "1m",
+            RateWindow::FiveMinutes => "5m",
+            RateWindow::FifteenMinutes => "15m",
+        }
+    }
+
+    /// Human readable length of the window, as in "average over the last 5 minutes".
+    pub fn description(self) -> &'static str {
+        match self {
+            RateWindow::OneMinute => "minute",
+            RateWindow::FiveMinutes => "5 minutes",
+            RateWindow::FifteenMinutes => "15 minutes",
+        }
+    }
+
+    pub fn duration(self) -> Duration {
+        match self {
+            RateWindow::OneMinute => Duration::from_secs(60),
+            RateWindow::FiveMinutes => Duration::from_secs(5 * 60),
+            RateWindow::FifteenMinutes => Duration::from_secs(15 * 60),
+        }
+    }
+}
+
+impl Default for RateWindow {
+    fn default() -> RateWindow {
+        RateWindow::FifteenMinutes
+    }
+}
+
+impl fmt::Display for RateWindow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+fn unknown_metric() -> f64 {
+    -1f64
+}
+
 #[derive(PartialEq, Serialize, Deserialize, Debug, Clone)]
 pub struct TopicBrokerMetrics {
     pub m_rate_15: f64,
     pub b_rate_15: f64,
     pub partitions: Vec<PartitionMetrics>,
+    /// Additional metrics read from the configured Jolokia MBeans, by metric name.
+    #[serde(default)]
+    pub extra: HashMap<String, f64>,
+    #[serde(default = "unknown_metric")]
+    pub m_rate_1: f64,
+    #[serde(default = "unknown_metric")]
+    pub m_rate_5: f64,
+    #[serde(default = "unknown_metric")]
+    pub b_rate_1: f64,
+    #[serde(default = "unknown_metric")]
+    pub b_rate_5: f64,
+    /// Number of messages since the broker started.
+    #[serde(default = "unknown_metric")]
+    pub m_count: f64,
+    /// Number of bytes since the broker started.
+    #[serde(default = "unknown_metric")]
+    pub b_count: f64,
+}
+
+fn add_known(acc: &mut f64, value: f64) {
+    if value < 0f64 {
+        return;
+    }
+    *acc = if *acc < 0f64 { value } else { *acc + value };
+}
+
+impl TopicBrokerMetrics {
+    /// Metrics where every value is unknown.
+    pub fn unknown(partitions: Vec<PartitionMetrics>) -> TopicBrokerMetrics {
+        TopicBrokerMetrics {
+            m_rate_15: -1f64,
+            b_rate_15: -1f64,
+            partitions,
+            extra: HashMap::new(),
+            m_rate_1: -1f64,
+            m_rate_5: -1f64,
+            b_rate_1: -1f64,
+            b_rate_5: -1f64,
+            m_count: -1f64,
+            b_count: -1f64,
+        }
+    }
+
+    pub fn b_rate(&self, window: RateWindow) -> f64 {
+        match window {
+            RateWindow::OneMinute => self.b_rate_1,
+            RateWindow::FiveMinutes => self.b_rate_5,
+            RateWindow::FifteenMinutes => self.b_rate_15,
+        }
+    }
+
+    pub fn m_rate(&self, window: RateWindow) -> f64 {
+        match window {
+            RateWindow::OneMinute => self.m_rate_1,
+            RateWindow::FiveMinutes => self.m_rate_5,
+            RateWindow::FifteenMinutes => self.m_rate_15,
+        }
+    }
 }
 
 impl Default for TopicBrokerMetrics {
@@ -39,6 +195,13 @@ impl Default for TopicBrokerMetrics {
             m_rate_15: 0f64,
             b_rate_15: 0f64,
             partitions: Vec::new(),
+            extra: HashMap::new(),
+            m_rate_1: 0f64,
+            m_rate_5: 0f64,
+            b_rate_1: 0f64,
+            b_rate_5: 0f64,
+            m_count: 0f64,
+            b_count: 0f64,
         }
     }
 }
@@ -55,12 +218,23 @@ impl TopicMetrics {
         }
     }
 
+    /// Sums the metrics of all brokers. Unknown values are skipped, and the sum is unknown
+    /// only if no broker knows the value.
     pub fn aggregate_broker_metrics(&self) -> TopicBrokerMetrics {
+        if self.brokers.is_empty() {
+            return TopicBrokerMetrics::default();
+        }
         self.brokers.iter().fold(
-            TopicBrokerMetrics::default(),
+            TopicBrokerMetrics::unknown(Vec::new()),
             |mut acc, (_, broker_metrics)| {
-                acc.m_rate_15 += broker_metrics.m_rate_15;
-                acc.b_rate_15 += broker_metrics.b_rate_15;
+                add_known(&mut acc.m_rate_1, broker_metrics.m_rate_1);
+                add_known(&mut acc.m_rate_5, broker_metrics.m_rate_5);
+                add_known(&mut acc.m_rate_15, broker_metrics.m_rate_15);
+                add_known(&mut acc.m_count, broker_metrics.m_count);
+                add_known(&mut acc.b_rate_1, broker_metrics.b_rate_1);
+                add_known(&mut acc.b_rate_5, broker_metrics.b_rate_5);
+                add_known(&mut acc.b_rate_15, broker_metrics.b_rate_15);
+                add_known(&mut acc.b_count, broker_metrics.b_count);
                 acc
             },
         )
@@ -73,107 +247,727 @@ impl Default for TopicMetrics {
     }
 }
 
-fn format_jolokia_path(hostname: &str, port: i32, filter: &str) -> String {
-    format!("http://{}:{}/jolokia/read/{}?ignoreErrors=true&includeStackTrace=false&maxCollectionSize=0",
-            hostname, port, filter)
+/// Replicas whose size differs from the leader's by less than this are never flagged, to ignore
+/// the noise of small partitions.
+const MIN_DIVERGENCE_BYTES: f64 = 1024f64 * 1024f64;
+
+/// The size of a replica, compared with the size of the leader replica.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReplicaSize {
+    pub broker_id: BrokerId,
+    pub size_bytes: f64,
+    pub leader_size_bytes: f64,
+}
+
+impl ReplicaSize {
+    /// Difference from the leader's size in percent, if both sizes are known.
+    pub fn divergence(&self) -> Option<f64> {
+        if self.size_bytes < 0f64 || self.leader_size_bytes <= 0f64 {
+            return None;
+        }
+        Some((self.size_bytes - self.leader_size_bytes) * 100f64 / self.leader_size_bytes)
+    }
+
+    /// True if the replica is smaller (lagging) or bigger (bloated) than the leader by more than
+    /// the threshold percentage.
+    pub fn is_divergent(&self, threshold_percent: f64) -> bool {
+        if self.size_bytes < 0f64 || self.leader_size_bytes < 0f64 {
+            return false;
+        }
+        let difference = (self.size_bytes - self.leader_size_bytes).abs();
+        difference > MIN_DIVERGENCE_BYTES
+            && difference > self.leader_size_bytes * threshold_percent / 100f64
+    }
+
+    pub fn description(&self) -> String {
+        let state = if self.size_bytes < self.leader_size_bytes {
+            "lagging"
+        } else {
+            "bloated"
+        };
+        match self.divergence() {
+            Some(divergence) => format!("{}: {:+.1}% ({})", self.broker_id, divergence, state),
+            None => format!("{}: {}", self.broker_id, state),
+        }
+    }
+}
+
+impl TopicMetrics {
+    /// The size of the partition on the broker, if known.
+    pub fn partition_size(&self, broker_id: BrokerId, partition_id: i32) -> Option<f64> {
+        self.brokers
+            .get(&broker_id)
+            .and_then(|broker_metrics| broker_metrics.partitions.get(partition_id as usize))
+            .map(|partition_metrics| partition_metrics.size_bytes)
+            .filter(|&size| size >= 0f64)
+    }
+
+    /// The size of every follower replica of the partition, compared with the leader's. Unknown
+    /// sizes are -1.
+    pub fn follower_sizes(&self, partition: &Partition) -> Vec<ReplicaSize> {
+        let leader_size_bytes = self
+            .partition_size(partition.leader, partition.id)
+            .unwrap_or(-1f64);
+        partition
+            .replicas
+            .iter()
+            .filter(|&&broker_id| broker_id != partition.leader)
+            .map(|&broker_id| ReplicaSize {
+                broker_id,
+                size_bytes: self
+                    .partition_size(broker_id, partition.id)
+                    .unwrap_or(-1f64),
+                leader_size_bytes,
+            })
+            .collect()
+    }
 }
 
-fn fetch_metrics_json(hostname: &str, port: i32, filter: &str) -> Result<Value> {
-    let client = Client::new();
-    let url = format_jolokia_path(hostname, port, filter);
-    let mut response = client.get(&url).send().chain_err(|| "Connection error")?;
+const BYTES_IN: &str = "bytes_in";
+const MESSAGES_IN: &str = "messages_in";
+const PARTITION_SIZE: &str = "partition_size";
 
+fn read_response_body(response: &mut Response) -> Result<String> {
     let mut body = String::new();
     response
         .read_to_string(&mut body)
         .chain_err(|| "Could not read response to string")?;
 
-    let value = serde_json::from_str(&body).chain_err(|| "Failed to parse JSON")?;
+    Ok(body)
+}
 
-    Ok(value)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum RateMetric {
+    BytesIn,
+    MessagesIn,
 }
 
-fn jolokia_response_get_value(json_response: &Value) -> Result<&serde_json::Map<String, Value>> {
-    let obj = match json_response.as_object() {
-        Some(obj) => obj,
-        None => bail!("The provided Value is not a JSON object"),
-    };
-    if let Some(v) = obj.get("value") {
-        if let Some(value_obj) = v.as_object() {
-            return Ok(value_obj);
-        } else {
-            bail!("'value' is not a JSON object");
+impl RateMetric {
+    fn from_name(name: &str) -> Option<RateMetric> {
+        match name {
+            BYTES_IN => Some(RateMetric::BytesIn),
+            MESSAGES_IN => Some(RateMetric::MessagesIn),
+            _ => None,
         }
-    } else {
-        bail!("Missing value");
     }
 }
 
-fn parse_broker_rate_metrics(jolokia_json_response: &Value) -> Result<HashMap<TopicName, f64>> {
-    let value_map = jolokia_response_get_value(jolokia_json_response)
-        .chain_err(|| "Failed to extract 'value' from jolokia response.")?;
-    let mut metrics = HashMap::new();
-    let re = Regex::new(r"topic=([^,]+),").unwrap();
+/// The Jolokia attributes of a meter.
+const METER_ATTRIBUTES: [&str; 4] = [
+    "Count",
+    "OneMinuteRate",
+    "FiveMinuteRate",
+    "FifteenMinuteRate",
+];
+
+/// The count and the rates of a meter, such as `BytesInPerSec`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Meter {
+    count: Option<f64>,
+    rate_1: Option<f64>,
+    rate_5: Option<f64>,
+    rate_15: Option<f64>,
+}
 
-    for (mbean_name, value) in value_map.iter() {
-        let topic = match re.captures(mbean_name) {
-            Some(cap) => cap.get(1).unwrap().as_str(),
-            None => "__TOTAL__",
+impl Meter {
+    fn aggregate(aggregation: MetricAggregation, meters: &[Meter]) -> Meter {
+        let field = |get: fn(&Meter) -> Option<f64>| {
+            let values = meters.iter().filter_map(get).collect::<Vec<_>>();
+            if values.is_empty() {
+                None
+            } else {
+                Some(aggregate(aggregation, &values))
+            }
         };
-        if let Value::Object(ref obj) = *value {
-            match obj.get("FifteenMinuteRate") {
-                Some(&Value::Number(ref rate)) => {
-                    metrics.insert(topic.to_owned(), rate.as_f64().unwrap_or(-1f64))
-                }
-                None => bail!("Can't find key in metric"),
-                _ => bail!("Unexpected metric type"),
+        Meter {
+            count: field(|m| m.count),
+            rate_1: field(|m| m.rate_1),
+            rate_5: field(|m| m.rate_5),
+            rate_15: field(|m| m.rate_15),
+        }
+    }
+}
+
+/// The metrics of a single broker, as read by a `MetricsFetcher`. Broker-wide metrics are
+/// stored under the `__TOTAL__` topic.
+#[derive(Debug, Default)]
+struct BrokerMetrics {
+    bytes_in: HashMap<TopicName, Meter>,
+    messages_in: HashMap<TopicName, Meter>,
+    partition_sizes: HashMap<TopicName, Vec<PartitionMetrics>>,
+    extra: HashMap<TopicName, HashMap<String, f64>>,
+}
+
+impl BrokerMetrics {
+    fn meters(&mut self, metric: RateMetric) -> &mut HashMap<TopicName, Meter> {
+        match metric {
+            RateMetric::BytesIn => &mut self.bytes_in,
+            RateMetric::MessagesIn => &mut self.messages_in,
+        }
+    }
+
+    fn insert_extra(&mut self, name: &str, topic: TopicName, value: f64) {
+        self.extra
+            .entry(topic)
+            .or_insert_with(HashMap::new)
+            .insert(name.to_owned(), value);
+    }
+
+    fn insert_partition_size(&mut self, topic: &str, partition: u32, size_bytes: f64) {
+        insert_at(
+            self.partition_sizes
+                .entry(topic.to_owned())
+                .or_insert_with(Vec::new),
+            partition as usize,
+            PartitionMetrics::with_size(size_bytes),
+            // Not every partition is stored on the broker
+            PartitionMetrics::with_size(-1f64),
+        );
+    }
+
+    fn topics(&self) -> HashSet<&TopicName> {
+        self.bytes_in
+            .keys()
+            .chain(self.messages_in.keys())
+            .chain(self.partition_sizes.keys())
+            .chain(self.extra.keys())
+            .collect()
+    }
+}
+
+/// A source of broker metrics.
+trait MetricsFetcher {
+    fn fetch(&self, cluster_id: &ClusterId, broker: &Broker, port: i32) -> Result<BrokerMetrics>;
+}
+
+//
+// ********** JOLOKIA **********
+//
+
+fn aggregate(aggregation: MetricAggregation, values: &[f64]) -> f64 {
+    match aggregation {
+        MetricAggregation::Sum => values.iter().sum(),
+        MetricAggregation::Max => values.iter().cloned().fold(f64::NAN, f64::max),
+        MetricAggregation::Min => values.iter().cloned().fold(f64::NAN, f64::min),
+        MetricAggregation::Avg => values.iter().sum::<f64>() / values.len() as f64,
+    }
+}
+
+/// Returns the value of a key property of the MBean name, such as `topic` in
+/// `kafka.log:name=Size,partition=0,topic=events,type=Log`.
+fn mbean_property<'a>(mbean_name: &'a str, key: &str) -> Option<&'a str> {
+    let properties = &mbean_name[mbean_name.find(':')? + 1..];
+    properties
+        .split(',')
+        .filter_map(|property| {
+            let mut key_value = property.splitn(2, '=');
+            Some((key_value.next()?, key_value.next()?))
+        })
+        .find(|&(k, _)| k == key)
+        .map(|(_, v)| v)
+}
+
+fn is_mbean_pattern(mbean: &str) -> bool {
+    mbean.contains('*') || mbean.contains('?')
+}
+
+/// Groups the values read from a pattern by the `topic` property of the MBean. MBeans without
+/// a topic are grouped into the broker total.
+fn group_by_topic<T>(values: Vec<(String, T)>) -> HashMap<TopicName, Vec<T>> {
+    let mut topic_values = HashMap::new();
+    for (mbean_name, value) in values {
+        let topic = mbean_property(&mbean_name, "topic").unwrap_or("__TOTAL__");
+        topic_values
+            .entry(topic.to_owned())
+            .or_insert_with(Vec::new)
+            .push(value);
+    }
+    topic_values
+}
+
+/// Builds the body of a bulk Jolokia request, with one read per configured metric.
+fn jolokia_bulk_request(metrics: &[JolokiaMetricConfig]) -> Value {
+    let reads = metrics
+        .iter()
+        .map(|metric| {
+            // All the rates of the meters are read, to support any rate window.
+            let attribute = match RateMetric::from_name(&metric.name) {
+                Some(_) => json!(METER_ATTRIBUTES),
+                None => json!(metric.attribute),
             };
+            json!({
+                "type": "read",
+                "mbean": metric.mbean,
+                "attribute": attribute,
+                "config": {
+                    "ignoreErrors": true,
+                    "includeStackTrace": false,
+                    "maxCollectionSize": 0,
+                },
+            })
+        })
+        .collect();
+    Value::Array(reads)
+}
+
+/// An HTTP client for the Jolokia agents of a cluster, reused across requests.
+struct JolokiaClient {
+    client: Client,
+    config: JolokiaConfig,
+}
+
+impl JolokiaClient {
+    fn new(config: &JolokiaConfig) -> Result<JolokiaClient> {
+        let connect_timeout = Duration::from_millis(config.connect_timeout_ms);
+        let read_timeout = Duration::from_millis(config.read_timeout_ms);
+        let client = match config.scheme {
+            JolokiaScheme::Http => http_client(connect_timeout, read_timeout),
+            JolokiaScheme::Https => https_client(
+                connect_timeout,
+                read_timeout,
+                config.ca_file.as_ref().map(String::as_str),
+            )?,
+        };
+        Ok(JolokiaClient {
+            client,
+            config: config.clone(),
+        })
+    }
+
+    fn url(&self, hostname: &str, port: i32) -> String {
+        format!(
+            "{}://{}:{}{}/",
+            self.config.scheme.as_str(),
+            hostname,
+            port,
+            self.config.path.trim_end_matches('/')
+        )
+    }
+}
+
+fn fetch_jolokia_bulk(
+    jolokia: &JolokiaClient,
+    hostname: &str,
+    port: i32,
+    request: &Value,
+) -> Result<Vec<Value>> {
+    let url = jolokia.url(hostname, port);
+    let body = request.to_string();
+    let mut request = jolokia
+        .client
+        .post(&url)
+        .header(ContentType::json())
+        .body(&body);
+    if let Some(ref username) = jolokia.config.username {
+        request = request.header(Authorization(Basic {
+            username: username.clone(),
+            password: jolokia.config.password.clone(),
+        }));
+    }
+    let mut response = request.send().chain_err(|| "Connection error")?;
+    if response.status != StatusCode::Ok {
+        bail!("Unexpected HTTP status from {}: {}", url, response.status);
+    }
+
+    let body = read_response_body(&mut response)?;
+    match serde_json::from_str(&body).chain_err(|| "Failed to parse JSON")? {
+        Value::Array(responses) => Ok(responses),
+        _ => bail!("The bulk response is not a JSON array"),
+    }
+}
+
+fn jolokia_response_value(response: &Value) -> Result<&Value> {
+    let status = response.get("status").and_then(Value::as_i64).unwrap_or(-1);
+    if status != 200 {
+        let error = response.get("error").and_then(Value::as_str).unwrap_or("");
+        bail!("Jolokia returned status {}: {}", status, error);
+    }
+    match response.get("value") {
+        Some(value) => Ok(value),
+        None => bail!("Missing value"),
+    }
+}
+
+/// Extracts the (MBean name, value) pairs from the response to a single read. Pattern reads
+/// return a value per matched MBean, while the other reads return the value directly.
+fn jolokia_read_values(
+    metric: &JolokiaMetricConfig,
+    response: &Value,
+) -> Result<Vec<(String, f64)>> {
+    let attribute = match metric.attribute {
+        Some(ref attribute) => attribute,
+        None => bail!("No attribute configured"),
+    };
+    match *jolokia_response_value(response)? {
+        Value::Number(ref value) => Ok(vec![(
+            metric.mbean.clone(),
+            value.as_f64().unwrap_or(-1f64),
+        )]),
+        Value::Object(ref mbeans) => {
+            let mut values = Vec::new();
+            for (mbean_name, attributes) in mbeans {
+                match attributes.get(attribute).and_then(Value::as_f64) {
+                    Some(value) => values.push((mbean_name.clone(), value)),
+                    None => bail!("Can't find {} in {}", attribute, mbean_name),
+                }
+            }
+            Ok(values)
+        }
+        _ => bail!("Unexpected metric type"),
+    }
+}
+
+/// Extracts the (MBean name, meter) pairs from the response to the read of a meter.
+fn jolokia_read_meters(
+    metric: &JolokiaMetricConfig,
+    response: &Value,
+) -> Result<Vec<(String, Meter)>> {
+    let to_meter = |attributes: &Value| Meter {
+        count: attributes.get("Count").and_then(Value::as_f64),
+        rate_1: attributes.get("OneMinuteRate").and_then(Value::as_f64),
+        rate_5: attributes.get("FiveMinuteRate").and_then(Value::as_f64),
+        rate_15: attributes.get("FifteenMinuteRate").and_then(Value::as_f64),
+    };
+    let value = jolokia_response_value(response)?;
+    if !is_mbean_pattern(&metric.mbean) {
+        return Ok(vec![(metric.mbean.clone(), to_meter(value))]);
+    }
+    match value.as_object() {
+        Some(mbeans) => Ok(mbeans
+            .iter()
+            .map(|(mbean_name, attributes)| (mbean_name.clone(), to_meter(attributes)))
+            .collect()),
+        None => bail!("Unexpected metric type"),
+    }
+}
+
+/// Reads the JMX metrics exposed by the Jolokia agent, with a single bulk request per broker.
+struct JolokiaFetcher {
+    metrics: Vec<JolokiaMetricConfig>,
+    request: Value,
+    clients: HashMap<ClusterId, JolokiaClient>,
+}
+
+impl JolokiaFetcher {
+    fn new(config: &Config) -> JolokiaFetcher {
+        let mut clients = HashMap::new();
+        for (cluster_id, cluster_config) in &config.clusters {
+            match JolokiaClient::new(&cluster_config.jolokia) {
+                Ok(client) => {
+                    clients.insert(cluster_id.clone(), client);
+                }
+                Err(e) => {
+                    error!("Failed to create the Jolokia client for {}", cluster_id);
+                    format_error_chain!(e);
+                }
+            }
+        }
+        JolokiaFetcher {
+            metrics: config.jolokia_metrics.clone(),
+            request: jolokia_bulk_request(&config.jolokia_metrics),
+            clients,
         }
     }
-    Ok(metrics)
-}
-
-fn parse_partition_size_metrics(
-    jolokia_json_response: &Value,
-) -> Result<HashMap<TopicName, Vec<PartitionMetrics>>> {
-    let value_map = jolokia_response_get_value(jolokia_json_response)
-        .chain_err(|| "Failed to extract 'value' from jolokia response.")?;
-    let topic_re = Regex::new(r"topic=([^,]+),").unwrap();
-    let partition_re = Regex::new(r"partition=([^,]+),").unwrap();
-
-    let mut metrics = HashMap::new();
-    for (mbean_name, value) in value_map.iter() {
-        let topic = topic_re
-            .captures(mbean_name)
-            .and_then(|cap| cap.get(1).map(|m| m.as_str()));
-        let partition = partition_re
-            .captures(mbean_name)
-            .and_then(|cap| cap.get(1).map(|m| m.as_str()))
-            .and_then(|p_str| p_str.parse::<u32>().ok());
-        if topic.is_none() || partition.is_none() {
-            bail!("Can't parse topic and partition metadata from metric name");
-        }
-        if let Value::Object(ref obj) = *value {
-            match obj.get("Value") {
-                Some(&Value::Number(ref size)) => {
-                    let partition_metrics = PartitionMetrics {
-                        size_bytes: size.as_f64().unwrap_or(-1f64),
-                    };
-                    insert_at(
-                        metrics
-                            .entry(topic.unwrap().to_owned())
-                            .or_insert_with(Vec::new),
-                        partition.unwrap() as usize,
-                        partition_metrics,
-                        PartitionMetrics::default(),
+}
+
+impl MetricsFetcher for JolokiaFetcher {
+    fn fetch(&self, cluster_id: &ClusterId, broker: &Broker, port: i32) -> Result<BrokerMetrics> {
+        let client = match self.clients.get(cluster_id) {
+            Some(client) => client,
+            None => bail!("Missing Jolokia client for {}", cluster_id),
+        };
+        let responses = fetch_jolokia_bulk(client, &broker.hostname, port, &self.request)
+            .chain_err(|| format!("Failed to fetch metrics from {}", broker.hostname))?;
+        if responses.len() != self.metrics.len() {
+            bail!(
+                "Expected {} responses from {}, got {}",
+                self.metrics.len(),
+                broker.hostname,
+                responses.len()
+            );
+        }
+
+        let mut broker_metrics = BrokerMetrics::default();
+        for (metric, response) in self.metrics.iter().zip(responses.iter()) {
+            if let Some(rate_metric) = RateMetric::from_name(&metric.name) {
+                let meters = match jolokia_read_meters(metric, response) {
+                    Ok(meters) => meters,
+                    Err(e) => {
+                        warn!(
+                            "Failed to read {} from {}: {}",
+                            metric.name, broker.hostname, e
+                        );
+                        continue;
+                    }
+                };
+                for (topic, meters) in group_by_topic(meters) {
+                    let meter = Meter::aggregate(metric.aggregation, &meters);
+                    broker_metrics.meters(rate_metric).insert(topic, meter);
+                }
+                continue;
+            }
+            let values = match jolokia_read_values(metric, response) {
+                Ok(values) => values,
+                Err(e) => {
+                    warn!(
+                        "Failed to read {} from {}: {}",
+                        metric.name, broker.hostname, e
                     );
+                    continue;
                 }
-                None => bail!("Can't find key in metric"),
-                _ => bail!("Unexpected metric type"),
             };
+            if metric.name == PARTITION_SIZE {
+                for (mbean_name, value) in values {
+                    let topic = mbean_property(&mbean_name, "topic");
+                    let partition = mbean_property(&mbean_name, "partition")
+                        .and_then(|p_str| p_str.parse::<u32>().ok());
+                    match (topic, partition) {
+                        (Some(topic), Some(partition)) => {
+                            broker_metrics.insert_partition_size(topic, partition, value)
+                        }
+                        _ => bail!("Can't parse topic and partition metadata from metric name"),
+                    }
+                }
+                continue;
+            }
+            for (topic, values) in group_by_topic(values) {
+                broker_metrics.insert_extra(
+                    &metric.name,
+                    topic,
+                    aggregate(metric.aggregation, &values),
+                );
+            }
         }
+        Ok(broker_metrics)
+    }
+}
+
+//
+// ********** PROMETHEUS JMX EXPORTER **********
+//
+
+const PROMETHEUS_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const PROMETHEUS_READ_TIMEOUT: Duration = Duration::from_secs(10);
+const BROKER_TOPIC_METRICS_PREFIX: &str = "kafka_server_brokertopicmetrics_";
+
+#[derive(Debug, PartialEq)]
+struct PrometheusSample {
+    name: String,
+    labels: HashMap<String, String>,
+    value: f64,
+}
+
+impl PrometheusSample {
+    fn label(&self, name: &str) -> Option<&str> {
+        self.labels.get(name).map(String::as_str)
+    }
+}
+
+/// Parses the `{name="value",...}` label set at the beginning of `input`, returning the labels
+/// and the rest of the input.
+fn parse_prometheus_labels(input: &str) -> Option<(HashMap<String, String>, &str)> {
+    let mut labels = HashMap::new();
+    let mut rest = input.trim_start();
+    loop {
+        if rest.starts_with('}') {
+            return Some((labels, &rest[1..]));
+        }
+        let eq = rest.find('=')?;
+        let name = rest[..eq].trim().to_owned();
+        rest = rest[eq + 1..].trim_start();
+        if !rest.starts_with('"') {
+            return None;
+        }
+        let mut value = String::new();
+        let mut chars = rest[1..].char_indices();
+        let end = loop {
+            match chars.next()? {
+                (i, '"') => break i + 2,
+                (_, '\\') => match chars.next()? {
+                    (_, 'n') => value.push('\n'),
+                    (_, c) => value.push(c),
+                },
+                (_, c) => value.push(c),
+            }
+        };
+        labels.insert(name, value);
+        rest = rest[end..].trim_start();
+        if rest.starts_with(',') {
+            rest = rest[1..].trim_start();
+        }
+    }
+}
+
+/// Parses a line of the Prometheus text exposition format. Comments, empty lines and
+/// malformed samples return `None`.
+fn parse_prometheus_sample(line: &str) -> Option<PrometheusSample> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let name_end = line
+        .find(|c: char| c == '{' || c.is_whitespace())
+        .unwrap_or_else(|| line.len());
+    let name = line[..name_end].to_owned();
+    let (labels, rest) = if line[name_end..].starts_with('{') {
+        parse_prometheus_labels(&line[name_end + 1..])?
+    } else {
+        (HashMap::new(), &line[name_end..])
+    };
+    let value = match rest.split_whitespace().next()? {
+        "+Inf" => f64::INFINITY,
+        "-Inf" => f64::NEG_INFINITY,
+        value => value.parse::<f64>().ok()?,
+    };
+    Some(PrometheusSample {
+        name,
+        labels,
+        value,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RateAttribute {
+    OneMinuteRate,
+    FiveMinuteRate,
+    FifteenMinuteRate,
+    Count,
+}
+
+/// Recognizes the byte and message rate metrics of `BrokerTopicMetrics`, both when the exporter
+/// is configured with the usual Kafka rules (`kafka_server_brokertopicmetrics_bytesin_total`)
+/// and with the default ones (`kafka_server_brokertopicmetrics_count{name="BytesInPerSec"}`).
+fn classify_broker_topic_metric(sample: &PrometheusSample) -> Option<(RateMetric, RateAttribute)> {
+    let name = sample.name.to_lowercase();
+    if !name.starts_with(BROKER_TOPIC_METRICS_PREFIX) {
+        return None;
+    }
+    let rest = &name[BROKER_TOPIC_METRICS_PREFIX.len()..];
+    let (metric, attribute) = match rest.rfind('_') {
+        Some(pos) => (rest[..pos].to_owned(), &rest[pos + 1..]),
+        None => (sample.label("name")?.to_lowercase(), rest),
+    };
+    let metric = match metric.trim_end_matches("persec") {
+        "bytesin" => RateMetric::BytesIn,
+        "messagesin" => RateMetric::MessagesIn,
+        _ => return None,
+    };
+    let attribute = match attribute {
+        "oneminuterate" => RateAttribute::OneMinuteRate,
+        "fiveminuterate" => RateAttribute::FiveMinuteRate,
+        "fifteenminuterate" => RateAttribute::FifteenMinuteRate,
+        "total" | "count" => RateAttribute::Count,
+        _ => return None,
+    };
+    Some((metric, attribute))
+}
+
+fn is_partition_size_metric(sample: &PrometheusSample) -> bool {
+    match sample.name.to_lowercase().as_str() {
+        "kafka_log_log_size" => true,
+        "kafka_log_log_value" => sample.label("name") == Some("Size"),
+        _ => false,
+    }
+}
+
+type CounterKey = (ClusterId, BrokerId, RateMetric, TopicName);
+
+/// Scrapes the metrics exposed by the Prometheus JMX exporter. If the exporter doesn't expose
+/// the fifteen minute rates, the rates are computed from the counters of consecutive scrapes.
+struct PrometheusFetcher {
+    client: Client,
+    counters: Mutex<HashMap<CounterKey, (Instant, f64)>>,
+}
+
+impl PrometheusFetcher {
+    fn new() -> PrometheusFetcher {
+        PrometheusFetcher {
+            client: http_client(PROMETHEUS_CONNECT_TIMEOUT, PROMETHEUS_READ_TIMEOUT),
+            counters: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the rate of the counter since the previous scrape, if any.
+    fn counter_rate(&self, key: CounterKey, value: f64) -> Option<f64> {
+        let now = Instant::now();
+        let mut counters = self.counters.lock().unwrap();
+        let previous = counters.insert(key, (now, value));
+        match previous {
+            Some((then, previous_value)) if value >= previous_value => {
+                let elapsed = now.duration_since(then);
+                let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+                if seconds > 0f64 {
+                    Some((value - previous_value) / seconds)
+                } else {
+                    None
+                }
+            }
+            _ => None, // First scrape or counter reset
+        }
+    }
+}
+
+impl MetricsFetcher for PrometheusFetcher {
+    fn fetch(&self, cluster_id: &ClusterId, broker: &Broker, port: i32) -> Result<BrokerMetrics> {
+        let url = format!("http://{}:{}/metrics", broker.hostname, port);
+        let body = self
+            .client
+            .get(&url)
+            .send()
+            .chain_err(|| "Connection error")
+            .and_then(|mut response| {
+                if response.status != StatusCode::Ok {
+                    bail!("Unexpected HTTP status from {}: {}", url, response.status);
+                }
+                read_response_body(&mut response)
+            })
+            .chain_err(|| format!("Failed to fetch metrics from {}", broker.hostname))?;
+
+        let mut metrics = BrokerMetrics::default();
+        let mut counters = Vec::new();
+        for sample in body.lines().filter_map(parse_prometheus_sample) {
+            if let Some((metric, attribute)) = classify_broker_topic_metric(&sample) {
+                let topic = sample.label("topic").unwrap_or("__TOTAL__").to_owned();
+                let meter = metrics
+                    .meters(metric)
+                    .entry(topic.clone())
+                    .or_insert_with(Meter::default);
+                match attribute {
+                    RateAttribute::OneMinuteRate => meter.rate_1 = Some(sample.value),
+                    RateAttribute::FiveMinuteRate => meter.rate_5 = Some(sample.value),
+                    RateAttribute::FifteenMinuteRate => meter.rate_15 = Some(sample.value),
+                    RateAttribute::Count => {
+                        meter.count = Some(sample.value);
+                        counters.push((metric, topic, sample.value));
+                    }
+                }
+            } else if is_partition_size_metric(&sample) {
+                let topic = sample.label("topic");
+                let partition = sample
+                    .label("partition")
+                    .and_then(|p_str| p_str.parse::<u32>().ok());
+                if let (Some(topic), Some(partition)) = (topic, partition) {
+                    metrics.insert_partition_size(topic, partition, sample.value);
+                }
+            }
+        }
+
+        for (metric, topic, value) in counters {
+            let key = (cluster_id.clone(), broker.id, metric, topic.clone());
+            // The rates exported by the exporter take precedence over the computed one. The
+            // computed rate only covers the time since the previous scrape, so it's only used
+            // as the one minute rate.
+            if let Some(rate) = self.counter_rate(key, value) {
+                let meter = metrics
+                    .meters(metric)
+                    .entry(topic)
+                    .or_insert_with(Meter::default);
+                meter.rate_1.get_or_insert(rate);
+            }
+        }
+
+        Ok(metrics)
     }
-    Ok(metrics)
 }
 
 fn log_elapsed_time(task_name: &str, start: DateTime<Utc>) {
@@ -191,6 +985,8 @@ fn log_elapsed_time(task_name: &str, start: DateTime<Utc>) {
 pub struct MetricsFetchTaskGroup {
     cache: Cache,
     config: Config,
+    jolokia: JolokiaFetcher,
+    prometheus: PrometheusFetcher,
 }
 
 impl MetricsFetchTaskGroup {
@@ -198,72 +994,60 @@ impl MetricsFetchTaskGroup {
         MetricsFetchTaskGroup {
             cache: cache.alias(),
             config: config.clone(),
+            jolokia: JolokiaFetcher::new(config),
+            prometheus: PrometheusFetcher::new(),
         }
     }
 
-    fn fetch_metrics(&self, cluster_id: &ClusterId, broker: &Broker, port: i32) -> Result<()> {
+    fn fetch_metrics(
+        &self,
+        cluster_id: &ClusterId,
+        broker: &Broker,
+        source: MetricsSource,
+        port: i32,
+    ) -> Result<()> {
         let start = Utc::now();
-        let byte_rate_json = fetch_metrics_json(
-            &broker.hostname,
-            port,
-            "kafka.server:name=BytesInPerSec,*,type=BrokerTopicMetrics/FifteenMinuteRate",
-        )
-        .chain_err(|| format!("Failed to fetch byte rate metrics from {}", broker.hostname))?;
-
-        let byte_rate_metrics = parse_broker_rate_metrics(&byte_rate_json)
-            .chain_err(|| "Failed to parse byte rate broker metrics")?;
-
-        let msg_rate_json = fetch_metrics_json(
-            &broker.hostname,
-            port,
-            "kafka.server:name=MessagesInPerSec,*,type=BrokerTopicMetrics/FifteenMinuteRate",
-        )
-        .chain_err(|| {
-            format!(
-                "Failed to fetch message rate metrics from {}",
-                broker.hostname
-            )
-        })?;
-
-        let msg_rate_metrics = parse_broker_rate_metrics(&msg_rate_json)
-            .chain_err(|| "Failed to parse message rate broker metrics")?;
-        let partition_metrics_json = fetch_metrics_json(
-            &broker.hostname,
-            port,
-            "kafka.log:name=Size,*,type=Log/Value",
-        )
-        .chain_err(|| {
-            format!(
-                "Failed to fetch partition size metrics from {}",
-                broker.hostname
-            )
-        })?;
-
-        let pt_size_metrics = parse_partition_size_metrics(&partition_metrics_json)
-            .chain_err(|| "Failed to parse partition size broker metrics")?;
-
-        let topics = byte_rate_metrics
-            .keys()
-            .chain(msg_rate_metrics.keys())
-            .chain(pt_size_metrics.keys())
-            .collect::<HashSet<_>>();
+        let broker_metrics = match source {
+            MetricsSource::Jolokia => self.jolokia.fetch(cluster_id, broker, port)?,
+            MetricsSource::Prometheus => self.prometheus.fetch(cluster_id, broker, port)?,
+        };
 
-        for topic in topics {
+        for topic in broker_metrics.topics() {
             let mut topic_metrics = self
                 .cache
                 .metrics
                 .get(&(cluster_id.clone(), topic.clone()))
                 .unwrap_or_default();
 
-            let b_rate_15 = *byte_rate_metrics.get(topic).unwrap_or(&-1f64);
-            let m_rate_15 = *msg_rate_metrics.get(topic).unwrap_or(&-1f64);
-            let partitions = pt_size_metrics.get(topic).cloned().unwrap_or_else(Vec::new);
+            let bytes_in = broker_metrics
+                .bytes_in
+                .get(topic)
+                .cloned()
+                .unwrap_or_default();
+            let messages_in = broker_metrics
+                .messages_in
+                .get(topic)
+                .cloned()
+                .unwrap_or_default();
+            let partitions = broker_metrics
+                .partition_sizes
+                .get(topic)
+                .cloned()
+                .unwrap_or_else(Vec::new);
+            let extra = broker_metrics.extra.get(topic).cloned().unwrap_or_default();
             topic_metrics.brokers.insert(
                 broker.id,
                 TopicBrokerMetrics {
-                    m_rate_15,
-                    b_rate_15,
+                    m_rate_15: messages_in.rate_15.unwrap_or(-1f64),
+                    b_rate_15: bytes_in.rate_15.unwrap_or(-1f64),
                     partitions,
+                    extra,
+                    m_rate_1: messages_in.rate_1.unwrap_or(-1f64),
+                    m_rate_5: messages_in.rate_5.unwrap_or(-1f64),
+                    b_rate_1: bytes_in.rate_1.unwrap_or(-1f64),
+                    b_rate_5: bytes_in.rate_5.unwrap_or(-1f64),
+                    m_count: messages_in.count.unwrap_or(-1f64),
+                    b_count: bytes_in.count.unwrap_or(-1f64),
                 },
             );
 
@@ -278,19 +1062,24 @@ impl MetricsFetchTaskGroup {
 }
 
 impl TaskGroup for MetricsFetchTaskGroup {
-    type TaskId = (ClusterId, Broker, i32);
+    type TaskId = (ClusterId, Broker, MetricsSource, i32);
 
     fn get_tasks(&self) -> Vec<Self::TaskId> {
         self.cache.brokers.lock_iter(|iter| {
             let mut tasks = Vec::new();
             for (cluster_id, brokers) in iter {
-                let port = self
-                    .config
-                    .cluster(cluster_id)
-                    .and_then(|cluster_config| cluster_config.jolokia_port);
-                if port.is_some() {
+                let cluster_config = match self.config.cluster(cluster_id) {
+                    Some(cluster_config) => cluster_config,
+                    None => continue,
+                };
+                if let Some(port) = cluster_config.metrics_port() {
                     for broker in brokers {
-                        tasks.push((cluster_id.clone(), broker.clone(), port.unwrap()));
+                        tasks.push((
+                            cluster_id.clone(),
+                            broker.clone(),
+                            cluster_config.metrics_source,
+                            port,
+                        ));
                     }
                 }
             }
@@ -299,10 +1088,235 @@ impl TaskGroup for MetricsFetchTaskGroup {
         })
     }
 
-    fn execute(&self, task_id: (ClusterId, Broker, i32)) {
+    fn execute(&self, task_id: (ClusterId, Broker, MetricsSource, i32)) {
         debug!("Starting fetch for {}: {}", task_id.0, task_id.1.id);
-        if let Err(e) = self.fetch_metrics(&task_id.0, &task_id.1, task_id.2) {
+        let start = Instant::now();
+        let result = self.fetch_metrics(&task_id.0, &task_id.1, task_id.2, task_id.3);
+        STATS.record_fetch(
+            "metrics",
+            &task_id.0,
+            Some(task_id.1.id),
+            start.elapsed(),
+            &result,
+        );
+        if let Err(e) = result {
             format_error_chain!(e);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::EPSILON;
+
+    const EXPORTER_OUTPUT: &str = r#"
+# HELP kafka_server_brokertopicmetrics_bytesin_total Attribute exposed for management
+# TYPE kafka_server_brokertopicmetrics_bytesin_total counter
+kafka_server_brokertopicmetrics_bytesin_total 1234.0
+kafka_server_brokertopicmetrics_bytesin_total{topic="events",} 1000.0
+kafka_server_brokertopicmetrics_messagesinpersec_fifteenminuterate{topic="events",} 12.5
+kafka_server_BrokerTopicMetrics_Count{name="MessagesInPerSec",topic="logs",} 7.0
+kafka_log_log_size{topic="events",partition="1",} 2048.0
+kafka_log_Log_Value{name="Size",topic="logs",partition="0",} 512.0
+jvm_threads_current 42.0
+"#;
+
+    fn samples() -> Vec<PrometheusSample> {
+        EXPORTER_OUTPUT
+            .lines()
+            .filter_map(parse_prometheus_sample)
+            .collect()
+    }
+
+    #[test]
+    fn parse_samples() {
+        let samples = samples();
+        assert_eq!(samples.len(), 7);
+        assert_eq!(
+            samples[0].name,
+            "kafka_server_brokertopicmetrics_bytesin_total"
+        );
+        assert!(samples[0].labels.is_empty());
+        assert_eq!(samples[1].label("topic"), Some("events"));
+        assert!((samples[1].value - 1000f64).abs() < EPSILON);
+        assert_eq!(samples[4].label("partition"), Some("1"));
+    }
+
+    #[test]
+    fn parse_escaped_labels() {
+        let sample = parse_prometheus_sample(r#"metric{a="x\"y",b="1\\2"} 3 1500000000"#).unwrap();
+        assert_eq!(sample.label("a"), Some("x\"y"));
+        assert_eq!(sample.label("b"), Some("1\\2"));
+        assert!((sample.value - 3f64).abs() < EPSILON);
+        assert!(parse_prometheus_sample(r#"metric{a="x} 3"#).is_none());
+    }
+
+    #[test]
+    fn mbean_properties() {
+        let name = "kafka.log:name=Size,partition=3,topic=events,type=Log";
+        assert_eq!(mbean_property(name, "topic"), Some("events"));
+        assert_eq!(mbean_property(name, "partition"), Some("3"));
+        assert_eq!(mbean_property(name, "type"), Some("Log"));
+        assert_eq!(mbean_property(name, "request"), None);
+    }
+
+    #[test]
+    fn read_jolokia_values() {
+        let metric = JolokiaMetricConfig {
+            name: "bytes_out".to_owned(),
+            mbean: "kafka.server:type=BrokerTopicMetrics,name=BytesOutPerSec,*".to_owned(),
+            attribute: Some("FifteenMinuteRate".to_owned()),
+            aggregation: MetricAggregation::Sum,
+        };
+        let response = json!({
+            "status": 200,
+            "value": {
+                "kafka.server:name=BytesOutPerSec,topic=events,type=BrokerTopicMetrics": {
+                    "FifteenMinuteRate": 10.5
+                }
+            }
+        });
+        assert_eq!(
+            jolokia_read_values(&metric, &response).unwrap(),
+            vec![(
+                "kafka.server:name=BytesOutPerSec,topic=events,type=BrokerTopicMetrics".to_owned(),
+                10.5
+            )]
+        );
+        let single = json!({"status": 200, "value": 3});
+        assert_eq!(
+            jolokia_read_values(&metric, &single).unwrap(),
+            vec![(metric.mbean.clone(), 3f64)]
+        );
+        let not_found = json!({"status": 404, "error": "InstanceNotFoundException"});
+        assert!(jolokia_read_values(&metric, &not_found).is_err());
+    }
+
+    #[test]
+    fn read_jolokia_meters() {
+        let metric = JolokiaMetricConfig {
+            name: "bytes_in".to_owned(),
+            mbean: "kafka.server:type=BrokerTopicMetrics,name=BytesInPerSec,*".to_owned(),
+            attribute: None,
+            aggregation: MetricAggregation::Sum,
+        };
+        let response = json!({
+            "status": 200,
+            "value": {
+                "kafka.server:name=BytesInPerSec,type=BrokerTopicMetrics": {
+                    "Count": 100,
+                    "OneMinuteRate": 1.5,
+                    "FiveMinuteRate": 2.5,
+                    "FifteenMinuteRate": 3.5
+                }
+            }
+        });
+        let meters = jolokia_read_meters(&metric, &response).unwrap();
+        let topic_meters = group_by_topic(meters);
+        assert_eq!(
+            topic_meters["__TOTAL__"],
+            vec![Meter {
+                count: Some(100f64),
+                rate_1: Some(1.5),
+                rate_5: Some(2.5),
+                rate_15: Some(3.5),
+            }]
+        );
+    }
+
+    #[test]
+    fn aggregate_meters() {
+        let meters = [
+            Meter {
+                count: Some(10f64),
+                rate_1: Some(1f64),
+                rate_5: None,
+                rate_15: Some(3f64),
+            },
+            Meter {
+                count: Some(20f64),
+                rate_1: Some(2f64),
+                rate_5: None,
+                rate_15: None,
+            },
+        ];
+        let meter = Meter::aggregate(MetricAggregation::Sum, &meters);
+        assert_eq!(meter.count, Some(30f64));
+        assert_eq!(meter.rate_1, Some(3f64));
+        assert_eq!(meter.rate_5, None);
+        assert_eq!(meter.rate_15, Some(3f64));
+    }
+
+    #[test]
+    fn aggregations() {
+        let values = [1f64, 4f64, 2f64, 5f64];
+        let expected = [
+            (MetricAggregation::Sum, 12f64),
+            (MetricAggregation::Max, 5f64),
+            (MetricAggregation::Min, 1f64),
+            (MetricAggregation::Avg, 3f64),
+        ];
+        for &(aggregation, value) in &expected {
+            assert!((aggregate(aggregation, &values) - value).abs() < EPSILON);
+        }
+    }
+
+    #[test]
+    fn classify_samples() {
+        let classified = samples()
+            .iter()
+            .map(|sample| classify_broker_topic_metric(sample))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            classified,
+            vec![
+                Some((RateMetric::BytesIn, RateAttribute::Count)),
+                Some((RateMetric::BytesIn, RateAttribute::Count)),
+                Some((RateMetric::MessagesIn, RateAttribute::FifteenMinuteRate)),
+                Some((RateMetric::MessagesIn, RateAttribute::Count)),
+                None,
+                None,
+                None,
+            ]
+        );
+        let partition_sizes = samples()
+            .iter()
+            .filter(|sample| is_partition_size_metric(sample))
+            .count();
+        assert_eq!(partition_sizes, 2);
+    }
+
+    #[test]
+    fn follower_sizes() {
+        let mib = 1024f64 * 1024f64;
+        let mut topic_metrics = TopicMetrics::new();
+        for &(broker_id, size_bytes) in &[(1, 100f64 * mib), (2, 95f64 * mib), (3, 50f64 * mib)] {
+            topic_metrics.brokers.insert(
+                broker_id,
+                TopicBrokerMetrics {
+                    partitions: vec![PartitionMetrics::with_size(size_bytes)],
+                    ..TopicBrokerMetrics::default()
+                },
+            );
+        }
+        let partition = Partition {
+            id: 0,
+            leader: 1,
+            replicas: vec![1, 2, 3, 4],
+            isr: vec![1, 2, 3],
+            error: None,
+        };
+
+        let sizes = topic_metrics.follower_sizes(&partition);
+        let divergent = sizes
+            .iter()
+            .filter(|replica| replica.is_divergent(10f64))
+            .map(|replica| replica.broker_id)
+            .collect::<Vec<_>>();
+        assert_eq!(sizes.len(), 3);
+        assert!(sizes[2].size_bytes < 0f64);
+        assert_eq!(divergent, vec![3]);
+        assert_eq!(sizes[1].description(), "3: -50.0% (lagging)");
+    }
+}
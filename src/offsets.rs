@@ -1,24 +1,111 @@
 use byteorder::{BigEndian, ReadBytesExt};
+use chrono::{DateTime, Utc};
 use futures::Stream;
-use rdkafka::config::{ClientConfig, TopicConfig};
+use rdkafka::config::ClientConfig;
 use rdkafka::consumer::stream_consumer::StreamConsumer;
-use rdkafka::consumer::{Consumer, EmptyConsumerContext};
+use rdkafka::consumer::{BaseConsumer, Consumer, DefaultConsumerContext};
 use rdkafka::error::KafkaError;
 use rdkafka::{Message, Offset, TopicPartitionList};
+use scheduled_executor::TaskGroup;
 
 use cache::{Cache, OffsetsCache};
-use config::{ClusterConfig, Config};
+use config::{ClusterConfig, Config, OffsetsSource};
 use error::*;
 use metadata::{ClusterId, TopicName};
 use utils::{insert_at, read_string};
+use zk::ZK_SESSIONS;
 
 use std::cmp;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::io::Cursor;
 use std::str;
+use std::sync::RwLock;
 use std::thread;
 use std::time::{Duration, Instant};
 
+const CONSUMER_OFFSETS_TOPIC: &str = "__consumer_offsets";
+
+lazy_static! {
+    pub static ref OFFSET_CONSUMERS: OffsetConsumerStatusStore = OffsetConsumerStatusStore::new();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OffsetConsumerState {
+    Starting,
+    Running,
+    Restarting,
+}
+
+impl fmt::Display for OffsetConsumerState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            OffsetConsumerState::Starting => "Starting",
+            OffsetConsumerState::Running => "Running",
+            OffsetConsumerState::Restarting => "Restarting",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Health of the offset consumer of a cluster, as reported by its supervisor.
+#[derive(Debug, Clone)]
+pub struct OffsetConsumerStatus {
+    pub state: OffsetConsumerState,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+    /// Last time the consumer wrote its updates to the `OffsetsCache`.
+    pub last_update: Option<DateTime<Utc>>,
+    /// Number of messages the consumer is behind the end of `__consumer_offsets`.
+    pub lag: Option<i64>,
+}
+
+impl OffsetConsumerStatus {
+    fn new() -> OffsetConsumerStatus {
+        OffsetConsumerStatus {
+            state: OffsetConsumerState::Starting,
+            restarts: 0,
+            last_error: None,
+            last_update: None,
+            lag: None,
+        }
+    }
+}
+
+pub struct OffsetConsumerStatusStore {
+    statuses: RwLock<HashMap<ClusterId, OffsetConsumerStatus>>,
+}
+
+impl OffsetConsumerStatusStore {
+    pub fn new() -> OffsetConsumerStatusStore {
+        OffsetConsumerStatusStore {
+            statuses: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn update<F>(&self, cluster_id: &ClusterId, f: F)
+    where
+        F: FnOnce(&mut OffsetConsumerStatus),
+    {
+        match self.statuses.write() {
+            Ok(mut statuses) => f((*statuses)
+                .entry(cluster_id.clone())
+                .or_insert_with(OffsetConsumerStatus::new)),
+            Err(_) => panic!("Poison error while writing offset consumer status"),
+        };
+    }
+
+    pub fn statuses(&self) -> Vec<(ClusterId, OffsetConsumerStatus)> {
+        match self.statuses.read() {
+            Ok(statuses) => (*statuses)
+                .iter()
+                .map(|(cluster_id, status)| (cluster_id.clone(), status.clone()))
+                .collect(),
+            Err(_) => panic!("Poison error while reading offset consumer status"),
+        }
+    }
+}
+
 #[derive(Debug)]
 enum ConsumerUpdate {
     Metadata,
@@ -84,7 +171,7 @@ fn create_consumer(
     brokers: &str,
     group_id: &str,
     start_offsets: Option<Vec<i64>>,
-) -> Result<StreamConsumer<EmptyConsumerContext>> {
+) -> Result<StreamConsumer<DefaultConsumerContext>> {
     let consumer = ClientConfig::new()
         .set("group.id", group_id)
         .set("bootstrap.servers", brokers)
@@ -95,11 +182,7 @@ fn create_consumer(
         //.set("fetch.message.max.bytes", "1024000") // Reduce memory usage
         .set("queued.min.messages", "10000") // Reduce memory usage
         .set("message.max.bytes", "10485760")
-        .set_default_topic_config(
-            TopicConfig::new()
-                .set("auto.offset.reset", "smallest")
-                .finalize(),
-        )
+        .set("auto.offset.reset", "smallest")
         .create::<StreamConsumer<_>>()
         .chain_err(|| format!("Consumer creation failed: {}", brokers))?;
 
@@ -108,7 +191,7 @@ fn create_consumer(
             let mut tp_list = TopicPartitionList::new();
             for (partition, &offset) in pos.iter().enumerate() {
                 tp_list.add_partition_offset(
-                    "__consumer_offsets",
+                    CONSUMER_OFFSETS_TOPIC,
                     partition as i32,
                     Offset::Offset(offset),
                 );
@@ -123,53 +206,77 @@ fn create_consumer(
         }
         None => {
             debug!("No previous offsets found, subscribing to topic");
-            consumer.subscribe(&["__consumer_offsets"]).chain_err(|| {
-                format!("Can't subscribe to offset __consumer_offsets ({})", brokers)
-            })?;
+            consumer
+                .subscribe(&[CONSUMER_OFFSETS_TOPIC])
+                .chain_err(|| {
+                    format!("Can't subscribe to offset __consumer_offsets ({})", brokers)
+                })?;
         }
     }
 
     Ok(consumer)
 }
 
-// we should really have some tests here
+/// Marks a partition whose offset was deleted by a tombstone in `__consumer_offsets`. It only
+/// lives in the local cache of the offset consumer and never reaches the `OffsetsCache`.
+const OFFSET_TOMBSTONE: i64 = -2;
+
+/// Merges a newly consumed offset into the existing one: `-1` means no update was received for
+/// the partition, while a tombstone deletes the stored offset.
+fn merge_offset(existing: i64, new: i64) -> i64 {
+    match new {
+        OFFSET_TOMBSTONE => -1,
+        -1 => existing,
+        _ => cmp::max(existing, new),
+    }
+}
+
 fn update_global_cache(
     cluster_id: &ClusterId,
     local_cache: &HashMap<(String, String), Vec<i64>>,
     cache: &OffsetsCache,
 ) {
     for (&(ref group, ref topic), new_offsets) in local_cache {
-        // Consider a consuming iterator
-        // This logic is not needed if i store the consumer offset, right? wrong!
-        if new_offsets.iter().any(|&offset| offset == -1) {
-            if let Some(mut existing_offsets) =
-                cache.get(&(cluster_id.to_owned(), group.to_owned(), topic.to_owned()))
-            {
-                // If the new offset is not complete and i have an old one, do the merge
-                vec_merge_in_place(&mut existing_offsets, &new_offsets, -1, cmp::max);
-                //                for i in 0..(cmp::max(offsets.len(), existing_offsets.len())) {
-                //                    let new_offset = cmp::max(
-                //                        offsets.get(i).cloned().unwrap_or(-1),
-                //                        existing_offsets.get(i).cloned().unwrap_or(-1));
-                //                    insert_at(&mut existing_offsets, i, new_offset, -1);
-                //                }
-                let _ = cache.insert(
-                    (cluster_id.to_owned(), group.to_owned(), topic.to_owned()),
-                    existing_offsets,
-                );
-                continue;
+        let key = (cluster_id.to_owned(), group.to_owned(), topic.to_owned());
+        if new_offsets.iter().all(|&offset| offset >= 0) {
+            // TODO: log errors
+            let _ = cache.insert(key, new_offsets.clone());
+            continue;
+        }
+        // The new offsets are not complete or contain tombstones, merge with the existing ones
+        let existing_offsets = cache.get(&key);
+        match merge_offsets(existing_offsets.as_ref().map(Vec::as_slice), new_offsets) {
+            Some(offsets) => {
+                let _ = cache.insert(key, offsets);
             }
+            None if existing_offsets.is_some() => {
+                // All the partitions have been deleted
+                if let Err(e) = cache.remove(&key) {
+                    format_error_chain!(e);
+                }
+            }
+            None => {}
         }
-        // TODO: log errors
-        let _ = cache.insert(
-            (cluster_id.to_owned(), group.to_owned(), topic.to_owned()),
-            new_offsets.clone(),
-        );
+    }
+}
+
+/// Merges a partial update into the existing offsets of a group and topic, dropping the trailing
+/// partitions without an offset. Returns `None` if no partition is left.
+fn merge_offsets(existing: Option<&[i64]>, new_offsets: &[i64]) -> Option<Vec<i64>> {
+    let mut offsets = existing.map(<[i64]>::to_vec).unwrap_or_default();
+    vec_merge_in_place(&mut offsets, new_offsets, -1, merge_offset);
+    while offsets.last() == Some(&-1) {
+        offsets.pop();
+    }
+    if offsets.is_empty() {
+        None
+    } else {
+        Some(offsets)
     }
 }
 
 fn commit_offset_position_to_array(tp_list: TopicPartitionList) -> Vec<i64> {
-    let tp_elements = tp_list.elements_for_topic("__consumer_offsets");
+    let tp_elements = tp_list.elements_for_topic(CONSUMER_OFFSETS_TOPIC);
     let mut offsets = vec![0; tp_elements.len()];
     for tp in &tp_elements {
         offsets[tp.partition() as usize] = tp.offset().to_raw();
@@ -177,15 +284,84 @@ fn commit_offset_position_to_array(tp_list: TopicPartitionList) -> Vec<i64> {
     offsets
 }
 
+/// Returns the given logical offset (beginning or end) of each partition of `__consumer_offsets`,
+/// looked up with a single request per broker.
+fn consumer_offsets_watermarks(
+    consumer: &StreamConsumer<DefaultConsumerContext>,
+    partition_count: usize,
+    offset: Offset,
+) -> Result<Vec<i64>> {
+    let mut tp_list = TopicPartitionList::new();
+    for partition in 0..partition_count {
+        tp_list.add_partition_offset(CONSUMER_OFFSETS_TOPIC, partition as i32, offset);
+    }
+    let offsets = consumer
+        .offsets_for_times(tp_list, Duration::from_secs(10))
+        .chain_err(|| "Failed to fetch watermarks")?;
+    let mut watermarks = vec![0; partition_count];
+    for element in offsets.elements() {
+        let partition = element.partition() as usize;
+        match (element.error(), element.offset()) {
+            (Ok(()), Offset::Offset(watermark)) if partition < partition_count => {
+                watermarks[partition] = watermark
+            }
+            (Err(e), _) => bail!(
+                "Failed to fetch watermarks for partition {}: {}",
+                partition,
+                e
+            ),
+            (Ok(()), offset) => bail!(
+                "Unexpected watermark for partition {}: {:?}",
+                partition,
+                offset
+            ),
+        }
+    }
+    Ok(watermarks)
+}
+
+/// Returns the number of messages between the given position and the end of each partition of
+/// `__consumer_offsets`.
+fn consumer_lag(
+    consumer: &StreamConsumer<DefaultConsumerContext>,
+    partition_count: usize,
+    position: &[i64],
+) -> Result<i64> {
+    let low = consumer_offsets_watermarks(consumer, partition_count, Offset::Beginning)?;
+    let high = consumer_offsets_watermarks(consumer, partition_count, Offset::End)?;
+    let lag = (0..partition_count)
+        .map(|partition| {
+            let current = position.get(partition).cloned().unwrap_or(-1);
+            high[partition] - cmp::max(current, low[partition])
+        })
+        .sum();
+    Ok(lag)
+}
+
+// After this many consecutive failures to compute the lag, the consumer is considered stuck.
+const MAX_LAG_FAILURES: u32 = 6;
+
 fn consume_offset_topic(
     cluster_id: ClusterId,
-    consumer: StreamConsumer<EmptyConsumerContext>,
+    consumer: StreamConsumer<DefaultConsumerContext>,
     cache: &Cache,
 ) -> Result<()> {
     let mut local_cache = HashMap::new();
     let mut last_dump = Instant::now();
+    let mut lag_failures = 0;
+
+    let partition_count = consumer
+        .fetch_metadata(Some(CONSUMER_OFFSETS_TOPIC), Duration::from_secs(30))
+        .chain_err(|| format!("Failed to fetch {} metadata", CONSUMER_OFFSETS_TOPIC))?
+        .topics()
+        .get(0)
+        .map(|topic| topic.partitions().len())
+        .unwrap_or(0);
 
     debug!("Starting offset consumer loop for {:?}", cluster_id);
+    OFFSET_CONSUMERS.update(&cluster_id, |status| {
+        status.state = OffsetConsumerState::Running;
+    });
 
     for message in consumer.start_with(Duration::from_millis(200), true).wait() {
         match message {
@@ -204,7 +380,17 @@ fn consume_offset_topic(
                             .or_insert_with(Vec::new);
                         insert_at(&mut offsets, partition as usize, offset, -1);
                     }
-                    Ok(_) => {}
+                    Ok(ConsumerUpdate::OffsetTombstone {
+                        group,
+                        topic,
+                        partition,
+                    }) => {
+                        let mut offsets = local_cache
+                            .entry((group.to_owned(), topic.to_owned()))
+                            .or_insert_with(Vec::new);
+                        insert_at(&mut offsets, partition as usize, OFFSET_TOMBSTONE, -1);
+                    }
+                    Ok(ConsumerUpdate::Metadata) => {}
                     Err(e) => format_error_chain!(e),
                 };
             }
@@ -222,29 +408,44 @@ fn consume_offset_topic(
             update_global_cache(&cluster_id, &local_cache, &cache.offsets);
             // Consumer position is not up to date after start, so we have to merge with the
             // existing offsets and take the largest.
-            let res = consumer
+            let current_position = consumer
                 .position()
-                .map(|current_position| {
-                    let previous_position_vec = cache
-                        .internal_offsets
-                        .get(&cluster_id)
-                        .unwrap_or_else(Vec::new);
-                    let mut current_position_vec =
-                        commit_offset_position_to_array(current_position);
-                    vec_merge_in_place(
-                        &mut current_position_vec,
-                        &previous_position_vec,
-                        Offset::Invalid.to_raw(),
-                        cmp::max,
-                    );
-                    cache
-                        .internal_offsets
-                        .insert(cluster_id.clone(), current_position_vec)
-                })
                 .chain_err(|| "Failed to store consumer offset position")?;
-            if let Err(e) = res {
+            let previous_position_vec = cache
+                .internal_offsets
+                .get(&cluster_id)
+                .unwrap_or_else(Vec::new);
+            let mut current_position_vec = commit_offset_position_to_array(current_position);
+            vec_merge_in_place(
+                &mut current_position_vec,
+                &previous_position_vec,
+                Offset::Invalid.to_raw(),
+                cmp::max,
+            );
+            let lag = match consumer_lag(&consumer, partition_count, &current_position_vec) {
+                Ok(lag) => {
+                    lag_failures = 0;
+                    Some(lag)
+                }
+                Err(e) => {
+                    lag_failures += 1;
+                    if lag_failures >= MAX_LAG_FAILURES {
+                        return Err(e).chain_err(|| "Offset consumer is not making progress");
+                    }
+                    format_error_chain!(e);
+                    None
+                }
+            };
+            if let Err(e) = cache
+                .internal_offsets
+                .insert(cluster_id.clone(), current_position_vec)
+            {
                 format_error_chain!(e);
             }
+            OFFSET_CONSUMERS.update(&cluster_id, |status| {
+                status.last_update = Some(Utc::now());
+                status.lag = lag;
+            });
             local_cache = HashMap::with_capacity(local_cache.len());
             last_dump = Instant::now();
         }
@@ -277,74 +478,585 @@ where
 //        .collect::<Vec<T>>()
 //}
 
+fn start_offset_consumer(
+    cluster_id: ClusterId,
+    brokers: &str,
+    group_id: &str,
+    cache: &Cache,
+) -> Result<()> {
+    let start_position = cache.internal_offsets.get(&cluster_id);
+    let consumer = create_consumer(brokers, group_id, start_position)
+        .chain_err(|| format!("Failed to create offset consumer for {}", cluster_id))?;
+    consume_offset_topic(cluster_id, consumer, cache)
+}
+
+const MIN_RESTART_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(300);
+// A consumer that ran for longer than this is restarted with the minimum backoff.
+const BACKOFF_RESET_AFTER: Duration = Duration::from_secs(600);
+
+/// Runs the offset consumer of a cluster in a child thread, restarting it with exponential
+/// backoff every time it fails or panics.
+fn supervise_offset_consumer(
+    cluster_id: ClusterId,
+    brokers: String,
+    group_id: String,
+    cache: Cache,
+) {
+    let mut backoff = MIN_RESTART_BACKOFF;
+    loop {
+        let start_time = Instant::now();
+        let cluster_id_clone = cluster_id.clone();
+        let brokers_clone = brokers.clone();
+        let group_id_clone = group_id.clone();
+        let cache_alias = cache.alias();
+        let result = thread::Builder::new()
+            .name("offset-consumer".to_owned())
+            .spawn(move || {
+                start_offset_consumer(
+                    cluster_id_clone,
+                    &brokers_clone,
+                    &group_id_clone,
+                    &cache_alias,
+                )
+            });
+        let error = match result.map(thread::JoinHandle::join) {
+            Ok(Ok(Ok(()))) => "Offset consumer terminated".to_owned(),
+            Ok(Ok(Err(e))) => {
+                format_error_chain!(e);
+                e.iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(": ")
+            }
+            Ok(Err(_)) => "Offset consumer panicked".to_owned(),
+            Err(e) => format!("Failed to start offset consumer thread: {}", e),
+        };
+
+        if start_time.elapsed() > BACKOFF_RESET_AFTER {
+            backoff = MIN_RESTART_BACKOFF;
+        }
+        warn!(
+            "Offset consumer for {} stopped ({}), restarting in {:?}",
+            cluster_id, error, backoff
+        );
+        OFFSET_CONSUMERS.update(&cluster_id, |status| {
+            status.state = OffsetConsumerState::Restarting;
+            status.restarts += 1;
+            status.last_error = Some(error);
+            status.lag = None;
+        });
+        thread::sleep(backoff);
+        backoff = cmp::min(backoff * 2, MAX_RESTART_BACKOFF);
+        OFFSET_CONSUMERS.update(&cluster_id, |status| {
+            status.state = OffsetConsumerState::Starting;
+        });
+    }
+}
+
 pub fn run_offset_consumer(
     cluster_id: &ClusterId,
     cluster_config: &ClusterConfig,
     config: &Config,
     cache: &Cache,
 ) -> Result<()> {
-    let start_position = cache.internal_offsets.get(cluster_id);
-    let consumer = create_consumer(
-        &cluster_config.bootstrap_servers(),
-        &config.consumer_offsets_group_id,
-        start_position,
-    )
-    .chain_err(|| format!("Failed to create offset consumer for {}", cluster_id))?;
+    OFFSET_CONSUMERS.update(cluster_id, |status| {
+        status.state = OffsetConsumerState::Starting;
+    });
 
     let cluster_id_clone = cluster_id.clone();
+    let brokers = cluster_config.bootstrap_servers();
+    let group_id = config.consumer_offsets_group_id.clone();
     let cache_alias = cache.alias();
     let _ = thread::Builder::new()
-        .name("offset-consumer".to_owned())
-        .spawn(move || {
-            if let Err(e) = consume_offset_topic(cluster_id_clone, consumer, &cache_alias) {
-                format_error_chain!(e);
-            }
-        })
-        .chain_err(|| "Failed to start offset consumer thread")?;
+        .name("offset-supervisor".to_owned())
+        .spawn(move || supervise_offset_consumer(cluster_id_clone, brokers, group_id, cache_alias))
+        .chain_err(|| "Failed to start offset consumer supervisor thread")?;
 
     Ok(())
 }
 
-pub trait OffsetStore {
-    fn offsets_by_cluster(
+//
+// ********** OFFSETS BY TIMESTAMP **********
+//
+
+/// Returns the first offset of the partition whose message timestamp is greater or equal to the
+/// given one (in milliseconds since epoch), or the high watermark if there is none. The offset
+/// is looked up in the time index of the broker, and the lookup fails if it times out.
+pub fn offset_for_timestamp(
+    consumer: &BaseConsumer<DefaultConsumerContext>,
+    topic: &str,
+    partition: i32,
+    timestamp: i64,
+) -> Result<i64> {
+    let mut tp_list = TopicPartitionList::new();
+    tp_list.add_partition_offset(topic, partition, Offset::Offset(timestamp));
+    let offsets = consumer
+        .offsets_for_times(tp_list, Duration::from_secs(10))
+        .chain_err(|| {
+            format!(
+                "Failed to look up offset by time for {}/{}",
+                topic, partition
+            )
+        })?;
+    let element = match offsets.find_partition(topic, partition) {
+        Some(element) => element,
+        None => bail!("Missing offset by time for {}/{}", topic, partition),
+    };
+    element.error().chain_err(|| {
+        format!(
+            "Failed to look up offset by time for {}/{}",
+            topic, partition
+        )
+    })?;
+    match element.offset() {
+        Offset::Offset(offset) => Ok(offset),
+        // No message is that recent
+        Offset::End => {
+            let (_, high) = consumer
+                .fetch_watermarks(topic, partition, Duration::from_secs(10))
+                .chain_err(|| format!("Failed to fetch watermarks for {}/{}", topic, partition))?;
+            Ok(high)
+        }
+        offset => bail!(
+            "Unexpected offset by time for {}/{}: {:?}",
+            topic,
+            partition,
+            offset
+        ),
+    }
+}
+
+//
+// ********** OFFSET FETCH **********
+//
+
+/// Fetches the committed offsets of a group for all the partitions of the given topics, using
+/// an OffsetFetch request. Partitions without a committed offset are set to `-1`.
+fn fetch_group_offsets(
+    brokers: &str,
+    group: &str,
+    topics: &[(TopicName, usize)],
+) -> Result<HashMap<TopicName, Vec<i64>>> {
+    let consumer = ClientConfig::new()
+        .set("bootstrap.servers", brokers)
+        .set("group.id", group)
+        .set("enable.auto.commit", "false")
+        .set("api.version.request", "true")
+        .create::<BaseConsumer<DefaultConsumerContext>>()
+        .chain_err(|| "Consumer creation failed")?;
+
+    let mut tp_list = TopicPartitionList::new();
+    for &(ref topic, partition_count) in topics {
+        for partition in 0..partition_count {
+            tp_list.add_partition(topic, partition as i32);
+        }
+    }
+    // Assigning partitions doesn't join the group, it just tells the consumer which
+    // partitions to fetch the committed offsets for.
+    consumer
+        .assign(&tp_list)
+        .chain_err(|| "Failure during consumer assignment")?;
+    let committed = consumer
+        .committed(Duration::from_secs(30))
+        .chain_err(|| "Failed to fetch committed offsets")?;
+
+    let mut offsets = HashMap::new();
+    for tp in committed.elements() {
+        if let Offset::Offset(offset) = tp.offset() {
+            let topic_offsets = offsets
+                .entry(tp.topic().to_owned())
+                .or_insert_with(Vec::new);
+            insert_at(topic_offsets, tp.partition() as usize, offset, -1);
+        }
+    }
+    Ok(offsets)
+}
+
+/// Minimum delay between the offset fetches of two groups of the same cluster.
+const GROUP_FETCH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Periodically fetches the committed offsets of every known group, for the clusters that use
+/// `OffsetsSource::OffsetFetch`.
+pub struct OffsetFetchTaskGroup {
+    cache: Cache,
+    config: Config,
+}
+
+impl OffsetFetchTaskGroup {
+    pub fn new(cache: &Cache, config: &Config) -> OffsetFetchTaskGroup {
+        OffsetFetchTaskGroup {
+            cache: cache.alias(),
+            config: config.clone(),
+        }
+    }
+
+    fn fetch_offsets(&self, cluster_id: &ClusterId, cluster_config: &ClusterConfig) -> Result<()> {
+        let brokers = cluster_config.bootstrap_servers();
+        let topics = self
+            .cache
+            .topics
+            .filter_clone(|&(ref c, _)| c == cluster_id)
+            .into_iter()
+            .map(|((_, topic), partitions)| (topic, partitions.len()))
+            .collect::<Vec<_>>();
+        let groups = self
+            .cache
+            .groups
+            .filter_clone_k(|&(ref c, _)| c == cluster_id);
+
+        let mut failures = 0;
+        for (index, (_, group)) in groups.iter().enumerate() {
+            if index > 0 {
+                // Every group needs its own client: limit the rate at which they are created
+                thread::sleep(GROUP_FETCH_INTERVAL);
+            }
+            if let Err(e) = self.update_group_offsets(cluster_id, &brokers, group, &topics) {
+                format_error_chain!(e);
+                failures += 1;
+            }
+        }
+        if failures > 0 {
+            bail!(
+                "Failed to fetch offsets for {} of {} groups",
+                failures,
+                groups.len()
+            );
+        }
+        Ok(())
+    }
+
+    fn update_group_offsets(
         &self,
         cluster_id: &ClusterId,
-    ) -> Vec<((ClusterId, String, TopicName), Vec<i64>)>;
+        brokers: &str,
+        group: &str,
+        topics: &[(TopicName, usize)],
+    ) -> Result<()> {
+        let offsets = fetch_group_offsets(brokers, group, topics)
+            .chain_err(|| format!("Failed to fetch offsets for group {}", group))?;
+        // The fetched offsets are authoritative: drop the topics the group doesn't
+        // have offsets for anymore.
+        let stale_keys = self.cache.offsets.filter_clone_k(|&(ref c, ref g, ref t)| {
+            c == cluster_id && g == group && !offsets.contains_key(t)
+        });
+        for key in stale_keys {
+            self.cache
+                .offsets
+                .remove(&key)
+                .chain_err(|| "Failed to remove offsets from cache")?;
+        }
+        for (topic, topic_offsets) in offsets {
+            self.cache
+                .offsets
+                .insert((cluster_id.clone(), group.to_owned(), topic), topic_offsets)
+                .chain_err(|| "Failed to insert offsets in cache")?;
+        }
+        Ok(())
+    }
+}
+
+impl TaskGroup for OffsetFetchTaskGroup {
+    type TaskId = ClusterId;
+
+    fn get_tasks(&self) -> Vec<ClusterId> {
+        self.config
+            .clusters
+            .iter()
+            .filter(|&(_, cluster_config)| {
+                cluster_config.offsets_source == OffsetsSource::OffsetFetch
+            })
+            .map(|(cluster_id, _)| cluster_id.clone())
+            .collect()
+    }
+
+    fn execute(&self, cluster_id: ClusterId) {
+        let cluster_config = self.config.cluster(&cluster_id).unwrap();
+        match self.fetch_offsets(&cluster_id, cluster_config) {
+            Ok(()) => OFFSET_CONSUMERS.update(&cluster_id, |status| {
+                status.state = OffsetConsumerState::Running;
+                status.last_update = Some(Utc::now());
+                status.last_error = None;
+            }),
+            Err(e) => {
+                format_error_chain!(e);
+                let error = e.to_string();
+                OFFSET_CONSUMERS.update(&cluster_id, |status| {
+                    status.last_error = Some(error);
+                });
+            }
+        }
+    }
+}
+
+//
+// ********** ZOOKEEPER OFFSETS **********
+//
+
+/// Periodically reads the offsets committed to ZooKeeper by legacy consumers, for the clusters
+/// with `zk_consumer_offsets` enabled. The offsets are stored in `Cache::zk_offsets`.
+pub struct ZkOffsetsFetchTaskGroup {
+    cache: Cache,
+    config: Config,
+}
+
+impl ZkOffsetsFetchTaskGroup {
+    pub fn new(cache: &Cache, config: &Config) -> ZkOffsetsFetchTaskGroup {
+        ZkOffsetsFetchTaskGroup {
+            cache: cache.alias(),
+            config: config.clone(),
+        }
+    }
+
+    fn fetch_offsets(&self, cluster_id: &ClusterId, cluster_config: &ClusterConfig) -> Result<()> {
+        let zk = ZK_SESSIONS.get_or_init(cluster_id, cluster_config, &self.cache)?;
+        let offsets = zk
+            .consumer_offsets(|topic| {
+                self.cache
+                    .topics
+                    .get(&(cluster_id.clone(), topic.to_owned()))
+                    .map(|partitions| partitions.len())
+            })
+            .chain_err(|| "Failed to read consumer offsets from ZooKeeper")?;
+
+        let mut keys = HashSet::with_capacity(offsets.len());
+        for (group, topic, topic_offsets) in offsets {
+            let key = (cluster_id.clone(), group, topic);
+            self.cache
+                .zk_offsets
+                .insert(key.clone(), topic_offsets)
+                .chain_err(|| "Failed to insert offsets in cache")?;
+            keys.insert(key);
+        }
+        // Remove the offsets that have been deleted from ZooKeeper
+        let stale_keys = self
+            .cache
+            .zk_offsets
+            .filter_clone_k(|key| key.0 == *cluster_id && !keys.contains(key));
+        for key in stale_keys {
+            self.cache
+                .zk_offsets
+                .remove(&key)
+                .chain_err(|| "Failed to remove offsets from cache")?;
+        }
+        Ok(())
+    }
+}
+
+impl TaskGroup for ZkOffsetsFetchTaskGroup {
+    type TaskId = ClusterId;
+
+    fn get_tasks(&self) -> Vec<ClusterId> {
+        self.config
+            .clusters
+            .iter()
+            .filter(|&(_, cluster_config)| {
+                cluster_config.zk_consumer_offsets && cluster_config.zookeeper.is_some()
+            })
+            .map(|(cluster_id, _)| cluster_id.clone())
+            .collect()
+    }
+
+    fn execute(&self, cluster_id: ClusterId) {
+        let cluster_config = self.config.cluster(&cluster_id).unwrap();
+        if let Err(e) = self.fetch_offsets(&cluster_id, cluster_config) {
+            format_error_chain!(e);
+        }
+    }
+}
+
+//
+// ********** OFFSET STORE **********
+//
+
+/// Where a consumer group commits its offsets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OffsetStorage {
+    Kafka,
+    Zookeeper,
+}
+
+impl fmt::Display for OffsetStorage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            OffsetStorage::Kafka => "Kafka",
+            OffsetStorage::Zookeeper => "ZooKeeper",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+pub type StoredOffsets = (OffsetStorage, (ClusterId, String, TopicName), Vec<i64>);
+
+pub trait OffsetStore {
+    fn offsets_by_cluster(&self, cluster_id: &ClusterId) -> Vec<StoredOffsets>;
     fn offsets_by_cluster_topic(
         &self,
         cluster_id: &ClusterId,
         topic_name: &str,
-    ) -> Vec<((ClusterId, String, TopicName), Vec<i64>)>;
+    ) -> Vec<StoredOffsets>;
     fn offsets_by_cluster_group(
         &self,
         cluster_id: &ClusterId,
         group_name: &str,
-    ) -> Vec<((ClusterId, String, TopicName), Vec<i64>)>;
+    ) -> Vec<StoredOffsets>;
+}
+
+fn filter_stored_offsets<F>(cache: &Cache, f: F) -> Vec<StoredOffsets>
+where
+    F: Fn(&(ClusterId, String, TopicName)) -> bool,
+{
+    let kafka_offsets = cache
+        .offsets
+        .filter_clone(&f)
+        .into_iter()
+        .map(|(key, offsets)| (OffsetStorage::Kafka, key, offsets));
+    let zk_offsets = cache
+        .zk_offsets
+        .filter_clone(&f)
+        .into_iter()
+        .map(|(key, offsets)| (OffsetStorage::Zookeeper, key, offsets));
+    kafka_offsets.chain(zk_offsets).collect()
 }
 
 impl OffsetStore for Cache {
-    fn offsets_by_cluster(
-        &self,
-        cluster: &ClusterId,
-    ) -> Vec<((ClusterId, String, TopicName), Vec<i64>)> {
-        self.offsets.filter_clone(|&(ref c, _, _)| c == cluster)
+    fn offsets_by_cluster(&self, cluster: &ClusterId) -> Vec<StoredOffsets> {
+        filter_stored_offsets(self, |&(ref c, _, _)| c == cluster)
     }
 
-    fn offsets_by_cluster_topic(
-        &self,
-        cluster: &ClusterId,
-        topic: &str,
-    ) -> Vec<((ClusterId, String, TopicName), Vec<i64>)> {
-        self.offsets
-            .filter_clone(|&(ref c, _, ref t)| c == cluster && t == topic)
+    fn offsets_by_cluster_topic(&self, cluster: &ClusterId, topic: &str) -> Vec<StoredOffsets> {
+        filter_stored_offsets(self, |&(ref c, _, ref t)| c == cluster && t == topic)
     }
 
-    fn offsets_by_cluster_group(
-        &self,
-        cluster: &ClusterId,
-        group: &str,
-    ) -> Vec<((ClusterId, String, TopicName), Vec<i64>)> {
-        self.offsets
-            .filter_clone(|&(ref c, ref g, _)| c == cluster && g == group)
+    fn offsets_by_cluster_group(&self, cluster: &ClusterId, group: &str) -> Vec<StoredOffsets> {
+        filter_stored_offsets(self, |&(ref c, ref g, _)| c == cluster && g == group)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cache::{ReplicaWriter, ReplicatedMap};
+    use std::sync::Arc;
+
+    /// The writer doesn't connect to the broker until the first update is delivered, which only
+    /// happens in the background.
+    fn offsets_cache() -> OffsetsCache {
+        let writer = ReplicaWriter::new("localhost:9", "kafka-view-test").unwrap();
+        ReplicatedMap::new("offsets", Arc::new(writer))
+    }
+
+    fn group_offsets(group: &str, offsets: Vec<i64>) -> HashMap<(String, String), Vec<i64>> {
+        let mut local_cache = HashMap::new();
+        local_cache.insert((group.to_owned(), "topic".to_owned()), offsets);
+        local_cache
+    }
+
+    #[test]
+    fn test_merge_in_place_extends_and_merges() {
+        let mut vec1 = vec![1, -1, 5];
+        vec_merge_in_place(&mut vec1, &[3, 2, -1, 7], -1, cmp::max);
+        assert_eq!(vec1, vec![3, 2, 5, 7]);
+    }
+
+    #[test]
+    fn test_merge_in_place_applies_tombstones() {
+        let mut vec1 = vec![10, 20, 30];
+        vec_merge_in_place(&mut vec1, &[-1, OFFSET_TOMBSTONE, 35], -1, merge_offset);
+        assert_eq!(vec1, vec![10, -1, 35]);
+    }
+
+    #[test]
+    fn test_merge_offset_keeps_tombstones_and_missing_updates_apart() {
+        assert_eq!(merge_offset(10, 12), 12);
+        assert_eq!(merge_offset(10, 8), 10);
+        assert_eq!(merge_offset(10, -1), 10);
+        assert_eq!(merge_offset(10, OFFSET_TOMBSTONE), -1);
+        assert_eq!(merge_offset(-1, OFFSET_TOMBSTONE), -1);
+    }
+
+    #[test]
+    fn test_partial_update_is_merged() {
+        assert_eq!(
+            merge_offsets(Some(&[10, 20, 30]), &[-1, 25]),
+            Some(vec![10, 25, 30])
+        );
+    }
+
+    #[test]
+    fn test_partial_tombstone_removes_partition() {
+        assert_eq!(
+            merge_offsets(Some(&[10, 20, 30]), &[-1, OFFSET_TOMBSTONE]),
+            Some(vec![10, -1, 30])
+        );
+    }
+
+    #[test]
+    fn test_trailing_tombstones_are_trimmed() {
+        assert_eq!(
+            merge_offsets(
+                Some(&[10, 20, 30]),
+                &[-1, OFFSET_TOMBSTONE, OFFSET_TOMBSTONE]
+            ),
+            Some(vec![10])
+        );
+    }
+
+    #[test]
+    fn test_full_tombstone_removes_entry() {
+        assert_eq!(
+            merge_offsets(Some(&[10, 20]), &[OFFSET_TOMBSTONE, OFFSET_TOMBSTONE]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_tombstone_for_missing_entry_is_ignored() {
+        assert_eq!(merge_offsets(None, &[OFFSET_TOMBSTONE]), None);
+        assert_eq!(
+            merge_offsets(None, &[OFFSET_TOMBSTONE, 4]),
+            Some(vec![-1, 4])
+        );
+    }
+
+    #[test]
+    fn test_update_global_cache_replaces_complete_offsets() {
+        let cluster_id = ClusterId::from("cluster");
+        let cache = offsets_cache();
+        let key = (cluster_id.clone(), "group".to_owned(), "topic".to_owned());
+        cache.insert(key.clone(), vec![10, 20, 30]).unwrap();
+
+        update_global_cache(&cluster_id, &group_offsets("group", vec![5, 25]), &cache);
+        assert_eq!(cache.get(&key), Some(vec![5, 25]));
+    }
+
+    #[test]
+    fn test_update_global_cache_merges_partial_offsets() {
+        let cluster_id = ClusterId::from("cluster");
+        let cache = offsets_cache();
+        let key = (cluster_id.clone(), "group".to_owned(), "topic".to_owned());
+        cache.insert(key.clone(), vec![10, 20, 30]).unwrap();
+
+        let update = vec![-1, OFFSET_TOMBSTONE, 35];
+        update_global_cache(&cluster_id, &group_offsets("group", update), &cache);
+        assert_eq!(cache.get(&key), Some(vec![10, -1, 35]));
+    }
+
+    #[test]
+    fn test_update_global_cache_removes_deleted_offsets() {
+        let cluster_id = ClusterId::from("cluster");
+        let cache = offsets_cache();
+        let key = (cluster_id.clone(), "group".to_owned(), "topic".to_owned());
+        cache.insert(key.clone(), vec![10, 20]).unwrap();
+
+        let update = vec![OFFSET_TOMBSTONE, OFFSET_TOMBSTONE];
+        update_global_cache(&cluster_id, &group_offsets("group", update), &cache);
+        assert_eq!(cache.get(&key), None);
+        assert!(cache.keys().is_empty());
+
+        // Tombstones of unknown groups don't create entries.
+        let update = vec![OFFSET_TOMBSTONE];
+        update_global_cache(&cluster_id, &group_offsets("other", update), &cache);
+        assert!(cache.keys().is_empty());
     }
 }
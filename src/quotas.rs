@@ -0,0 +1,210 @@
+use scheduled_executor::TaskGroup;
+
+use cache::Cache;
+use config::Config;
+use error::*;
+use metadata::ClusterId;
+use utils::format_bytes;
+use zk::ZK_SESSIONS;
+
+use std::collections::HashMap;
+
+/// Entity name of the quotas applying to every user or client id without a specific quota.
+pub const DEFAULT_ENTITY: &str = "<default>";
+
+/// A quota override, as configured in `/config/users` or `/config/clients`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Quota {
+    /// The user the quota applies to, `<default>` for all users, or none for client id quotas.
+    pub user: Option<String>,
+    pub client_id: Option<String>,
+    pub producer_byte_rate: Option<f64>,
+    pub consumer_byte_rate: Option<f64>,
+    pub request_percentage: Option<f64>,
+}
+
+impl Quota {
+    /// Builds the quota from the dynamic config of the entity, ignoring any key that is not a
+    /// quota, such as the SCRAM credentials of users.
+    pub fn from_config(
+        user: Option<String>,
+        client_id: Option<String>,
+        config: &HashMap<String, String>,
+    ) -> Option<Quota> {
+        let value = |key: &str| config.get(key).and_then(|value| value.parse::<f64>().ok());
+        let quota = Quota {
+            user,
+            client_id,
+            producer_byte_rate: value("producer_byte_rate"),
+            consumer_byte_rate: value("consumer_byte_rate"),
+            request_percentage: value("request_percentage"),
+        };
+        if quota.producer_byte_rate.is_some()
+            || quota.consumer_byte_rate.is_some()
+            || quota.request_percentage.is_some()
+        {
+            Some(quota)
+        } else {
+            None
+        }
+    }
+
+    pub fn description(&self) -> String {
+        let mut limits = Vec::new();
+        if let Some(rate) = self.producer_byte_rate {
+            limits.push(format!("produce {}/s", format_bytes(rate)));
+        }
+        if let Some(rate) = self.consumer_byte_rate {
+            limits.push(format!("fetch {}/s", format_bytes(rate)));
+        }
+        if let Some(percentage) = self.request_percentage {
+            limits.push(format!("request {:.0}%", percentage));
+        }
+        if limits.is_empty() {
+            "None".to_owned()
+        } else {
+            limits.join(", ")
+        }
+    }
+}
+
+/// The client id quota applying to a client: for each quota type, the quota of the client id
+/// is used if set, otherwise the default client id quota. Users are not known from the group
+/// membership, and user quotas take precedence over client id quotas: if any user quota is
+/// configured, the quota applying to the client is unknown.
+pub fn client_quota(quotas: &[Quota], client_id: &str) -> Option<Quota> {
+    if quotas.iter().any(|q| q.user.is_some()) {
+        return None;
+    }
+    let candidates = [client_id, DEFAULT_ENTITY]
+        .iter()
+        .filter_map(|&name| {
+            quotas
+                .iter()
+                .find(|q| q.client_id.as_ref().map(String::as_str) == Some(name))
+        })
+        .collect::<Vec<_>>();
+    Some(Quota {
+        user: None,
+        client_id: Some(client_id.to_owned()),
+        producer_byte_rate: candidates
+            .iter()
+            .filter_map(|q| q.producer_byte_rate)
+            .next(),
+        consumer_byte_rate: candidates
+            .iter()
+            .filter_map(|q| q.consumer_byte_rate)
+            .next(),
+        request_percentage: candidates
+            .iter()
+            .filter_map(|q| q.request_percentage)
+            .next(),
+    })
+}
+
+/// Periodically reads the quotas of each cluster from ZooKeeper, and stores them in
+/// `Cache::quotas`.
+pub struct QuotaFetchTaskGroup {
+    cache: Cache,
+    config: Config,
+}
+
+impl QuotaFetchTaskGroup {
+    pub fn new(cache: &Cache, config: &Config) -> QuotaFetchTaskGroup {
+        QuotaFetchTaskGroup {
+            cache: cache.alias(),
+            config: config.clone(),
+        }
+    }
+
+    fn fetch_quotas(&self, cluster_id: &ClusterId) -> Result<()> {
+        let cluster_config = self.config.cluster(cluster_id).unwrap();
+        let zk = ZK_SESSIONS.get_or_init(cluster_id, cluster_config, &self.cache)?;
+        let quotas = zk
+            .quotas()
+            .chain_err(|| "Failed to read quotas from ZooKeeper")?;
+        self.cache
+            .quotas
+            .insert(cluster_id.clone(), quotas)
+            .chain_err(|| "Failed to insert quotas in cache")
+    }
+}
+
+impl TaskGroup for QuotaFetchTaskGroup {
+    type TaskId = ClusterId;
+
+    fn get_tasks(&self) -> Vec<ClusterId> {
+        self.config.zookeeper_clusters()
+    }
+
+    fn execute(&self, cluster_id: ClusterId) {
+        if let Err(e) = self.fetch_quotas(&cluster_id) {
+            format_error_chain!(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|&(key, value)| (key.to_owned(), value.to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn test_quota_from_config() {
+        let credentials = config(&[("SCRAM-SHA-256", "salt=...")]);
+        assert_eq!(
+            Quota::from_config(Some("alice".to_owned()), None, &credentials),
+            None
+        );
+
+        let quota = Quota::from_config(
+            None,
+            Some("app".to_owned()),
+            &config(&[("producer_byte_rate", "1048576")]),
+        )
+        .unwrap();
+        assert_eq!(quota.producer_byte_rate, Some(1_048_576f64));
+        assert_eq!(quota.description(), "produce 1.0 MiB/s");
+    }
+
+    #[test]
+    fn test_client_quota() {
+        let quotas = vec![
+            Quota::from_config(
+                None,
+                Some("app".to_owned()),
+                &config(&[("producer_byte_rate", "1024")]),
+            )
+            .unwrap(),
+            Quota::from_config(
+                None,
+                Some(DEFAULT_ENTITY.to_owned()),
+                &config(&[("producer_byte_rate", "10"), ("consumer_byte_rate", "2048")]),
+            )
+            .unwrap(),
+        ];
+
+        let quota = client_quota(&quotas, "app").unwrap();
+        assert_eq!(quota.producer_byte_rate, Some(1024f64));
+        assert_eq!(quota.consumer_byte_rate, Some(2048f64));
+        assert_eq!(
+            client_quota(&quotas, "other").unwrap().producer_byte_rate,
+            Some(10f64)
+        );
+        assert_eq!(client_quota(&[], "app").unwrap().description(), "None");
+
+        let mut with_users = quotas;
+        with_users.extend(Quota::from_config(
+            Some(DEFAULT_ENTITY.to_owned()),
+            None,
+            &config(&[("consumer_byte_rate", "4096")]),
+        ));
+        assert_eq!(client_quota(&with_users, "app"), None);
+    }
+}
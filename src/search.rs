@@ -0,0 +1,684 @@
+use rand::random;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{BaseConsumer, Consumer, DefaultConsumerContext};
+use rdkafka::error::KafkaError;
+use rdkafka::{Message, Offset, TopicPartitionList};
+use regex::Regex;
+use rocket::http::RawStr;
+use rocket::request::Form;
+use rocket::State;
+use scheduled_executor::ThreadPoolExecutor;
+use serde_json::{self, Value};
+
+use cache::Cache;
+use config::{ClusterConfig, Config};
+use error::*;
+use metadata::ClusterId;
+use offsets::offset_for_timestamp;
+use web_server::server::SameOrigin;
+
+use std::cmp;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Number of partitions scanned in parallel by each job.
+const SEARCH_THREADS: usize = 4;
+/// The job stops once it found this many matches.
+const MAX_MATCHES: usize = 1000;
+/// Payloads longer than this are truncated in the matches.
+const MAX_PAYLOAD_LENGTH: usize = 1024;
+/// Jobs that are not polled for this long are cancelled and removed.
+const JOB_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+//
+// ********** MATCHERS **********
+//
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathSegment {
+    Field(String),
+    Index(usize),
+}
+
+/// Parses a JSON path such as `$.user.addresses[0].city`. Only fields and array indexes are
+/// supported.
+fn parse_json_path(path: &str) -> Result<Vec<PathSegment>> {
+    if !path.starts_with('$') {
+        bail!("JSON paths should start with '$': {}", path);
+    }
+    let mut segments = Vec::new();
+    let mut rest = &path[1..];
+    while !rest.is_empty() {
+        if rest.starts_with('.') {
+            let end = rest[1..]
+                .find(|c| c == '.' || c == '[')
+                .map(|end| end + 1)
+                .unwrap_or_else(|| rest.len());
+            if end == 1 {
+                bail!("Empty field name in JSON path: {}", path);
+            }
+            segments.push(PathSegment::Field(rest[1..end].to_owned()));
+            rest = &rest[end..];
+        } else if rest.starts_with('[') {
+            let end = match rest.find(']') {
+                Some(end) => end,
+                None => bail!("Unclosed index in JSON path: {}", path),
+            };
+            match rest[1..end].parse::<usize>() {
+                Ok(index) => segments.push(PathSegment::Index(index)),
+                Err(_) => bail!("Invalid index in JSON path: {}", path),
+            }
+            rest = &rest[end + 1..];
+        } else {
+            bail!("Invalid JSON path: {}", path);
+        }
+    }
+    Ok(segments)
+}
+
+fn select_json_path<'a>(value: &'a Value, path: &[PathSegment]) -> Option<&'a Value> {
+    path.iter()
+        .fold(Some(value), |value, segment| match *segment {
+            PathSegment::Field(ref name) => value.and_then(|v| v.get(name.as_str())),
+            PathSegment::Index(index) => value.and_then(|v| v.get(index)),
+        })
+}
+
+/// What a search job looks for in each message.
+#[derive(Debug)]
+pub enum MessageMatcher {
+    /// Messages with exactly this key.
+    Key(Vec<u8>),
+    /// Messages whose payload contains the bytes.
+    Substring(Vec<u8>),
+    /// Messages whose payload, read as UTF-8, matches the regex.
+    Regex(Regex),
+    /// JSON payloads where the path exists, and is equal to the value if any.
+    JsonPath(Vec<PathSegment>, Option<Value>),
+}
+
+impl MessageMatcher {
+    /// Parses the matcher of a search request. For JSON paths the value is parsed as JSON, or
+    /// used as a string if it isn't valid JSON.
+    pub fn parse(kind: &str, pattern: &str, value: Option<&str>) -> Result<MessageMatcher> {
+        let matcher = match kind {
+            "key" => MessageMatcher::Key(pattern.as_bytes().to_vec()),
+            "substring" => MessageMatcher::Substring(pattern.as_bytes().to_vec()),
+            "regex" => MessageMatcher::Regex(
+                Regex::new(pattern).chain_err(|| format!("Invalid regex: {}", pattern))?,
+            ),
+            "jsonpath" => MessageMatcher::JsonPath(
+                parse_json_path(pattern)?,
+                value.filter(|v| !v.is_empty()).map(|v| {
+                    serde_json::from_str(v).unwrap_or_else(|_| Value::String(v.to_owned()))
+                }),
+            ),
+            _ => bail!("Unknown search type: {}", kind),
+        };
+        Ok(matcher)
+    }
+
+    pub fn matches(&self, key: Option<&[u8]>, payload: Option<&[u8]>) -> bool {
+        let payload = payload.unwrap_or(&[]);
+        match *self {
+            MessageMatcher::Key(ref expected) => key == Some(expected.as_slice()),
+            MessageMatcher::Substring(ref needle) => {
+                needle.is_empty() || payload.windows(needle.len()).any(|w| w == &needle[..])
+            }
+            MessageMatcher::Regex(ref regex) => regex.is_match(&String::from_utf8_lossy(payload)),
+            MessageMatcher::JsonPath(ref path, ref expected) => {
+                let json = match serde_json::from_slice::<Value>(payload) {
+                    Ok(json) => json,
+                    Err(_) => return false,
+                };
+                match (select_json_path(&json, path), expected) {
+                    (Some(found), &Some(ref expected)) => found == expected,
+                    (Some(_), &None) => true,
+                    (None, _) => false,
+                }
+            }
+        }
+    }
+}
+
+/// The part of each partition scanned by a search job.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchRange {
+    /// The last N messages.
+    Last(i64),
+    /// From the first offset, up to the second one (excluded) or the end.
+    Offsets(i64, Option<i64>),
+    /// From the first timestamp, up to the second one or the end (millis since epoch).
+    Time(i64, Option<i64>),
+}
+
+impl SearchRange {
+    pub fn parse(range: &str, from: Option<i64>, to: Option<i64>) -> Result<SearchRange> {
+        let search_range = match (range, from) {
+            ("last", Some(count)) if count > 0 => SearchRange::Last(count),
+            ("last", Some(count)) => bail!("The number of messages must be positive: {}", count),
+            ("offsets", Some(from)) => SearchRange::Offsets(from, to),
+            ("time", Some(from)) => SearchRange::Time(from, to),
+            ("last", None) | ("offsets", None) | ("time", None) => {
+                bail!("A start is required for the '{}' range", range)
+            }
+            _ => bail!("Unknown search range: {}", range),
+        };
+        Ok(search_range)
+    }
+
+    /// The offsets to scan in the partition, start included and end excluded.
+    fn resolve(
+        &self,
+        consumer: &SearchConsumer,
+        topic: &str,
+        partition: i32,
+    ) -> Result<(i64, i64)> {
+        let (low, high) = consumer
+            .fetch_watermarks(topic, partition, Duration::from_secs(10))
+            .chain_err(|| format!("Failed to fetch watermarks for {}/{}", topic, partition))?;
+        let clamp = |offset: i64| cmp::min(cmp::max(offset, low), high);
+        let (start, end) = match *self {
+            SearchRange::Last(count) => (high.saturating_sub(count), high),
+            SearchRange::Offsets(from, to) => (from, to.unwrap_or(high)),
+            SearchRange::Time(from, to) => {
+                let end = match to {
+                    Some(to) => offset_for_timestamp(consumer, topic, partition, to)?,
+                    None => high,
+                };
+                (offset_for_timestamp(consumer, topic, partition, from)?, end)
+            }
+        };
+        Ok((clamp(start), clamp(end)))
+    }
+}
+
+//
+// ********** JOBS **********
+//
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Running,
+    Done,
+    Cancelled,
+    LimitReached,
+    Failed,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SearchMatch {
+    partition: i32,
+    offset: i64,
+    timestamp: Option<i64>,
+    key: Option<String>,
+    payload: String,
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+struct PartitionProgress {
+    start: i64,
+    end: i64,
+    /// The next offset to scan.
+    position: i64,
+}
+
+#[derive(Debug)]
+struct JobStatus {
+    state: JobState,
+    message: Option<String>,
+    partitions: HashMap<i32, PartitionProgress>,
+    bytes_scanned: u64,
+    matches: Vec<SearchMatch>,
+}
+
+pub struct SearchJob {
+    id: u64,
+    cluster_id: ClusterId,
+    topic: String,
+    partition_count: usize,
+    max_bytes: u64,
+    status: Mutex<JobStatus>,
+    last_poll: RwLock<Instant>,
+}
+
+impl SearchJob {
+    fn new(
+        id: u64,
+        cluster_config: &ClusterConfig,
+        topic: &str,
+        partition_count: usize,
+    ) -> SearchJob {
+        SearchJob {
+            id,
+            cluster_id: cluster_config.cluster_id.clone().unwrap(),
+            topic: topic.to_owned(),
+            partition_count,
+            max_bytes: cluster_config.search_max_bytes,
+            status: Mutex::new(JobStatus {
+                state: JobState::Running,
+                message: None,
+                partitions: HashMap::new(),
+                bytes_scanned: 0,
+                matches: Vec::new(),
+            }),
+            last_poll: RwLock::new(Instant::now()),
+        }
+    }
+
+    fn status(&self) -> MutexGuard<JobStatus> {
+        self.status.lock().expect("Poison error")
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.status().state == JobState::Running
+    }
+
+    /// Stops the job, unless it already stopped.
+    fn stop(&self, state: JobState, message: Option<String>) {
+        let mut status = self.status();
+        if status.state == JobState::Running {
+            status.state = state;
+            status.message = message;
+        }
+    }
+
+    /// Records a scanned message. Returns false if the job should stop.
+    fn record_message<M: Message>(&self, partition: i32, message: &M, matched: bool) -> bool {
+        let mut status = self.status();
+        if let Some(progress) = status.partitions.get_mut(&partition) {
+            progress.position = message.offset() + 1;
+        }
+        status.bytes_scanned += (message.key().map(|key| key.len()).unwrap_or(0)
+            + message.payload().map(|payload| payload.len()).unwrap_or(0))
+            as u64;
+        if matched {
+            let payload = message
+                .payload()
+                .map(|bytes| String::from_utf8_lossy(bytes).into_owned())
+                .unwrap_or_default();
+            status.matches.push(SearchMatch {
+                partition,
+                offset: message.offset(),
+                timestamp: message.timestamp().to_millis(),
+                key: message
+                    .key()
+                    .map(|bytes| String::from_utf8_lossy(bytes).into_owned()),
+                payload: payload.chars().take(MAX_PAYLOAD_LENGTH).collect(),
+            });
+        }
+        if status.state != JobState::Running {
+            return false;
+        }
+        let limit = if status.bytes_scanned >= self.max_bytes {
+            Some(format!(
+                "Stopped after scanning {} bytes",
+                status.bytes_scanned
+            ))
+        } else if status.matches.len() >= MAX_MATCHES {
+            Some(format!("Stopped after {} matches", MAX_MATCHES))
+        } else {
+            None
+        };
+        if limit.is_some() {
+            status.state = JobState::LimitReached;
+            status.message = limit;
+            return false;
+        }
+        true
+    }
+
+    fn last_poll(&self) -> Instant {
+        *self.last_poll.read().unwrap()
+    }
+
+    /// The status of the job, with the matches found after the first `from`.
+    fn to_json(&self, from: usize) -> Value {
+        *self.last_poll.write().unwrap() = Instant::now();
+        let status = self.status();
+        let (scanned, total) = status
+            .partitions
+            .values()
+            .fold((0, 0), |(scanned, total), p| {
+                (scanned + p.position - p.start, total + p.end - p.start)
+            });
+        json!({
+            "state": status.state,
+            "message": status.message,
+            "partitions": self.partition_count,
+            "partitions_started": status.partitions.len(),
+            "scanned": scanned,
+            "total": total,
+            "bytes_scanned": status.bytes_scanned,
+            "match_count": status.matches.len(),
+            "matches": status.matches.iter().skip(from).collect::<Vec<_>>(),
+        })
+    }
+}
+
+type SearchConsumer = BaseConsumer<DefaultConsumerContext>;
+
+fn scan_partition(
+    job: &SearchJob,
+    cluster_config: &ClusterConfig,
+    partition: i32,
+    matcher: &MessageMatcher,
+    range: SearchRange,
+) -> Result<()> {
+    let consumer = ClientConfig::new()
+        .set("bootstrap.servers", &cluster_config.bootstrap_servers())
+        .set("group.id", "kafka_view_message_search")
+        .set("enable.partition.eof", "true")
+        .set("enable.auto.commit", "false")
+        .set("api.version.request", "true")
+        .create::<SearchConsumer>()
+        .chain_err(|| "Consumer creation failed")?;
+
+    let (start, end) = range.resolve(&consumer, &job.topic, partition)?;
+    job.status().partitions.insert(
+        partition,
+        PartitionProgress {
+            start,
+            end,
+            position: start,
+        },
+    );
+    if start >= end {
+        return Ok(());
+    }
+
+    let mut tp_list = TopicPartitionList::new();
+    tp_list.add_partition_offset(&job.topic, partition, Offset::Offset(start));
+    consumer
+        .assign(&tp_list)
+        .chain_err(|| "Failure during consumer assignment")?;
+
+    while job.is_running() {
+        match consumer.poll(Duration::from_millis(100)) {
+            Some(Ok(message)) => {
+                if message.offset() >= end {
+                    break;
+                }
+                let matched = matcher.matches(message.key(), message.payload());
+                if !job.record_message(partition, &message, matched) || message.offset() + 1 >= end
+                {
+                    break;
+                }
+            }
+            Some(Err(KafkaError::PartitionEOF(_))) => break,
+            Some(Err(e)) => bail!(
+                "Error while reading from {}/{}: {}",
+                job.topic,
+                partition,
+                e
+            ),
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+/// Scans the partitions with `SEARCH_THREADS` threads, and marks the job as done once they
+/// are all scanned.
+fn run_job(
+    job: Arc<SearchJob>,
+    cluster_config: ClusterConfig,
+    partitions: Vec<i32>,
+    matcher: MessageMatcher,
+    range: SearchRange,
+) {
+    let queue = Arc::new(Mutex::new(partitions));
+    let cluster_config = Arc::new(cluster_config);
+    let matcher = Arc::new(matcher);
+    let workers = (0..SEARCH_THREADS)
+        .map(|_| {
+            let (job, queue) = (job.clone(), queue.clone());
+            let (cluster_config, matcher) = (cluster_config.clone(), matcher.clone());
+            thread::Builder::new()
+                .name("message-search".to_owned())
+                .spawn(move || loop {
+                    let partition = match queue.lock().expect("Poison error").pop() {
+                        Some(partition) if job.is_running() => partition,
+                        _ => break,
+                    };
+                    if let Err(e) =
+                        scan_partition(&job, &cluster_config, partition, &matcher, range)
+                    {
+                        format_error_chain!(e);
+                        job.stop(JobState::Failed, Some(e.to_string()));
+                    }
+                })
+        })
+        .collect::<Vec<_>>();
+    for worker in workers {
+        match worker {
+            Ok(handle) => {
+                let _ = handle.join();
+            }
+            Err(e) => job.stop(
+                JobState::Failed,
+                Some(format!("Failed to start search thread: {}", e)),
+            ),
+        }
+    }
+    job.stop(JobState::Done, None);
+    debug!("Search job {} finished", job.id);
+}
+
+type SearchJobMap = HashMap<u64, Arc<SearchJob>>;
+
+fn remove_idle_jobs(jobs: &mut SearchJobMap) {
+    jobs.retain(|_, job| {
+        let idle = job.last_poll().elapsed() >= JOB_IDLE_TIMEOUT;
+        if idle {
+            job.stop(JobState::Cancelled, Some("Not polled anymore".to_owned()));
+        }
+        !idle
+    });
+}
+
+/// The message search jobs, similar to `LiveConsumerStore`.
+pub struct SearchJobStore {
+    jobs: Arc<RwLock<SearchJobMap>>,
+    _executor: ThreadPoolExecutor,
+}
+
+impl SearchJobStore {
+    pub fn new(executor: ThreadPoolExecutor) -> SearchJobStore {
+        let jobs = Arc::new(RwLock::new(HashMap::new()));
+        let jobs_clone = Arc::clone(&jobs);
+        executor.schedule_fixed_rate(
+            Duration::from_secs(10),
+            Duration::from_secs(10),
+            move |_handle| {
+                let mut jobs = jobs_clone.write().unwrap();
+                remove_idle_jobs(&mut *jobs);
+            },
+        );
+        SearchJobStore {
+            jobs,
+            _executor: executor,
+        }
+    }
+
+    fn get_job(&self, id: u64) -> Option<Arc<SearchJob>> {
+        let jobs = self.jobs.read().expect("Poison error");
+        (*jobs).get(&id).cloned()
+    }
+
+    /// Starts a new job, if the cluster doesn't have too many running already.
+    fn start_job(
+        &self,
+        cluster_config: &ClusterConfig,
+        topic: &str,
+        partitions: Vec<i32>,
+        matcher: MessageMatcher,
+        range: SearchRange,
+    ) -> Result<u64> {
+        let mut jobs = self.jobs.write().expect("Poison error");
+        let running = jobs
+            .values()
+            .filter(|job| Some(&job.cluster_id) == cluster_config.cluster_id.as_ref())
+            .filter(|job| job.is_running())
+            .count();
+        if running >= cluster_config.search_max_jobs {
+            bail!(
+                "Too many searches running on this cluster ({}), try again later",
+                running
+            );
+        }
+
+        let id = random::<u64>();
+        let job = Arc::new(SearchJob::new(id, cluster_config, topic, partitions.len()));
+        jobs.insert(id, job.clone());
+        let cluster_config = cluster_config.clone();
+        thread::Builder::new()
+            .name("message-search-job".to_owned())
+            .spawn(move || run_job(job, cluster_config, partitions, matcher, range))
+            .chain_err(|| "Failed to start search job")?;
+        Ok(id)
+    }
+}
+
+//
+// ********** API **********
+//
+
+#[derive(FromForm)]
+pub struct SearchParams {
+    kind: String,
+    pattern: String,
+    value: Option<String>,
+    range: String,
+    from: Option<i64>,
+    to: Option<i64>,
+}
+
+fn start_search(
+    cluster_id: &ClusterId,
+    topic: &str,
+    params: &SearchParams,
+    cache: &Cache,
+    config: &Config,
+    store: &SearchJobStore,
+) -> Result<u64> {
+    let cluster_config = match config.cluster(cluster_id) {
+        Some(cluster_config) if cluster_config.enable_tailing => cluster_config,
+        _ => bail!("Reading messages is not enabled for cluster {}", cluster_id),
+    };
+    let partitions = match cache.topics.get(&(cluster_id.clone(), topic.to_owned())) {
+        Some(partitions) => partitions.iter().map(|p| p.id).collect::<Vec<_>>(),
+        None => bail!("Topic {} not found", topic),
+    };
+    let matcher = MessageMatcher::parse(
+        &params.kind,
+        &params.pattern,
+        params.value.as_ref().map(String::as_str),
+    )?;
+    let range = SearchRange::parse(&params.range, params.from, params.to)?;
+    store.start_job(cluster_config, topic, partitions, matcher, range)
+}
+
+/// Starts a search job. The job id is returned as a string, since it doesn't fit in a
+/// JavaScript number.
+#[post("/api/clusters/<cluster_id>/topics/<topic>/search", data = "<params>")]
+pub fn topic_search_start(
+    _origin: SameOrigin,
+    cluster_id: ClusterId,
+    topic: &RawStr,
+    params: Form<SearchParams>,
+    cache: State<Cache>,
+    config: State<Config>,
+    store: State<SearchJobStore>,
+) -> String {
+    match start_search(&cluster_id, topic, &params, &cache, &config, &store) {
+        Ok(id) => json!({ "job": id.to_string() }).to_string(),
+        Err(e) => {
+            format_error_chain!(e);
+            json!({ "error": e.to_string() }).to_string()
+        }
+    }
+}
+
+/// The progress of the job, and the matches after the first `from`.
+#[get("/api/search_jobs/<id>?<from>")]
+pub fn topic_search_status(id: u64, from: Option<usize>, store: State<SearchJobStore>) -> String {
+    match store.get_job(id) {
+        Some(job) => job.to_json(from.unwrap_or(0)).to_string(),
+        None => json!({ "error": "Search job not found" }).to_string(),
+    }
+}
+
+#[post("/api/search_jobs/<id>/cancel")]
+pub fn topic_search_cancel(_origin: SameOrigin, id: u64, store: State<SearchJobStore>) -> String {
+    if let Some(job) = store.get_job(id) {
+        job.stop(JobState::Cancelled, None);
+    }
+    json!({}).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_json_path() {
+        assert_eq!(
+            parse_json_path("$.user.addresses[1].city").unwrap(),
+            vec![
+                PathSegment::Field("user".to_owned()),
+                PathSegment::Field("addresses".to_owned()),
+                PathSegment::Index(1),
+                PathSegment::Field("city".to_owned()),
+            ]
+        );
+        assert!(parse_json_path("$").unwrap().is_empty());
+        assert!(parse_json_path("user.id").is_err());
+        assert!(parse_json_path("$.user[x]").is_err());
+        assert!(parse_json_path("$..id").is_err());
+    }
+
+    #[test]
+    fn test_message_matcher() {
+        let payload = &br#"{"user": {"id": 42, "tags": ["a", "b"]}}"#[..];
+
+        let key = MessageMatcher::parse("key", "k1", None).unwrap();
+        assert!(key.matches(Some(&b"k1"[..]), None));
+        assert!(!key.matches(Some(&b"k12"[..]), None));
+        assert!(!key.matches(None, None));
+
+        let substring = MessageMatcher::parse("substring", "\"tags\"", None).unwrap();
+        assert!(substring.matches(None, Some(payload)));
+
+        let regex = MessageMatcher::parse("regex", r#""id": \d+"#, None).unwrap();
+        assert!(regex.matches(None, Some(payload)));
+        assert!(MessageMatcher::parse("regex", "(", None).is_err());
+
+        let exists = MessageMatcher::parse("jsonpath", "$.user.tags[1]", None).unwrap();
+        assert!(exists.matches(None, Some(payload)));
+        assert!(!exists.matches(None, Some(&b"not json"[..])));
+        let equal = MessageMatcher::parse("jsonpath", "$.user.id", Some("42")).unwrap();
+        assert!(equal.matches(None, Some(payload)));
+        let different = MessageMatcher::parse("jsonpath", "$.user.tags[0]", Some("b")).unwrap();
+        assert!(!different.matches(None, Some(payload)));
+    }
+
+    #[test]
+    fn test_search_range_parse() {
+        assert_eq!(
+            SearchRange::parse("last", Some(100), None).unwrap(),
+            SearchRange::Last(100)
+        );
+        assert_eq!(
+            SearchRange::parse("offsets", Some(5), Some(10)).unwrap(),
+            SearchRange::Offsets(5, Some(10))
+        );
+        assert!(SearchRange::parse("last", Some(0), None).is_err());
+        assert!(SearchRange::parse("last", Some(i64::min_value()), None).is_err());
+        assert!(SearchRange::parse("time", None, None).is_err());
+        assert!(SearchRange::parse("middle", Some(1), None).is_err());
+    }
+}
@@ -0,0 +1,118 @@
+use chrono::{DateTime, Utc};
+
+use error::*;
+use metadata::{BrokerId, ClusterId};
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::Duration;
+
+lazy_static! {
+    pub static ref STATS: InternalStats = InternalStats::new();
+}
+
+/// Identifies a periodic fetch task: the task name, the cluster, and the broker if the task
+/// runs once per broker.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FetchTask {
+    pub name: &'static str,
+    pub cluster_id: ClusterId,
+    pub broker_id: Option<BrokerId>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FetchStats {
+    /// Duration of the last execution.
+    pub last_duration: Duration,
+    pub executions: u64,
+    pub errors: u64,
+    /// Time of the first of the current streak of failures, if the last execution failed.
+    pub failing_since: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+impl FetchStats {
+    /// Short description of the state of the task, such as "OK".
+    pub fn status(&self) -> String {
+        match self.failing_since {
+            Some(since) => format!("Unavailable since {}", since.format("%F %T UTC")),
+            None => "OK".to_owned(),
+        }
+    }
+}
+
+/// Statistics about kafka-view itself.
+pub struct InternalStats {
+    fetches: RwLock<HashMap<FetchTask, FetchStats>>,
+}
+
+impl InternalStats {
+    pub fn new() -> InternalStats {
+        InternalStats {
+            fetches: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn record_fetch(
+        &self,
+        name: &'static str,
+        cluster_id: &ClusterId,
+        broker_id: Option<BrokerId>,
+        duration: Duration,
+        result: &Result<()>,
+    ) {
+        let task = FetchTask {
+            name,
+            cluster_id: cluster_id.clone(),
+            broker_id,
+        };
+        match self.fetches.write() {
+            Ok(mut fetches) => {
+                let stats = (*fetches).entry(task).or_insert_with(FetchStats::default);
+                stats.last_duration = duration;
+                stats.executions += 1;
+                match *result {
+                    Ok(()) => stats.failing_since = None,
+                    Err(ref e) => {
+                        stats.errors += 1;
+                        stats.failing_since = stats.failing_since.or_else(|| Some(Utc::now()));
+                        stats.last_error = Some(
+                            e.iter()
+                                .map(ToString::to_string)
+                                .collect::<Vec<_>>()
+                                .join(": "),
+                        );
+                    }
+                }
+            }
+            Err(_) => panic!("Poison error while writing fetch statistics"),
+        };
+    }
+
+    pub fn fetch(
+        &self,
+        name: &'static str,
+        cluster_id: &ClusterId,
+        broker_id: Option<BrokerId>,
+    ) -> Option<FetchStats> {
+        let task = FetchTask {
+            name,
+            cluster_id: cluster_id.clone(),
+            broker_id,
+        };
+        match self.fetches.read() {
+            Ok(fetches) => (*fetches).get(&task).cloned(),
+            Err(_) => panic!("Poison error while reading fetch statistics"),
+        }
+    }
+
+    pub fn fetches(&self) -> Vec<(FetchTask, FetchStats)> {
+        match self.fetches.read() {
+            Ok(fetches) => (*fetches)
+                .iter()
+                .map(|(task, stats)| (task.clone(), stats.clone()))
+                .collect(),
+            Err(_) => panic!("Poison error while reading fetch statistics"),
+        }
+    }
+}
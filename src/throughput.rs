@@ -0,0 +1,371 @@
+use rdkafka::consumer::Consumer;
+use rdkafka::{Offset, TopicPartitionList};
+use scheduled_executor::TaskGroup;
+
+use cache::Cache;
+use config::Config;
+use error::*;
+use metadata::{BrokerId, ClusterId, Partition, TopicName, CONSUMERS};
+use metrics::{PartitionMetrics, RateWindow, TopicBrokerMetrics, TopicMetrics};
+use stats::STATS;
+use utils::insert_at;
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    pub static ref WATERMARKS: WatermarkStore = WatermarkStore::new();
+}
+
+/// Samples older than this are not needed by any rate window.
+const MAX_SAMPLE_AGE: Duration = Duration::from_secs(20 * 60);
+
+type PartitionKey = (ClusterId, TopicName, i32);
+type HighWatermarks = HashMap<(TopicName, i32), i64>;
+
+/// The high watermarks of every partition, as last sampled by `WatermarkRatesTaskGroup`.
+pub struct WatermarkStore {
+    high_watermarks: RwLock<HashMap<ClusterId, HighWatermarks>>,
+}
+
+impl WatermarkStore {
+    fn new() -> WatermarkStore {
+        WatermarkStore {
+            high_watermarks: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn set(&self, cluster_id: &ClusterId, high_watermarks: HighWatermarks) {
+        self.high_watermarks
+            .write()
+            .unwrap()
+            .insert(cluster_id.clone(), high_watermarks);
+    }
+
+    pub fn high_watermark(
+        &self,
+        cluster_id: &ClusterId,
+        topic: &str,
+        partition: i32,
+    ) -> Option<i64> {
+        self.high_watermarks
+            .read()
+            .unwrap()
+            .get(cluster_id)
+            .and_then(|watermarks| watermarks.get(&(topic.to_owned(), partition)).cloned())
+    }
+}
+
+fn as_secs_f64(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9
+}
+
+/// Computes the message rate over the window from the high watermark samples of a partition,
+/// using the oldest sample in the window. Since samples are taken every `interval`, a sample
+/// up to half an interval older than the window is still accepted.
+fn window_rate(
+    samples: &VecDeque<(Instant, i64)>,
+    window: Duration,
+    interval: Duration,
+) -> Option<f64> {
+    let &(now, high) = samples.back()?;
+    let max_age = window + interval / 2;
+    let &(then, old_high) = samples
+        .iter()
+        .find(|&&(ts, _)| now.duration_since(ts) <= max_age)?;
+    let elapsed = as_secs_f64(now.duration_since(then));
+    if elapsed > 0f64 && high >= old_high {
+        Some((high - old_high) as f64 / elapsed)
+    } else {
+        // Not enough samples yet, or the topic was recreated.
+        None
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct WindowRates {
+    rate_1: Option<f64>,
+    rate_5: Option<f64>,
+    rate_15: Option<f64>,
+}
+
+impl WindowRates {
+    fn from_samples(samples: &VecDeque<(Instant, i64)>, interval: Duration) -> WindowRates {
+        let rate = |window: RateWindow| window_rate(samples, window.duration(), interval);
+        WindowRates {
+            rate_1: rate(RateWindow::OneMinute),
+            rate_5: rate(RateWindow::FiveMinutes),
+            rate_15: rate(RateWindow::FifteenMinutes),
+        }
+    }
+
+    fn add(&mut self, other: &WindowRates) {
+        fn add_rate(acc: &mut Option<f64>, rate: Option<f64>) {
+            if let Some(rate) = rate {
+                *acc = Some(acc.unwrap_or(0f64) + rate);
+            }
+        }
+        add_rate(&mut self.rate_1, other.rate_1);
+        add_rate(&mut self.rate_5, other.rate_5);
+        add_rate(&mut self.rate_15, other.rate_15);
+    }
+
+    fn partition_metrics(&self) -> PartitionMetrics {
+        PartitionMetrics {
+            size_bytes: -1f64,
+            m_rate_1: self.rate_1.unwrap_or(-1f64),
+            m_rate_5: self.rate_5.unwrap_or(-1f64),
+            m_rate_15: self.rate_15.unwrap_or(-1f64),
+        }
+    }
+
+    fn broker_metrics(&self, partitions: Vec<PartitionMetrics>) -> TopicBrokerMetrics {
+        TopicBrokerMetrics {
+            m_rate_1: self.rate_1.unwrap_or(-1f64),
+            m_rate_5: self.rate_5.unwrap_or(-1f64),
+            m_rate_15: self.rate_15.unwrap_or(-1f64),
+            ..TopicBrokerMetrics::unknown(partitions)
+        }
+    }
+}
+
+/// Periodically samples the high watermarks of every partition, which are kept in `WATERMARKS`
+/// for the consumer lag. For clusters without a metrics port, the samples are also used to
+/// estimate the message rates: they are attributed to the leader of each partition and stored
+/// in the metrics cache, like the ones read from JMX. Byte rates remain unknown.
+pub struct WatermarkRatesTaskGroup {
+    cache: Cache,
+    config: Config,
+    history: Mutex<HashMap<PartitionKey, VecDeque<(Instant, i64)>>>,
+}
+
+impl WatermarkRatesTaskGroup {
+    pub fn new(cache: &Cache, config: &Config) -> WatermarkRatesTaskGroup {
+        WatermarkRatesTaskGroup {
+            cache: cache.alias(),
+            config: config.clone(),
+            history: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn record_sample(&self, key: PartitionKey, high: i64) -> WindowRates {
+        let now = Instant::now();
+        let mut history = self.history.lock().unwrap();
+        let samples = history.entry(key).or_insert_with(VecDeque::new);
+        samples.push_back((now, high));
+        while samples
+            .front()
+            .map(|&(ts, _)| now.duration_since(ts) > MAX_SAMPLE_AGE)
+            .unwrap_or(false)
+        {
+            samples.pop_front();
+        }
+        WindowRates::from_samples(samples, Duration::from_secs(self.config.metrics_refresh))
+    }
+
+    fn fetch_high_watermarks(
+        &self,
+        cluster_id: &ClusterId,
+        topics: &[(TopicName, Vec<Partition>)],
+    ) -> Result<HighWatermarks> {
+        let mut tp_list = TopicPartitionList::new();
+        for &(ref topic, ref partitions) in topics {
+            for p in partitions {
+                tp_list.add_partition_offset(topic, p.id, Offset::End);
+            }
+        }
+        let mut high_watermarks = HashMap::new();
+        if tp_list.count() == 0 {
+            return Ok(high_watermarks);
+        }
+        // The offsets of the latest timestamp are the high watermarks: they are all fetched with
+        // a single request per broker.
+        let offsets = CONSUMERS
+            .get_err(cluster_id)?
+            .offsets_for_times(tp_list, Duration::from_secs(30))
+            .chain_err(|| "Failed to fetch high watermarks")?;
+        for element in offsets.elements() {
+            match (element.error(), element.offset()) {
+                (Ok(()), Offset::Offset(high)) => {
+                    high_watermarks.insert((element.topic().to_owned(), element.partition()), high);
+                }
+                (Err(e), _) => debug!(
+                    "Failed to fetch watermarks for {}/{}: {}",
+                    element.topic(),
+                    element.partition(),
+                    e
+                ),
+                (Ok(()), offset) => debug!(
+                    "Unexpected high watermark for {}/{}: {:?}",
+                    element.topic(),
+                    element.partition(),
+                    offset
+                ),
+            }
+        }
+        Ok(high_watermarks)
+    }
+
+    fn estimate_rates(
+        &self,
+        cluster_id: &ClusterId,
+        topics: Vec<(TopicName, Vec<Partition>)>,
+        high_watermarks: &HighWatermarks,
+    ) -> Result<()> {
+        let mut totals: HashMap<BrokerId, WindowRates> = HashMap::new();
+        for (topic, partitions) in topics {
+            let mut brokers: HashMap<BrokerId, (WindowRates, Vec<PartitionMetrics>)> =
+                HashMap::new();
+            for p in &partitions {
+                let high = match high_watermarks.get(&(topic.clone(), p.id)) {
+                    Some(&high) => high,
+                    None => continue,
+                };
+                let rates = self.record_sample((cluster_id.clone(), topic.clone(), p.id), high);
+                let entry = brokers.entry(p.leader).or_insert_with(Default::default);
+                entry.0.add(&rates);
+                insert_at(
+                    &mut entry.1,
+                    p.id as usize,
+                    rates.partition_metrics(),
+                    WindowRates::default().partition_metrics(),
+                );
+            }
+
+            let mut topic_metrics = TopicMetrics::new();
+            for (broker_id, (rates, partitions)) in brokers {
+                totals
+                    .entry(broker_id)
+                    .or_insert_with(Default::default)
+                    .add(&rates);
+                topic_metrics
+                    .brokers
+                    .insert(broker_id, rates.broker_metrics(partitions));
+            }
+            self.cache
+                .metrics
+                .insert((cluster_id.clone(), topic), topic_metrics)
+                .chain_err(|| "Failed to insert to metrics")?;
+        }
+
+        let mut total_metrics = TopicMetrics::new();
+        for (broker_id, rates) in totals {
+            total_metrics
+                .brokers
+                .insert(broker_id, rates.broker_metrics(Vec::new()));
+        }
+        self.cache
+            .metrics
+            .insert((cluster_id.clone(), "__TOTAL__".to_owned()), total_metrics)
+            .chain_err(|| "Failed to insert to metrics")?;
+
+        // Forget partitions that haven't been sampled in a while, e.g. of deleted topics.
+        let now = Instant::now();
+        self.history.lock().unwrap().retain(|_, samples| {
+            samples
+                .back()
+                .map(|&(ts, _)| now.duration_since(ts) <= MAX_SAMPLE_AGE)
+                .unwrap_or(false)
+        });
+        Ok(())
+    }
+
+    fn sample_watermarks(&self, cluster_id: &ClusterId) -> Result<()> {
+        let topics = self
+            .cache
+            .topics
+            .filter_clone(|&(ref c, _)| c == cluster_id)
+            .into_iter()
+            .map(|((_, topic), partitions)| (topic, partitions))
+            .collect::<Vec<_>>();
+        let high_watermarks = self.fetch_high_watermarks(cluster_id, &topics)?;
+        let has_metrics = self
+            .config
+            .cluster(cluster_id)
+            .map(|cluster_config| cluster_config.metrics_port().is_some())
+            .unwrap_or(false);
+        if !has_metrics {
+            self.estimate_rates(cluster_id, topics, &high_watermarks)?;
+        }
+        WATERMARKS.set(cluster_id, high_watermarks);
+        Ok(())
+    }
+}
+
+impl TaskGroup for WatermarkRatesTaskGroup {
+    type TaskId = ClusterId;
+
+    fn get_tasks(&self) -> Vec<ClusterId> {
+        self.config.clusters.keys().cloned().collect()
+    }
+
+    fn execute(&self, cluster_id: ClusterId) {
+        debug!("Sampling watermarks for {}", cluster_id);
+        let start = Instant::now();
+        let result = self.sample_watermarks(&cluster_id);
+        STATS.record_fetch(
+            "watermark_rates",
+            &cluster_id,
+            None,
+            start.elapsed(),
+            &result,
+        );
+        if let Err(e) = result {
+            format_error_chain!(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Samples taken the given number of seconds after `start`.
+    fn samples(start: Instant, points: &[(u64, i64)]) -> VecDeque<(Instant, i64)> {
+        points
+            .iter()
+            .map(|&(secs, high)| (start + Duration::from_secs(secs), high))
+            .collect()
+    }
+
+    #[test]
+    fn test_window_rate() {
+        let start = Instant::now();
+        let interval = Duration::from_secs(60);
+        let samples = samples(start, &[(0, 0), (180, 600), (240, 1200), (300, 1800)]);
+
+        assert_eq!(
+            window_rate(&samples, Duration::from_secs(60), interval),
+            Some(10f64)
+        );
+        assert_eq!(
+            window_rate(&samples, Duration::from_secs(15 * 60), interval),
+            Some(6f64)
+        );
+    }
+
+    #[test]
+    fn test_window_rate_unknown() {
+        let start = Instant::now();
+        let interval = Duration::from_secs(60);
+
+        let single = samples(start, &[(0, 100)]);
+        assert_eq!(
+            window_rate(&single, Duration::from_secs(60), interval),
+            None
+        );
+
+        let recreated = samples(start, &[(0, 100), (60, 10)]);
+        assert_eq!(
+            window_rate(&recreated, Duration::from_secs(60), interval),
+            None
+        );
+
+        let too_old = samples(start, &[(0, 100), (600, 200)]);
+        assert_eq!(
+            window_rate(&too_old, Duration::from_secs(60), interval),
+            None
+        );
+    }
+}
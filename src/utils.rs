@@ -1,6 +1,6 @@
 use brotli;
 use byteorder::{BigEndian, ReadBytesExt};
-use chrono::Local;
+use chrono::{Local, TimeZone, Utc};
 use env_logger::Builder;
 use log::{LevelFilter, Record};
 use rocket::http::{ContentType, Status};
@@ -92,6 +92,28 @@ pub fn insert_at<T: Copy>(vector: &mut Vec<T>, pos: usize, value: T, default: T)
     vector[pos] = value;
 }
 
+/// Formats a number of bytes with binary units, such as `1.5 MiB`.
+pub fn format_bytes(bytes: f64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024f64 && unit < units.len() - 1 {
+        value /= 1024f64;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, units[unit])
+}
+
+/// Formats a timestamp in milliseconds since epoch, such as `2019-03-22 10:00:00 UTC`.
+/// Timestamps that can't be represented as a date are returned as they are.
+pub fn format_millis(millis: i64) -> String {
+    let nanos = millis.rem_euclid(1000) as u32 * 1_000_000;
+    match Utc.timestamp_opt(millis.div_euclid(1000), nanos).single() {
+        Some(datetime) => datetime.format("%F %T UTC").to_string(),
+        None => millis.to_string(),
+    }
+}
+
 /// Wraps a JSON value and implements a responder for it, with support for brotli compression.
 #[allow(dead_code)]
 pub struct CompressedJSON(pub serde_json::Value);
@@ -118,6 +140,33 @@ impl Responder<'static> for CompressedJSON {
     }
 }
 
+/// Decodes the `%XX` escapes of a URL component, or of a name sanitized by Kafka.
+pub fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' && i + 2 < bytes.len() {
+            str::from_utf8(&bytes[i + 1..i + 3])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
 pub fn read_str<'a>(rdr: &'a mut Cursor<&[u8]>) -> Result<&'a str> {
     let len = (rdr.read_i16::<BigEndian>()).chain_err(|| "Failed to parse string len")? as usize;
     let pos = rdr.position() as usize;
@@ -144,7 +193,7 @@ impl fairing::Fairing for GZip {
 
     fn on_response(&self, request: &Request, response: &mut Response) {
         use flate2::{Compression, FlateReadExt};
-        use std::io::{Cursor, Read};
+        use std::io::Read;
         let headers = request.headers();
         if headers
             .get("Accept-Encoding")
@@ -1,21 +1,31 @@
+use chrono::{TimeZone, Utc};
 use futures::{future, Future};
 use futures_cpupool::Builder;
 use rdkafka::consumer::Consumer;
 use rdkafka::error::KafkaResult;
 use regex::Regex;
 use rocket::http::RawStr;
+use rocket::request::Form;
 use rocket::State;
+use serde_json;
 
-use cache::Cache;
-use config::Config;
+use admin::{self, ResetTarget};
+use cache::{Cache, OffsetsCache};
+use config::{ClusterConfig, Config};
+use disk::{cluster_disk_usage, DiskForecast};
 use error::*;
 use live_consumer::LiveConsumerStore;
 use metadata::{ClusterId, TopicName, TopicPartition, CONSUMERS};
-use offsets::OffsetStore;
+use metrics::{RateWindow, ReplicaSize};
+use offsets::{OffsetStorage, OffsetStore, StoredOffsets, OFFSET_CONSUMERS};
+use quotas::client_quota;
+use stats::STATS;
+use utils::format_bytes;
 use web_server::pages::omnisearch::OmnisearchFormParams;
-use zk::ZK;
+use web_server::server::SameOrigin;
 
 use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 
 //
 // ********** TOPICS LIST **********
@@ -26,12 +36,17 @@ struct TopicDetails {
     topic_name: String,
     partition_count: usize,
     errors: String,
-    b_rate_15: f64,
-    m_rate_15: f64,
+    b_rate: f64,
+    m_rate: f64,
 }
 
-#[get("/api/clusters/<cluster_id>/topics")]
-pub fn cluster_topics(cluster_id: ClusterId, cache: State<Cache>) -> String {
+#[get("/api/clusters/<cluster_id>/topics?<window>")]
+pub fn cluster_topics(
+    cluster_id: ClusterId,
+    window: Option<RateWindow>,
+    cache: State<Cache>,
+) -> String {
+    let window = window.unwrap_or_default();
     let brokers = cache.brokers.get(&cluster_id);
     if brokers.is_none() {
         // TODO: Improve here
@@ -56,8 +71,8 @@ pub fn cluster_topics(cluster_id: ClusterId, cache: State<Cache>) -> String {
                     .filter_map(|p| p.error)
                     .collect::<Vec<_>>()
                     .join(","),
-                b_rate_15: metrics.b_rate_15.round(),
-                m_rate_15: metrics.m_rate_15.round(),
+                b_rate: metrics.b_rate(window).round(),
+                m_rate: metrics.m_rate(window).round(),
             }
         })
         .collect::<Vec<_>>();
@@ -69,8 +84,14 @@ pub fn cluster_topics(cluster_id: ClusterId, cache: State<Cache>) -> String {
 // ********** BROKERS LIST **********
 //
 
-#[get("/api/clusters/<cluster_id>/brokers")]
-pub fn brokers(cluster_id: ClusterId, cache: State<Cache>) -> String {
+#[get("/api/clusters/<cluster_id>/brokers?<window>")]
+pub fn brokers(
+    cluster_id: ClusterId,
+    window: Option<RateWindow>,
+    cache: State<Cache>,
+    config: State<Config>,
+) -> String {
+    let window = window.unwrap_or_default();
     let brokers = cache.brokers.get(&cluster_id);
     if brokers.is_none() {
         // TODO: Improve here
@@ -82,6 +103,8 @@ pub fn brokers(cluster_id: ClusterId, cache: State<Cache>) -> String {
         .metrics
         .get(&(cluster_id.to_owned(), "__TOTAL__".to_owned()))
         .unwrap_or_default();
+    let cluster_config = config.cluster(&cluster_id);
+    let disk_usage = cluster_disk_usage(&cache, &cluster_id, cluster_config);
     let mut result_data = Vec::with_capacity(brokers.len());
     for broker in brokers {
         let metric = broker_metrics
@@ -89,11 +112,38 @@ pub fn brokers(cluster_id: ClusterId, cache: State<Cache>) -> String {
             .get(&broker.id)
             .cloned()
             .unwrap_or_default();
+        let metrics_status = STATS
+            .fetch("metrics", &cluster_id, Some(broker.id))
+            .map(|stats| stats.status())
+            .unwrap_or_else(|| "No data".to_owned());
+        let broker_disk_usage = disk_usage.get(&broker.id).cloned().unwrap_or_default();
+        let disk_forecast = match cluster_config {
+            Some(cluster_config) => {
+                let history = cache
+                    .disk_usage
+                    .get(&(cluster_id.clone(), broker.id))
+                    .unwrap_or_default();
+                DiskForecast::new(
+                    &broker_disk_usage,
+                    &history,
+                    cluster_config.disk_usage_threshold,
+                )
+                .to_string()
+            }
+            None => "Cluster configuration is missing".to_owned(),
+        };
         result_data.push(json!((
             broker.id,
             broker.hostname,
-            metric.b_rate_15.round(),
-            metric.m_rate_15.round()
+            metric.b_rate(window).round(),
+            metric.m_rate(window).round(),
+            metrics_status,
+            broker_disk_usage.used_bytes().unwrap_or(-1f64).round(),
+            broker_disk_usage
+                .used_percent()
+                .map(|percent| format!("{:.1}%", percent))
+                .unwrap_or_else(|| "Unknown".to_owned()),
+            disk_forecast
         )));
     }
 
@@ -120,9 +170,13 @@ impl GroupInfo {
         }
     }
 
-    fn new_empty() -> GroupInfo {
+    fn new_empty(storage: OffsetStorage) -> GroupInfo {
+        let state = match storage {
+            OffsetStorage::Kafka => "Offsets only".to_owned(),
+            OffsetStorage::Zookeeper => "Offsets only (ZooKeeper)".to_owned(),
+        };
         GroupInfo {
-            state: "Offsets only".to_owned(),
+            state,
             members: 0,
             topics: HashSet::new(),
         }
@@ -150,14 +204,17 @@ where
             .collect()
     });
 
-    let offsets = cache
-        .offsets
-        .filter_clone_k(|&(ref c, ref g, _)| filter(c, g));
-    for (cluster_id, group, t) in offsets {
-        groups
-            .entry((cluster_id, group))
-            .or_insert_with(GroupInfo::new_empty)
-            .add_topic(t);
+    for &(storage, ref offsets_cache) in &[
+        (OffsetStorage::Kafka, &cache.offsets),
+        (OffsetStorage::Zookeeper, &cache.zk_offsets),
+    ] {
+        let offsets = offsets_cache.filter_clone_k(|&(ref c, ref g, _)| filter(c, g));
+        for (cluster_id, group, t) in offsets {
+            groups
+                .entry((cluster_id, group))
+                .or_insert_with(|| GroupInfo::new_empty(storage))
+                .add_topic(t);
+        }
     }
 
     groups
@@ -223,6 +280,8 @@ pub fn group_members(cluster_id: ClusterId, group_name: &RawStr, cache: State<Ca
     }
 
     let group = group.unwrap();
+    // Quotas are unknown if they haven't been read from ZooKeeper
+    let quotas = cache.quotas.get(&cluster_id);
 
     let mut result_data = Vec::with_capacity(group.members.len());
     for member in group.members {
@@ -243,11 +302,20 @@ pub fn group_members(cluster_id: ClusterId, group_name: &RawStr, cache: State<Ca
             })
             .collect::<Vec<_>>()
             .join("\n");
+        let quota = match quotas
+            .as_ref()
+            .map(|quotas| client_quota(quotas, &member.client_id))
+        {
+            Some(Some(quota)) => quota.description(),
+            Some(None) => "Unknown/overridden".to_owned(),
+            None => "Unknown".to_owned(),
+        };
         result_data.push(json!((
             member.id,
             member.client_id,
             member.client_host,
-            assigns
+            assigns,
+            quota
         )));
     }
 
@@ -268,8 +336,11 @@ pub fn group_offsets(cluster_id: ClusterId, group_name: &RawStr, cache: State<Ca
     };
 
     let mut result_data = Vec::with_capacity(offsets.len());
-    for ((_cluster_id, _group, topic), partitions) in offsets {
+    for (storage, (_cluster_id, _group, topic), partitions) in offsets {
         for (partition_id, &curr_offset) in partitions.iter().enumerate() {
+            if curr_offset < 0 {
+                continue; // no offset stored for this partition
+            }
             let (low, high) = match wms.get(&(topic.clone(), partition_id as i32)) {
                 Some(&Ok((low_mark, high_mark))) => (low_mark, high_mark),
                 _ => (-1, -1),
@@ -290,7 +361,8 @@ pub fn group_offsets(cluster_id: ClusterId, group_name: &RawStr, cache: State<Ca
                 high,
                 curr_offset,
                 lag_shown,
-                percentage_shown
+                percentage_shown,
+                storage.to_string()
             )));
         }
     }
@@ -298,11 +370,10 @@ pub fn group_offsets(cluster_id: ClusterId, group_name: &RawStr, cache: State<Ca
     json!({ "data": result_data }).to_string()
 }
 
-type ClusterGroupOffsets = ((ClusterId, String, TopicName), Vec<i64>);
-
+/// Fetches the watermarks of all the partitions the offsets refer to.
 fn fetch_watermarks(
     cluster_id: &ClusterId,
-    offsets: &[ClusterGroupOffsets],
+    offsets: &[StoredOffsets],
 ) -> Result<HashMap<TopicPartition, KafkaResult<(i64, i64)>>> {
     let consumer = CONSUMERS.get_err(cluster_id)?;
 
@@ -310,18 +381,23 @@ fn fetch_watermarks(
 
     let mut futures = Vec::new();
 
-    for &((_, _, ref topic), ref partitions) in offsets {
+    let mut topic_partitions = HashSet::new();
+    for &(_, (_, _, ref topic), ref partitions) in offsets {
         for partition_id in 0..partitions.len() {
-            let consumer_clone = consumer.clone();
-            let topic_clone = topic.clone();
-            let wm_future = cpu_pool.spawn_fn(move || {
-                let wms = consumer_clone.fetch_watermarks(&topic_clone, partition_id as i32, 10000);
-                Ok::<_, ()>(((topic_clone, partition_id as i32), wms)) // never fail
-            });
-            futures.push(wm_future);
+            topic_partitions.insert((topic.clone(), partition_id as i32));
         }
     }
 
+    for (topic, partition_id) in topic_partitions {
+        let consumer_clone = consumer.clone();
+        let wm_future = cpu_pool.spawn_fn(move || {
+            let wms =
+                consumer_clone.fetch_watermarks(&topic, partition_id, Duration::from_secs(10));
+            Ok::<_, ()>(((topic, partition_id), wms)) // never fail
+        });
+        futures.push(wm_future);
+    }
+
     let watermarks = future::join_all(futures)
         .wait()
         .unwrap()
@@ -331,12 +407,139 @@ fn fetch_watermarks(
     Ok(watermarks)
 }
 
+//
+// ********** OFFSET RESET **********
+//
+
+#[derive(FromForm)]
+pub struct OffsetResetParams {
+    topic: String,
+    partition: Option<i32>,
+    strategy: String,
+    value: Option<i64>,
+}
+
+/// The execution of a previewed offset reset: the parameters are the ones of the preview, and
+/// the plan is the one it returned.
+#[derive(FromForm)]
+pub struct OffsetResetExecuteParams {
+    topic: String,
+    partition: Option<i32>,
+    strategy: String,
+    value: Option<i64>,
+    plan: String,
+}
+
+fn admin_cluster_config<'a>(
+    config: &'a Config,
+    cluster_id: &ClusterId,
+) -> Result<&'a ClusterConfig> {
+    match config.cluster(cluster_id) {
+        Some(cluster_config) if cluster_config.enable_admin => Ok(cluster_config),
+        _ => bail!("Admin mode is not enabled for cluster {}", cluster_id),
+    }
+}
+
+fn preview_reset(
+    cluster_id: &ClusterId,
+    group_name: &str,
+    params: &OffsetResetParams,
+    cache: &Cache,
+    config: &Config,
+) -> Result<Vec<admin::PartitionReset>> {
+    let cluster_config = admin_cluster_config(config, cluster_id)?;
+    let target = ResetTarget::parse(&params.strategy, params.value)?;
+    admin::plan_reset(
+        cache,
+        cluster_config,
+        group_name,
+        &params.topic,
+        params.partition,
+        target,
+    )
+}
+
+fn execute_reset(
+    cluster_id: &ClusterId,
+    group_name: &str,
+    params: &OffsetResetExecuteParams,
+    cache: &Cache,
+    config: &Config,
+) -> Result<Vec<admin::PartitionReset>> {
+    let cluster_config = admin_cluster_config(config, cluster_id)?;
+    let target = ResetTarget::parse(&params.strategy, params.value)?;
+    let preview = serde_json::from_str::<Vec<admin::PartitionReset>>(&params.plan)
+        .chain_err(|| "Invalid offset reset plan")?;
+    admin::execute_reset(
+        cache,
+        cluster_config,
+        group_name,
+        &params.topic,
+        params.partition,
+        target,
+        &preview,
+    )
+}
+
+fn reset_response(result: Result<Vec<admin::PartitionReset>>) -> String {
+    match result {
+        Ok(plan) => json!({ "data": plan }).to_string(),
+        Err(e) => {
+            format_error_chain!(e);
+            json!({ "data": [], "error": e.to_string() }).to_string()
+        }
+    }
+}
+
+#[get("/api/clusters/<cluster_id>/groups/<group_name>/reset?<params..>")]
+pub fn group_offsets_reset_preview(
+    cluster_id: ClusterId,
+    group_name: &RawStr,
+    params: Form<OffsetResetParams>,
+    cache: State<Cache>,
+    config: State<Config>,
+) -> String {
+    reset_response(preview_reset(
+        &cluster_id,
+        group_name,
+        &params,
+        &cache,
+        &config,
+    ))
+}
+
+#[post(
+    "/api/clusters/<cluster_id>/groups/<group_name>/reset",
+    data = "<params>"
+)]
+pub fn group_offsets_reset(
+    _origin: SameOrigin,
+    cluster_id: ClusterId,
+    group_name: &RawStr,
+    params: Form<OffsetResetExecuteParams>,
+    cache: State<Cache>,
+    config: State<Config>,
+) -> String {
+    reset_response(execute_reset(
+        &cluster_id,
+        group_name,
+        &params,
+        &cache,
+        &config,
+    ))
+}
+
 //
 // ********** TOPIC TOPOLOGY **********
 //
 
 #[get("/api/clusters/<cluster_id>/topics/<topic_name>/topology")]
-pub fn topic_topology(cluster_id: ClusterId, topic_name: &RawStr, cache: State<Cache>) -> String {
+pub fn topic_topology(
+    cluster_id: ClusterId,
+    topic_name: &RawStr,
+    cache: State<Cache>,
+    config: State<Config>,
+) -> String {
     let partitions = cache
         .topics
         .get(&(cluster_id.to_owned(), topic_name.to_string()));
@@ -349,21 +552,32 @@ pub fn topic_topology(cluster_id: ClusterId, topic_name: &RawStr, cache: State<C
         .get(&(cluster_id.clone(), topic_name.to_string()))
         .unwrap_or_default();
     let partitions = partitions.unwrap();
+    let threshold = config
+        .cluster(&cluster_id)
+        .map(|cluster_config| cluster_config.replica_size_divergence);
 
     let mut result_data = Vec::with_capacity(partitions.len());
     for p in partitions {
-        let partition_metrics = topic_metrics
-            .brokers
-            .get(&p.leader)
-            .and_then(|broker_metrics| broker_metrics.partitions.get(p.id as usize))
-            .cloned()
-            .unwrap_or_default();
+        let follower_sizes = topic_metrics.follower_sizes(&p);
+        let divergent = follower_sizes
+            .iter()
+            .filter(|replica| threshold.map_or(false, |t| replica.is_divergent(t)))
+            .map(ReplicaSize::description)
+            .collect::<Vec<_>>()
+            .join(", ");
         result_data.push(json!((
             p.id,
-            partition_metrics.size_bytes,
+            topic_metrics
+                .partition_size(p.leader, p.id)
+                .unwrap_or(-1f64),
             p.leader,
             p.replicas,
             p.isr,
+            follower_sizes
+                .iter()
+                .map(|replica| replica.size_bytes)
+                .collect::<Vec<_>>(),
+            divergent,
             p.error
         )));
     }
@@ -371,6 +585,44 @@ pub fn topic_topology(cluster_id: ClusterId, topic_name: &RawStr, cache: State<C
     json!({ "data": result_data }).to_string()
 }
 
+#[get("/api/clusters/<cluster_id>/diverging_replicas")]
+pub fn diverging_replicas(
+    cluster_id: ClusterId,
+    cache: State<Cache>,
+    config: State<Config>,
+) -> String {
+    let threshold = match config.cluster(&cluster_id) {
+        Some(cluster_config) => cluster_config.replica_size_divergence,
+        None => return empty(),
+    };
+
+    let mut result_data = Vec::new();
+    for ((_, topic_name), partitions) in cache.topics.filter_clone(|&(ref c, _)| c == &cluster_id) {
+        let topic_metrics = cache
+            .metrics
+            .get(&(cluster_id.clone(), topic_name.clone()))
+            .unwrap_or_default();
+        for p in partitions {
+            for replica in topic_metrics.follower_sizes(&p) {
+                if !replica.is_divergent(threshold) {
+                    continue;
+                }
+                result_data.push(json!((
+                    &topic_name,
+                    p.id,
+                    p.leader,
+                    replica.leader_size_bytes,
+                    replica.broker_id,
+                    replica.size_bytes,
+                    replica.description()
+                )));
+            }
+        }
+    }
+
+    json!({ "data": result_data }).to_string()
+}
+
 //
 // ********** SEARCH **********
 //
@@ -399,8 +651,13 @@ pub fn consumer_search(search: OmnisearchFormParams, cache: State<Cache>) -> Str
     json!({ "data": result_data }).to_string()
 }
 
-#[get("/api/search/topic?<search..>")]
-pub fn topic_search(search: OmnisearchFormParams, cache: State<Cache>) -> String {
+#[get("/api/search/topic?<window>&<search..>")]
+pub fn topic_search(
+    window: Option<RateWindow>,
+    search: OmnisearchFormParams,
+    cache: State<Cache>,
+) -> String {
+    let window = window.unwrap_or_default();
     let topics = if search.regex {
         Regex::new(&search.string)
             .map(|r| cache.topics.filter_clone(|&(_, ref name)| r.is_match(name)))
@@ -424,8 +681,8 @@ pub fn topic_search(search: OmnisearchFormParams, cache: State<Cache>) -> String
             topic_name,
             partitions.len(),
             errors,
-            metrics.b_rate_15,
-            metrics.m_rate_15
+            metrics.b_rate(window),
+            metrics.m_rate(window)
         )));
     }
 
@@ -437,27 +694,12 @@ pub fn topic_search(search: OmnisearchFormParams, cache: State<Cache>) -> String
 //
 
 #[get("/api/clusters/<cluster_id>/reassignment")]
-pub fn cluster_reassignment(
-    cluster_id: ClusterId,
-    cache: State<Cache>,
-    config: State<Config>,
-) -> String {
-    if cache.brokers.get(&cluster_id).is_none() {
-        return empty();
-    }
-
-    let zk_url = &config.clusters.get(&cluster_id).unwrap().zookeeper;
-
-    let zk = match ZK::new(zk_url) {
-        // TODO: cache ZK clients
-        Ok(zk) => zk,
-        Err(_) => {
-            error!("Error connecting to {:?}", zk_url);
-            return empty();
-        }
-    };
-
-    let reassignment = match zk.pending_reassignment() {
+pub fn cluster_reassignment(cluster_id: ClusterId, cache: State<Cache>) -> String {
+    let reassignment = match cache
+        .zk_state
+        .get(&cluster_id)
+        .and_then(|state| state.reassignment)
+    {
         Some(reassignment) => reassignment,
         None => return empty(),
     };
@@ -474,14 +716,10 @@ pub fn cluster_reassignment(
             let replica_metrics = p
                 .replicas
                 .iter()
-                .map(|ref r| {
+                .map(|&r| {
                     topic_metrics
-                        .brokers
-                        .get(&r)
-                        .and_then(|b| b.partitions.get(p.partition as usize))
-                        .cloned()
-                        .unwrap_or_default()
-                        .size_bytes
+                        .partition_size(r, p.partition)
+                        .unwrap_or(-1f64)
                 })
                 .collect::<Vec<_>>();
 
@@ -492,6 +730,133 @@ pub fn cluster_reassignment(
     json!({ "data": result_data }).to_string()
 }
 
+//
+// ********** ACLS **********
+//
+
+fn resource_acls(cache: &Cache, cluster_id: &ClusterId, resource_type: &str, name: &str) -> String {
+    let result_data = cache
+        .acls
+        .get(cluster_id)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|acl| acl.matches(resource_type, name))
+        .map(|acl| {
+            json!((
+                &acl.principal,
+                &acl.operation,
+                &acl.permission_type,
+                &acl.host,
+                acl.resource_pattern()
+            ))
+        })
+        .collect::<Vec<_>>();
+
+    json!({ "data": result_data }).to_string()
+}
+
+#[get("/api/clusters/<cluster_id>/topics/<topic_name>/acls")]
+pub fn topic_acls(cluster_id: ClusterId, topic_name: &RawStr, cache: State<Cache>) -> String {
+    resource_acls(&cache, &cluster_id, "Topic", topic_name)
+}
+
+#[get("/api/clusters/<cluster_id>/groups/<group_name>/acls")]
+pub fn group_acls(cluster_id: ClusterId, group_name: &RawStr, cache: State<Cache>) -> String {
+    resource_acls(&cache, &cluster_id, "Group", group_name)
+}
+
+#[get("/api/search/principal?<search..>")]
+pub fn principal_search(search: OmnisearchFormParams, cache: State<Cache>) -> String {
+    let matcher: Box<dyn Fn(&str) -> bool> = if search.regex {
+        match Regex::new(&search.string) {
+            Ok(r) => Box::new(move |principal| r.is_match(principal)),
+            Err(_) => return empty(),
+        }
+    } else {
+        let string = search.string;
+        Box::new(move |principal| principal.contains(&string))
+    };
+
+    let mut result_data = Vec::new();
+    for (cluster_id, acls) in cache.acls.filter_clone(|_| true) {
+        for acl in acls {
+            if !matcher(&acl.principal) {
+                continue;
+            }
+            result_data.push(json!((
+                &cluster_id,
+                &acl.principal,
+                acl.resource_pattern(),
+                &acl.operation,
+                &acl.permission_type,
+                &acl.host
+            )));
+        }
+    }
+
+    json!({ "data": result_data }).to_string()
+}
+
+//
+// ********** CONTROLLER **********
+//
+
+#[get("/api/clusters/<cluster_id>/controller_changes")]
+pub fn controller_changes(cluster_id: ClusterId, cache: State<Cache>) -> String {
+    let result_data = cache
+        .cluster_info
+        .get(&cluster_id)
+        .unwrap_or_default()
+        .controller_changes
+        .into_iter()
+        .map(|change| {
+            json!((
+                Utc.timestamp(change.timestamp, 0)
+                    .format("%F %T UTC")
+                    .to_string(),
+                change
+                    .controller
+                    .map(|controller| controller.to_string())
+                    .unwrap_or_else(|| "None".to_owned())
+            ))
+        })
+        .collect::<Vec<_>>();
+
+    json!({ "data": result_data }).to_string()
+}
+
+//
+// ********** QUOTAS **********
+//
+
+#[get("/api/clusters/<cluster_id>/quotas")]
+pub fn cluster_quotas(cluster_id: ClusterId, cache: State<Cache>) -> String {
+    let format_rate = |rate: Option<f64>| {
+        rate.map(|rate| format!("{}/s", format_bytes(rate)))
+            .unwrap_or_default()
+    };
+    let result_data = cache
+        .quotas
+        .get(&cluster_id)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|quota| {
+            json!((
+                quota.user.unwrap_or_default(),
+                quota.client_id.unwrap_or_default(),
+                format_rate(quota.producer_byte_rate),
+                format_rate(quota.consumer_byte_rate),
+                quota
+                    .request_percentage
+                    .map(|percentage| format!("{:.0}%", percentage))
+                    .unwrap_or_default()
+            ))
+        })
+        .collect::<Vec<_>>();
+
+    json!({ "data": result_data }).to_string()
+}
+
 //
 // ********** INTERNALS **********
 //
@@ -527,7 +892,16 @@ pub fn cache_metrics(cache: State<Cache>) -> String {
 
 #[get("/api/internals/cache/offsets")]
 pub fn cache_offsets(cache: State<Cache>) -> String {
-    let result_data = cache.offsets.lock_iter(|offsets_cache_entry| {
+    offsets_cache_data(&cache.offsets)
+}
+
+#[get("/api/internals/cache/zk_offsets")]
+pub fn cache_zk_offsets(cache: State<Cache>) -> String {
+    offsets_cache_data(&cache.zk_offsets)
+}
+
+fn offsets_cache_data(offsets_cache: &OffsetsCache) -> String {
+    let result_data = offsets_cache.lock_iter(|offsets_cache_entry| {
         offsets_cache_entry
             .map(
                 |(&(ref cluster_id, ref group_name, ref topic_id), partitions)| {
@@ -562,6 +936,28 @@ pub fn live_consumers(live_consumers: State<LiveConsumerStore>) -> String {
     json!({ "data": result_data }).to_string()
 }
 
+#[get("/api/internals/offset_consumers")]
+pub fn offset_consumers() -> String {
+    let result_data = OFFSET_CONSUMERS
+        .statuses()
+        .into_iter()
+        .map(|(cluster_id, status)| {
+            (
+                cluster_id,
+                status.state.to_string(),
+                status.restarts,
+                status.lag.unwrap_or(-1),
+                status
+                    .last_update
+                    .map(|ts| ts.format("%F %T UTC").to_string())
+                    .unwrap_or_else(|| "Never".to_owned()),
+                status.last_error.unwrap_or_default(),
+            )
+        })
+        .collect::<Vec<_>>();
+    json!({ "data": result_data }).to_string()
+}
+
 fn empty() -> String {
     json!({"data": []}).to_string()
 }
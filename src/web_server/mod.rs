@@ -1,5 +1,7 @@
 mod api;
 mod pages;
+mod prometheus;
+mod stream;
 mod view;
 
 pub mod server;
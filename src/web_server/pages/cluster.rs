@@ -1,6 +1,11 @@
+use chrono::Utc;
 use maud::{html, Markup, PreEscaped};
 
+use disk::{cluster_disk_usage, BrokerDiskUsage, DiskForecast};
 use metadata::{BrokerId, ClusterId};
+use metrics::RateWindow;
+use stats::STATS;
+use utils::format_bytes;
 use web_server::pages;
 use web_server::view::layout;
 
@@ -9,31 +14,50 @@ use config::Config;
 
 use rocket::State;
 
-fn broker_table(cluster_id: &ClusterId) -> PreEscaped<String> {
-    let api_url = format!("/api/clusters/{}/brokers", cluster_id);
+fn format_disk_usage(usage: &BrokerDiskUsage) -> String {
+    match (
+        usage.used_bytes(),
+        usage.capacity_bytes,
+        usage.used_percent(),
+    ) {
+        (Some(used), Some(capacity), Some(percent)) => format!(
+            "{} ({:.1}% of {})",
+            format_bytes(used),
+            percent,
+            format_bytes(capacity)
+        ),
+        (Some(used), _, _) => format_bytes(used),
+        _ => "Unknown".to_owned(),
+    }
+}
+
+fn broker_table(cluster_id: &ClusterId, window: RateWindow) -> PreEscaped<String> {
+    let api_url = format!("/api/clusters/{}/brokers?window={}", cluster_id, window);
+    let title = format!("Total average over the last {}", window.description());
     layout::datatable_ajax(
         "brokers-ajax",
         &api_url,
         cluster_id.name(),
         html! { tr { th { "Broker id" } th { "Hostname" }
-            th data-toggle="tooltip" data-container="body"
-                title="Total average over the last 15 minutes" { "Total byte rate" }
-            th data-toggle="tooltip" data-container="body"
-                title="Total average over the last 15 minutes" { "Total msg rate" }
+            th data-toggle="tooltip" data-container="body" title=(title) { "Total byte rate" }
+            th data-toggle="tooltip" data-container="body" title=(title) { "Total msg rate" }
+            th { "Metrics" }
+            th { "Disk usage" } th { "Disk usage %" } th { "Disk forecast" }
             }
         },
     )
 }
 
-fn topic_table(cluster_id: &ClusterId) -> PreEscaped<String> {
-    let api_url = format!("/api/clusters/{}/topics", cluster_id);
+fn topic_table(cluster_id: &ClusterId, window: RateWindow) -> PreEscaped<String> {
+    let api_url = format!("/api/clusters/{}/topics?window={}", cluster_id, window);
+    let title = format!("Average over the last {}", window.description());
     layout::datatable_ajax(
         "topics-ajax",
         &api_url,
         cluster_id.name(),
         html! { tr { th { "Topic name" } th { "#Partitions" } th { "Status" }
-               th data-toggle="tooltip" data-container="body" title="Average over the last 15 minutes" { "Byte rate" }
-               th data-toggle="tooltip" data-container="body" title="Average over the last 15 minutes" { "Msg rate" }
+               th data-toggle="tooltip" data-container="body" title=(title) { "Byte rate" }
+               th data-toggle="tooltip" data-container="body" title=(title) { "Msg rate" }
              }
         },
     )
@@ -49,6 +73,37 @@ fn groups_table(cluster_id: &ClusterId) -> PreEscaped<String> {
     )
 }
 
+fn diverging_replicas_table(cluster_id: &ClusterId) -> PreEscaped<String> {
+    let api_url = format!("/api/clusters/{}/diverging_replicas", cluster_id);
+    layout::datatable_ajax(
+        "diverging-replicas-ajax",
+        &api_url,
+        cluster_id.name(),
+        html! { tr { th { "Topic name" } th { "Partition" } th { "Leader" } th { "Leader size" }
+        th { "Follower" } th { "Follower size" } th { "Divergence" } } },
+    )
+}
+
+fn quotas_table(cluster_id: &ClusterId) -> PreEscaped<String> {
+    let api_url = format!("/api/clusters/{}/quotas", cluster_id);
+    layout::datatable_ajax(
+        "quotas-ajax",
+        &api_url,
+        cluster_id.name(),
+        html! { tr { th { "User" } th { "Client id" } th { "Produce rate" } th { "Fetch rate" } th { "Request %" } } },
+    )
+}
+
+fn controller_changes_table(cluster_id: &ClusterId) -> PreEscaped<String> {
+    let api_url = format!("/api/clusters/{}/controller_changes", cluster_id);
+    layout::datatable_ajax(
+        "controller-changes-ajax",
+        &api_url,
+        cluster_id.name(),
+        html! { tr { th { "Time" } th { "Controller" } } },
+    )
+}
+
 fn reassignment_table(cluster_id: &ClusterId) -> PreEscaped<String> {
     let api_url = format!("/api/clusters/{}/reassignment", cluster_id);
     layout::datatable_ajax(
@@ -59,8 +114,13 @@ fn reassignment_table(cluster_id: &ClusterId) -> PreEscaped<String> {
     )
 }
 
-#[get("/clusters/<cluster_id>")]
-pub fn cluster_page(cluster_id: ClusterId, cache: State<Cache>, config: State<Config>) -> Markup {
+#[get("/clusters/<cluster_id>?<window>")]
+pub fn cluster_page(
+    cluster_id: ClusterId,
+    window: Option<RateWindow>,
+    cache: State<Cache>,
+    config: State<Config>,
+) -> Markup {
     if cache.brokers.get(&cluster_id).is_none() {
         return pages::warning_page(
             &format!("Cluster: {}", cluster_id),
@@ -68,38 +128,81 @@ pub fn cluster_page(cluster_id: ClusterId, cache: State<Cache>, config: State<Co
         );
     }
 
+    let window = window.unwrap_or_default();
     let cluster_config = config.clusters.get(&cluster_id);
+    let has_zookeeper = cluster_config
+        .map(|c| c.zookeeper.is_some())
+        .unwrap_or(false);
+    let cluster_info = cache.cluster_info.get(&cluster_id).unwrap_or_default();
+    let recent_changes = cluster_info.controller_changes_since(Utc::now().timestamp() - 24 * 3600);
+    let page_url = format!("/clusters/{}", cluster_id);
     let content = html! {
         h3 style="margin-top: 0px" { "Information" }
         dl class="dl-horizontal" {
             dt { "Cluster name: " } dd { (cluster_id.name()) }
-            @if cluster_config.is_some() {
-                dt { "Bootstrap list: " } dd { (cluster_config.unwrap().broker_list.join(", ")) }
-                dt { "Zookeeper: " } dd { (cluster_config.unwrap().zookeeper) }
+            dt { "Kafka cluster id: " } dd { (cluster_info.kafka_cluster_id.as_ref().map(String::as_str).unwrap_or("Unknown")) }
+            dt { "Controller: " } dd {
+                @if let Some(controller) = cluster_info.controller {
+                    a href=(format!("/clusters/{}/brokers/{}", cluster_id, controller)) { (controller) }
+                    (format!(" ({} changes in the last 24 hours)", recent_changes))
+                } @else {
+                    "Unknown"
+                }
+            }
+            @if let Some(cluster_config) = cluster_config {
+                dt { "Bootstrap list: " } dd { (cluster_config.broker_list.join(", ")) }
+                dt { "Zookeeper: " } dd {
+                    @if let Some(ref zookeeper) = cluster_config.zookeeper {
+                        (zookeeper) " "
+                        a href=(format!("/clusters/{}/zookeeper", cluster_id)) { "(browse)" }
+                    } @else {
+                        "Not configured"
+                    }
+                }
+                @if cluster_config.metrics_port().is_none() {
+                    dt { "Metrics: " } dd { "Message rates estimated from the high watermarks" }
+                }
             } @else {
                 dt { "Bootstrap list: " } dd { "Cluster configuration is missing" }
                 dt { "Zookeeper: " } dd { "Cluster configuration is missing" }
             }
+            dt { "Rate window: " } dd { (layout::rate_window_selector(&page_url, window)) }
         }
         h3 { "Brokers" }
-        div { (broker_table(&cluster_id)) }
+        div { (broker_table(&cluster_id, window)) }
         h3 { "Topics" }
-        (topic_table(&cluster_id))
+        (topic_table(&cluster_id, window))
         h3 { "Consumer groups" }
         (groups_table(&cluster_id))
+        h3 { "Diverging replicas" }
+        (diverging_replicas_table(&cluster_id))
+        h3 { "Quotas" }
+        @if has_zookeeper {
+            (quotas_table(&cluster_id))
+        } @else {
+            p { "Quotas are read from ZooKeeper, which is not configured for this cluster." }
+        }
+
+        h3 { "Controller changes" }
+        (controller_changes_table(&cluster_id))
 
         @if cluster_config.map(|c| c.show_zk_reassignments).unwrap_or(false) {
             h3 { "Reassignment" }
-            (reassignment_table(&cluster_id))
+            @if has_zookeeper {
+                (reassignment_table(&cluster_id))
+            } @else {
+                p { "Reassignments are read from ZooKeeper, which is not configured for this cluster." }
+            }
         }
     };
     layout::page(&format!("Cluster: {}", cluster_id), content)
 }
 
-#[get("/clusters/<cluster_id>/brokers/<broker_id>")]
+#[get("/clusters/<cluster_id>/brokers/<broker_id>?<window>")]
 pub fn broker_page(
     cluster_id: ClusterId,
     broker_id: BrokerId,
+    window: Option<RateWindow>,
     cache: State<Cache>,
     config: State<Config>,
 ) -> Markup {
@@ -117,19 +220,53 @@ pub fn broker_page(
     }
 
     let broker = broker.unwrap();
+    let window = window.unwrap_or_default();
+    let page_url = format!("/clusters/{}/brokers/{}", cluster_id, broker_id);
+    let metrics_stats = STATS.fetch("metrics", &cluster_id, Some(broker_id));
+    let disk_usage = cluster_disk_usage(&cache, &cluster_id, cluster_config)
+        .remove(&broker_id)
+        .unwrap_or_default();
+    let disk_history = cache
+        .disk_usage
+        .get(&(cluster_id.clone(), broker_id))
+        .unwrap_or_default();
+    let disk_forecast = DiskForecast::new(
+        &disk_usage,
+        &disk_history,
+        cluster_config.unwrap().disk_usage_threshold,
+    );
     let metrics = cache
         .metrics
         .get(&(cluster_id.to_owned(), "__TOTAL__".to_owned()))
         .unwrap_or_default()
         .aggregate_broker_metrics();
+    let is_controller = match cache
+        .cluster_info
+        .get(&cluster_id)
+        .and_then(|info| info.controller)
+    {
+        Some(controller) if controller == broker_id => "Yes",
+        Some(_) => "No",
+        None => "Unknown",
+    };
     let content = html! {
         h3 style="margin-top: 0px" { "Information" }
         dl class="dl-horizontal" {
             dt { "Cluster name: " } dd { (cluster_id.name()) }
             dt { "Bootstrap list: " } dd { (cluster_config.unwrap().broker_list.join(", ")) }
-            dt { "Zookeeper: " } dd { (cluster_config.unwrap().zookeeper) }
+            dt { "Zookeeper: " } dd { (cluster_config.unwrap().zookeeper.as_ref().map(String::as_str).unwrap_or("Not configured")) }
             dt { "Hostname" } dd { (broker.hostname) }
-            dt { "Traffic" } dd { (format!("{:.1} KB/s  {:.0} msg/s", metrics.b_rate_15 / 1000f64, metrics.m_rate_15)) }
+            dt { "Controller" } dd { (is_controller) }
+            dt { "Traffic" } dd { (format!("{:.1} KB/s  {:.0} msg/s", metrics.b_rate(window) / 1000f64, metrics.m_rate(window))) }
+            dt { "Rate window" } dd { (layout::rate_window_selector(&page_url, window)) }
+            dt { "Disk usage" } dd { (format_disk_usage(&disk_usage)) }
+            dt { "Disk forecast" } dd { (disk_forecast) }
+            @if let Some(ref stats) = metrics_stats {
+                dt { "Metrics" } dd { (stats.status()) }
+                @if let (Some(_), Some(error)) = (stats.failing_since, stats.last_error.as_ref()) {
+                    dt { "Metrics error" } dd { (error) }
+                }
+            }
         }
     };
     layout::page(&format!("Broker: {}", cluster_id), content)
@@ -2,6 +2,7 @@ use maud::{html, Markup, PreEscaped};
 use rocket::http::RawStr;
 
 use cache::Cache;
+use config::Config;
 use metadata::ClusterId;
 use web_server::pages;
 use web_server::view::layout;
@@ -14,7 +15,9 @@ fn group_members_table(cluster_id: &ClusterId, group_name: &str) -> PreEscaped<S
         "group-members-ajax",
         &api_url,
         cluster_id.name(),
-        html! { tr { th { "Member id" } th { "Client id" } th { "Hostname" } th { "Assignments" } } },
+        html! { tr { th { "Member id" } th { "Client id" } th { "Hostname" } th { "Assignments" }
+        th data-toggle="tooltip" data-container="body"
+            title="Client id quota. User quotas, if any, take precedence" { "Quota" } } },
     )
 }
 
@@ -25,12 +28,69 @@ fn group_offsets_table(cluster_id: &ClusterId, group_name: &str) -> PreEscaped<S
         &api_url,
         cluster_id.name(),
         html! { tr { th { "Topic" } th { "Partition" } th { "Size" } th { "Low mark" } th { "High mark" }
-        th { "Current offset" } th { "Lag" } th { "Lag %" }} },
+        th { "Current offset" } th { "Lag" } th { "Lag %" } th { "Stored in" } } },
     )
 }
 
+fn group_acls_table(cluster_id: &ClusterId, group_name: &str) -> PreEscaped<String> {
+    let api_url = format!("/api/clusters/{}/groups/{}/acls", cluster_id, group_name);
+    layout::datatable_ajax(
+        "acls-ajax",
+        &api_url,
+        cluster_id.name(),
+        html! { tr { th { "Principal" } th { "Operation" } th { "Permission" } th { "Host" } th { "Resource pattern" } } },
+    )
+}
+
+fn offset_reset_form(
+    cluster_id: &ClusterId,
+    group_name: &str,
+    topics: &[String],
+) -> PreEscaped<String> {
+    let api_url = format!("/api/clusters/{}/groups/{}/reset", cluster_id, group_name);
+    html! {
+        form class="form-inline offset-reset" data-url=(api_url) {
+            div class="form-group" {
+                label { "Topic " }
+                select class="form-control" name="topic" {
+                    @for topic in topics {
+                        option value=(topic) { (topic) }
+                    }
+                }
+            }
+            div class="form-group" {
+                label { " Partition " }
+                input class="form-control" type="number" name="partition" placeholder="All" {}
+            }
+            div class="form-group" {
+                label { " Reset to " }
+                select class="form-control" name="strategy" {
+                    option value="earliest" { "Earliest" }
+                    option value="latest" { "Latest" }
+                    option value="timestamp" { "Timestamp (ms)" }
+                    option value="offset" { "Offset" }
+                    option value="shift" { "Shift by" }
+                }
+            }
+            div class="form-group" {
+                input class="form-control" type="number" name="value" placeholder="Value" {}
+            }
+            " "
+            button type="button" class="btn btn-default offset-reset-preview" { "Preview" }
+            " "
+            button type="button" class="btn btn-danger offset-reset-execute" { "Reset" }
+        }
+        div class="offset-reset-result" style="margin-top: 10px" {}
+    }
+}
+
 #[get("/clusters/<cluster_id>/groups/<group_name>")]
-pub fn group_page(cluster_id: ClusterId, group_name: &RawStr, cache: State<Cache>) -> Markup {
+pub fn group_page(
+    cluster_id: ClusterId,
+    group_name: &RawStr,
+    cache: State<Cache>,
+    config: State<Config>,
+) -> Markup {
     if cache.brokers.get(&cluster_id).is_none() {
         return pages::warning_page(group_name, "The specified cluster doesn't exist.");
     }
@@ -43,6 +103,22 @@ pub fn group_page(cluster_id: ClusterId, group_name: &RawStr, cache: State<Cache
         None => "Not registered".to_string(),
     };
 
+    let enable_admin = config
+        .cluster(&cluster_id)
+        .map(|cluster_config| cluster_config.enable_admin)
+        .unwrap_or(false);
+    let has_zookeeper = config
+        .cluster(&cluster_id)
+        .map(|cluster_config| cluster_config.zookeeper.is_some())
+        .unwrap_or(false);
+    let mut topics = cache
+        .offsets
+        .filter_clone_k(|&(ref c, ref g, _)| c == &cluster_id && g == group_name.as_str())
+        .into_iter()
+        .map(|(_, _, topic)| topic)
+        .collect::<Vec<_>>();
+    topics.sort();
+
     let cluster_link = format!("/clusters/{}/", cluster_id.name());
     let content = html! {
         h3 style="margin-top: 0px" { "Information" }
@@ -55,6 +131,17 @@ pub fn group_page(cluster_id: ClusterId, group_name: &RawStr, cache: State<Cache
         div { (group_members_table(&cluster_id, group_name)) }
         h3 { "Offsets" }
         div { (group_offsets_table(&cluster_id, group_name)) }
+        h3 { "ACLs" }
+        @if has_zookeeper {
+            div { (group_acls_table(&cluster_id, group_name)) }
+        } @else {
+            p { "ACLs are read from ZooKeeper, which is not configured for this cluster." }
+        }
+        @if enable_admin && !topics.is_empty() {
+            h3 { "Reset offsets" }
+            p { "Offsets can only be reset while the group has no active members." }
+            (offset_reset_form(&cluster_id, group_name, &topics))
+        }
     };
 
     layout::page(&format!("Group: {}", group_name), content)
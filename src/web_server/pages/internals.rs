@@ -31,6 +31,15 @@ fn offsets_table() -> PreEscaped<String> {
     )
 }
 
+fn zk_offsets_table() -> PreEscaped<String> {
+    layout::datatable_ajax(
+        "internals-cache-zk-offsets-ajax",
+        "/api/internals/cache/zk_offsets",
+        "",
+        html! { tr { th { "Cluster id" } th { "Consumer group" } th { "Topic name" } th { "Offsets" } } },
+    )
+}
+
 fn cache_description_table(name: &str, key: &str, value: &str, count: usize) -> PreEscaped<String> {
     html! {
         table style="margin-top: 10px; margin-bottom: 10px" {
@@ -67,6 +76,9 @@ pub fn caches_page(cache: State<Cache>) -> Markup {
         h3 { "Offsets" }
         (cache_description_table("OffsetsCache", "(ClusterId, GroupName, TopicName)", "Vec<i64>", cache.offsets.keys().len()))
         div { (offsets_table()) }
+        h3 { "ZooKeeper offsets" }
+        (cache_description_table("OffsetsCache", "(ClusterId, GroupName, TopicName)", "Vec<i64>", cache.zk_offsets.keys().len()))
+        div { (zk_offsets_table()) }
     };
     layout::page("Caches", content)
 }
@@ -88,3 +100,24 @@ pub fn live_consumers_page() -> Markup {
     };
     layout::page("Live consumers", content)
 }
+
+fn offset_consumers_table() -> PreEscaped<String> {
+    layout::datatable_ajax(
+        "internals-offset-consumers-ajax",
+        "/api/internals/offset_consumers",
+        "",
+        html! { tr { th { "Cluster id" } th { "State" } th { "Restarts" }
+        th data-toggle="tooltip" data-container="body"
+            title="Messages behind the end of __consumer_offsets" { "Lag" }
+        th { "Last cache update" } th { "Last error" } } },
+    )
+}
+
+#[get("/internals/offset_consumers")]
+pub fn offset_consumers_page() -> Markup {
+    let content = html! {
+        h3 style="margin-top: 0px" { "Consumers of __consumer_offsets" }
+        div { (offset_consumers_table()) }
+    };
+    layout::page("Offset consumers", content)
+}
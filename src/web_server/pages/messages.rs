@@ -0,0 +1,247 @@
+use maud::{html, Markup, PreEscaped};
+use rocket::http::RawStr;
+use rocket::State;
+
+use cache::Cache;
+use config::Config;
+use decoders::{DecodedPayload, TopicDecoder};
+use messages::{read_message, read_messages, BrowsedMessage, PAGE_SIZE};
+use metadata::ClusterId;
+use utils::format_millis;
+use web_server::pages;
+use web_server::view::layout;
+
+/// Payloads longer than this are truncated in the message list.
+const MAX_PAYLOAD_PREVIEW: usize = 1024;
+
+fn message_url(cluster_id: &ClusterId, topic: &str, partition: i32, offset: i64) -> String {
+    format!(
+        "/clusters/{}/topics/{}/{}/{}",
+        cluster_id, topic, partition, offset
+    )
+}
+
+fn browser_url(cluster_id: &ClusterId, topic: &str, partition: i32, offset: i64) -> String {
+    format!(
+        "/clusters/{}/topics/{}/messages?partition={}&offset={}",
+        cluster_id, topic, partition, offset
+    )
+}
+
+fn format_bytes_lossy(bytes: &Option<Vec<u8>>) -> String {
+    match *bytes {
+        Some(ref bytes) => String::from_utf8_lossy(bytes).into_owned(),
+        None => "N/A".to_owned(),
+    }
+}
+
+fn format_headers(headers: &[(String, Vec<u8>)]) -> String {
+    headers
+        .iter()
+        .map(|&(ref name, ref value)| format!("{}: {}", name, String::from_utf8_lossy(value)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn decode_payload(decoder: &TopicDecoder, payload: &Option<Vec<u8>>) -> DecodedPayload {
+    match *payload {
+        Some(ref payload) => decoder.decode(payload),
+        None => DecodedPayload {
+            decoder: "none",
+            text: "N/A".to_owned(),
+        },
+    }
+}
+
+fn format_timestamp(message: &BrowsedMessage) -> String {
+    match message.timestamp {
+        Some(timestamp) => format_millis(timestamp),
+        None => "N/A".to_owned(),
+    }
+}
+
+fn truncate(text: String) -> String {
+    if text.chars().count() > MAX_PAYLOAD_PREVIEW {
+        format!(
+            "{}...",
+            text.chars().take(MAX_PAYLOAD_PREVIEW).collect::<String>()
+        )
+    } else {
+        text
+    }
+}
+
+/// Form to jump to an offset of a partition, shown by the topic page and the browser page.
+pub fn message_browser_form(
+    cluster_id: &ClusterId,
+    topic: &str,
+    partitions: &[i32],
+    selected: Option<i32>,
+) -> PreEscaped<String> {
+    html! {
+        form class="form-inline" method="GET" action=(format!("/clusters/{}/topics/{}/messages", cluster_id, topic)) {
+            div class="form-group" {
+                label { "Partition " }
+                select class="form-control" name="partition" {
+                    @for &partition in partitions {
+                        @if Some(partition) == selected {
+                            option value=(partition) selected="" { (partition) }
+                        } @else {
+                            option value=(partition) { (partition) }
+                        }
+                    }
+                }
+            }
+            " "
+            div class="form-group" {
+                label { " Offset " }
+                input class="form-control" type="number" name="offset" placeholder="Latest" {}
+            }
+            " "
+            button type="submit" class="btn btn-default" { "Browse" }
+        }
+    }
+}
+
+#[get("/clusters/<cluster_id>/topics/<topic_name>/messages?<partition>&<offset>")]
+pub fn message_browser_page(
+    cluster_id: ClusterId,
+    topic_name: &RawStr,
+    partition: Option<i32>,
+    offset: Option<i64>,
+    cache: State<Cache>,
+    config: State<Config>,
+) -> Markup {
+    let title = format!("Messages: {}", topic_name);
+    let cluster_config = match config.cluster(&cluster_id) {
+        Some(cluster_config) if cluster_config.enable_tailing => cluster_config,
+        Some(_) => {
+            return pages::warning_page(&title, "Reading messages is disabled in this cluster.")
+        }
+        None => return pages::warning_page(&title, "The specified cluster doesn't exist."),
+    };
+    let partitions = match cache
+        .topics
+        .get(&(cluster_id.clone(), topic_name.to_string()))
+    {
+        Some(partitions) => partitions.iter().map(|p| p.id).collect::<Vec<_>>(),
+        None => return pages::warning_page(&title, "The specified topic doesn't exist."),
+    };
+    let partition = partition.unwrap_or(0);
+    if !partitions.contains(&partition) {
+        return pages::warning_page(&title, "The specified partition doesn't exist.");
+    }
+
+    let page = match read_messages(cluster_config, topic_name, partition, offset, PAGE_SIZE) {
+        Ok(page) => page,
+        Err(e) => {
+            format_error_chain!(e);
+            return pages::warning_page(&title, "Failed to read messages from the partition.");
+        }
+    };
+
+    let decoder = TopicDecoder::new(cluster_config, topic_name);
+    let topic_link = format!("/clusters/{}/topics/{}", cluster_id, topic_name);
+    let content = html! {
+        h3 style="margin-top: 0px" { "Information" }
+        dl class="dl-horizontal" {
+            dt { "Cluster name" } dd { a href=(format!("/clusters/{}/", cluster_id)) { (cluster_id) } }
+            dt { "Topic name" } dd { a href=(topic_link) { (topic_name) } }
+            dt { "Partition" } dd { (partition) }
+            dt { "Watermarks" } dd { (format!("{} - {}", page.low, page.high)) }
+        }
+        (message_browser_form(&cluster_id, topic_name, &partitions, Some(partition)))
+        ul class="pager" {
+            @if let Some(previous) = page.previous_offset() {
+                li class="previous" { a href=(browser_url(&cluster_id, topic_name, partition, previous)) { "Previous" } }
+            }
+            @if let Some(next) = page.next_offset() {
+                li class="next" { a href=(browser_url(&cluster_id, topic_name, partition, next)) { "Next" } }
+            }
+        }
+        @if page.messages.is_empty() {
+            p { "No messages" }
+        } @else {
+            table class="table table-striped table-bordered" {
+                thead { tr { th { "Offset" } th { "Timestamp" } th { "Timestamp type" } th { "Key" } th { "Headers" } th { "Payload" } } }
+                tbody {
+                    @for message in &page.messages {
+                        tr {
+                            td { a href=(message_url(&cluster_id, topic_name, partition, message.offset)) { (message.offset) } }
+                            td { (format_timestamp(message)) }
+                            td { (message.timestamp_type) }
+                            td { (format_bytes_lossy(&message.key)) }
+                            td { (format_headers(&message.headers)) }
+                            td class="message-payload" { (truncate(decode_payload(&decoder, &message.payload).text)) }
+                        }
+                    }
+                }
+            }
+        }
+    };
+    layout::page(&title, content)
+}
+
+#[get("/clusters/<cluster_id>/topics/<topic_name>/<partition>/<offset>")]
+pub fn message_page(
+    cluster_id: ClusterId,
+    topic_name: &RawStr,
+    partition: i32,
+    offset: i64,
+    config: State<Config>,
+) -> Markup {
+    let title = format!("Message: {}/{}/{}", topic_name, partition, offset);
+    let cluster_config = match config.cluster(&cluster_id) {
+        Some(cluster_config) if cluster_config.enable_tailing => cluster_config,
+        Some(_) => {
+            return pages::warning_page(&title, "Reading messages is disabled in this cluster.")
+        }
+        None => return pages::warning_page(&title, "The specified cluster doesn't exist."),
+    };
+    let message = match read_message(cluster_config, topic_name, partition, offset) {
+        Ok(Some(message)) => message,
+        Ok(None) => return pages::warning_page(&title, "The message doesn't exist."),
+        Err(e) => {
+            format_error_chain!(e);
+            return pages::warning_page(&title, "Failed to read the message.");
+        }
+    };
+
+    let payload = decode_payload(
+        &TopicDecoder::new(cluster_config, topic_name),
+        &message.payload,
+    );
+    let topic_link = format!("/clusters/{}/topics/{}", cluster_id, topic_name);
+    let content = html! {
+        h3 style="margin-top: 0px" { "Information" }
+        dl class="dl-horizontal" {
+            dt { "Cluster name" } dd { a href=(format!("/clusters/{}/", cluster_id)) { (cluster_id) } }
+            dt { "Topic name" } dd { a href=(topic_link) { (topic_name) } }
+            dt { "Partition" } dd { (partition) }
+            dt { "Offset" } dd {
+                (offset) " "
+                a href=(browser_url(&cluster_id, topic_name, partition, offset)) { "(browse from here)" }
+            }
+            dt { "Timestamp" } dd { (format_timestamp(&message)) }
+            dt { "Timestamp type" } dd { (message.timestamp_type) }
+            dt { "Key" } dd { (format_bytes_lossy(&message.key)) }
+            dt { "Decoder" } dd { (payload.decoder) }
+        }
+        h3 { "Headers" }
+        @if message.headers.is_empty() {
+            p { "No headers" }
+        } @else {
+            table class="table table-striped table-bordered" {
+                thead { tr { th { "Name" } th { "Value" } } }
+                tbody {
+                    @for &(ref name, ref value) in &message.headers {
+                        tr { td { (name) } td { (String::from_utf8_lossy(value)) } }
+                    }
+                }
+            }
+        }
+        h3 { "Payload" }
+        pre style="white-space: pre-wrap; word-break: break-all" { (payload.text) }
+    };
+    layout::page(&title, content)
+}
@@ -3,8 +3,10 @@ pub mod clusters;
 pub mod error_defaults;
 pub mod group;
 pub mod internals;
+pub mod messages;
 pub mod omnisearch;
 pub mod topic;
+pub mod zookeeper;
 
 pub use self::cluster::cluster_page;
 pub use self::clusters::clusters_page;
@@ -1,7 +1,8 @@
-use maud::{html, Markup};
+use maud::{html, Markup, PreEscaped};
 use rocket::http::uri::Uri;
 use rocket::request::{FromQuery, Query};
 
+use metrics::RateWindow;
 use web_server::view::layout;
 
 #[derive(Debug)]
@@ -32,27 +33,36 @@ impl<'q> FromQuery<'q> for OmnisearchFormParams {
 
 #[get("/omnisearch")]
 pub fn omnisearch() -> Markup {
-    omnisearch_p(OmnisearchFormParams {
-        string: "".to_owned(),
-        regex: false,
-    })
+    omnisearch_p(
+        None,
+        OmnisearchFormParams {
+            string: "".to_owned(),
+            regex: false,
+        },
+    )
 }
 
-#[get("/omnisearch?<search..>")]
-pub fn omnisearch_p(search: OmnisearchFormParams) -> Markup {
+#[get("/omnisearch?<window>&<search..>")]
+pub fn omnisearch_p(window: Option<RateWindow>, search: OmnisearchFormParams) -> Markup {
+    let window = window.unwrap_or_default();
     let search_form =
         layout::search_form("/omnisearch", "Omnisearch", &search.string, search.regex);
-    let api_url = format!(
-        "/api/search/topic?string={}&regex={}",
+    let page_url = format!(
+        "/omnisearch?string={}&regex={}",
         &search.string, search.regex
     );
+    let api_url = format!(
+        "/api/search/topic?string={}&regex={}&window={}",
+        &search.string, search.regex, window
+    );
+    let title = format!("Average over the last {}", window.description());
     let topics = layout::datatable_ajax(
         "topic-search-ajax",
         &api_url,
         "",
         html! { tr { th { "Cluster name" } th { "Topic name" } th { "#Partitions" } th { "Status" }
-             th data-toggle="tooltip" data-container="body" title="Average over the last 15 minutes" { "Byte rate" }
-             th data-toggle="tooltip" data-container="body" title="Average over the last 15 minutes" { "Msg rate" }
+             th data-toggle="tooltip" data-container="body" title=(title) { "Byte rate" }
+             th data-toggle="tooltip" data-container="body" title=(title) { "Msg rate" }
         }},
     );
     let api_url = format!(
@@ -65,6 +75,7 @@ pub fn omnisearch_p(search: OmnisearchFormParams) -> Markup {
         "",
         html! { tr { th { "Cluster" } th { "Group name" } th { "Status" } th { "Registered members" } th { "Stored topic offsets" } } },
     );
+    let principals = principal_search_table(&search);
 
     layout::page(
         "Omnisearch",
@@ -72,12 +83,17 @@ pub fn omnisearch_p(search: OmnisearchFormParams) -> Markup {
             (search_form)
             @if !search.string.is_empty() {
                 h3 { "Topics" }
+                p { "Rate window: " (layout::rate_window_selector(&page_url, window)) }
                 (topics)
             }
             @if !search.string.is_empty() {
                 h3 { "Consumers" }
                 (consumers)
             }
+            @if !search.string.is_empty() {
+                h3 { "Principals" }
+                (principals)
+            }
         },
     )
 }
@@ -119,26 +135,32 @@ pub fn consumer_search_p(search: OmnisearchFormParams) -> Markup {
 
 #[get("/topics")]
 pub fn topic_search() -> Markup {
-    topic_search_p(OmnisearchFormParams {
-        string: "".to_owned(),
-        regex: false,
-    })
+    topic_search_p(
+        None,
+        OmnisearchFormParams {
+            string: "".to_owned(),
+            regex: false,
+        },
+    )
 }
 
-#[get("/topics?<search..>")]
-pub fn topic_search_p(search: OmnisearchFormParams) -> Markup {
+#[get("/topics?<window>&<search..>")]
+pub fn topic_search_p(window: Option<RateWindow>, search: OmnisearchFormParams) -> Markup {
+    let window = window.unwrap_or_default();
     let search_form = layout::search_form("/topics", "Topic name", &search.string, search.regex);
+    let page_url = format!("/topics?string={}&regex={}", &search.string, search.regex);
     let api_url = format!(
-        "/api/search/topic?string={}&regex={}",
-        &search.string, search.regex
+        "/api/search/topic?string={}&regex={}&window={}",
+        &search.string, search.regex, window
     );
+    let title = format!("Average over the last {}", window.description());
     let results = layout::datatable_ajax(
         "topic-search-ajax",
         &api_url,
         "",
         html! { tr { th { "Cluster name" } th { "Topic name" } th { "#Partitions" } th { "Status" }
-             th data-toggle="tooltip" data-container="body" title="Average over the last 15 minutes" { "Byte rate" }
-             th data-toggle="tooltip" data-container="body" title="Average over the last 15 minutes" { "Msg rate" }
+             th data-toggle="tooltip" data-container="body" title=(title) { "Byte rate" }
+             th data-toggle="tooltip" data-container="body" title=(title) { "Msg rate" }
         }},
     );
 
@@ -148,6 +170,46 @@ pub fn topic_search_p(search: OmnisearchFormParams) -> Markup {
             (search_form)
             @if !search.string.is_empty() {
                 h3 { "Search results" }
+                p { "Rate window: " (layout::rate_window_selector(&page_url, window)) }
+                (results)
+            }
+        },
+    )
+}
+
+fn principal_search_table(search: &OmnisearchFormParams) -> PreEscaped<String> {
+    let api_url = format!(
+        "/api/search/principal?string={}&regex={}",
+        &search.string, search.regex
+    );
+    layout::datatable_ajax(
+        "principal-search-ajax",
+        &api_url,
+        "",
+        html! { tr { th { "Cluster" } th { "Principal" } th { "Resource pattern" } th { "Operation" } th { "Permission" } th { "Host" } } },
+    )
+}
+
+#[get("/principals")]
+pub fn principal_search() -> Markup {
+    principal_search_p(OmnisearchFormParams {
+        string: "".to_owned(),
+        regex: false,
+    })
+}
+
+#[get("/principals?<search..>")]
+pub fn principal_search_p(search: OmnisearchFormParams) -> Markup {
+    let search_form = layout::search_form("/principals", "Principal", &search.string, search.regex);
+    let results = principal_search_table(&search);
+
+    layout::page(
+        "Principal search",
+        html! {
+            (search_form)
+            @if !search.string.is_empty() {
+                h3 { "Search results" }
+                p { "ACLs granted or denied to the matching principals." }
                 (results)
             }
         },
@@ -5,7 +5,9 @@ use rocket::http::RawStr;
 use cache::Cache;
 use config::Config;
 use metadata::ClusterId;
+use metrics::RateWindow;
 use web_server::pages;
+use web_server::pages::messages::message_browser_form;
 use web_server::view::layout;
 
 use rocket::State;
@@ -19,7 +21,9 @@ fn topic_table(cluster_id: &ClusterId, topic_name: &str) -> PreEscaped<String> {
         "topology-ajax",
         &api_url,
         cluster_id.name(),
-        html! { tr { th { "Id" } th { "Size" } th { "Leader" } th { "Replicas" } th { "ISR" } th { "Status" } } },
+        html! { tr { th { "Id" } th { "Size" } th { "Leader" } th { "Replicas" } th { "ISR" }
+        th data-toggle="tooltip" data-container="body" title="Size of the follower replicas, in the order of the replicas list" { "Follower sizes" }
+        th { "Diverging replicas" } th { "Status" } } },
     )
 }
 
@@ -33,6 +37,65 @@ fn consumer_groups_table(cluster_id: &ClusterId, topic_name: &str) -> PreEscaped
     )
 }
 
+fn topic_acls_table(cluster_id: &ClusterId, topic_name: &str) -> PreEscaped<String> {
+    let api_url = format!("/api/clusters/{}/topics/{}/acls", cluster_id, topic_name);
+    layout::datatable_ajax(
+        "acls-ajax",
+        &api_url,
+        cluster_id.name(),
+        html! { tr { th { "Principal" } th { "Operation" } th { "Permission" } th { "Host" } th { "Resource pattern" } } },
+    )
+}
+
+fn message_search_form(cluster_id: &ClusterId, topic: &str) -> PreEscaped<String> {
+    let api_url = format!("/api/clusters/{}/topics/{}/search", cluster_id, topic);
+    html! {
+        form class="form-inline message-search" data-url=(api_url) data-cluster=(cluster_id) data-topic=(topic) {
+            div class="form-group" {
+                select class="form-control" name="kind" {
+                    option value="key" { "Key" }
+                    option value="substring" { "Payload substring" }
+                    option value="regex" { "Payload regex" }
+                    option value="jsonpath" { "JSON path" }
+                }
+            }
+            " "
+            div class="form-group" {
+                input class="form-control" type="text" name="pattern" placeholder="Pattern" {}
+            }
+            " "
+            div class="form-group" {
+                input class="form-control" type="text" name="value" placeholder="JSON value (optional)" {}
+            }
+            div class="form-group" {
+                label { " In " }
+                select class="form-control" name="range" {
+                    option value="last" { "Last N messages per partition" }
+                    option value="offsets" { "Offsets" }
+                    option value="time" { "Time window (ms)" }
+                }
+            }
+            " "
+            div class="form-group" {
+                input class="form-control" type="number" name="from" placeholder="N / from" {}
+            }
+            " "
+            div class="form-group" {
+                input class="form-control" type="number" name="to" placeholder="To (optional)" {}
+            }
+            " "
+            button type="button" class="btn btn-default message-search-start" { "Search" }
+            " "
+            button type="button" class="btn btn-default message-search-cancel" { "Cancel" }
+        }
+        div class="message-search-progress" style="margin-top: 10px" {}
+        table class="table table-striped table-bordered message-search-results" style="display: none" {
+            thead { tr { th { "Partition" } th { "Offset" } th { "Timestamp" } th { "Key" } th { "Payload" } } }
+            tbody {}
+        }
+    }
+}
+
 fn graph_link(graph_url: &str, topic: &str) -> PreEscaped<String> {
     let url = graph_url.replace("{%s}", topic);
     html! {
@@ -40,22 +103,57 @@ fn graph_link(graph_url: &str, topic: &str) -> PreEscaped<String> {
     }
 }
 
-fn topic_tailer_panel(cluster_id: &ClusterId, topic: &str, tailer_id: u64) -> PreEscaped<String> {
+fn topic_tailer_panel(
+    cluster_id: &ClusterId,
+    topic: &str,
+    tailer_id: u64,
+    stream_port: Option<u16>,
+) -> PreEscaped<String> {
+    // An empty port makes the tailer poll the API.
+    let stream_port = stream_port.map(|port| port.to_string()).unwrap_or_default();
     let panel_head = html! {
         i class="fa fa-align-left fa-fw" {} "Messages"
     };
     let panel_body = html! {
-        div class="topic_tailer" data-cluster=(cluster_id) data-topic=(topic) data-tailer=(tailer_id) {
+        form class="form-inline tailer-start" style="margin-bottom: 10px" {
+            div class="form-group" {
+                label { "Start from " }
+                select class="form-control" name="start" {
+                    option value="latest" { "Latest" }
+                    option value="earliest" { "Earliest" }
+                    option value="last" { "Last N messages per partition" }
+                    option value="timestamp" { "Timestamp (ms)" }
+                    option value="offsets" { "Offsets (partition:offset,...)" }
+                }
+            }
+            " "
+            div class="form-group" {
+                input class="form-control" type="text" name="value" placeholder="Value" {}
+            }
+            " "
+            div class="form-group" {
+                label { " Header " }
+                input class="form-control" type="text" name="header" placeholder="Name" {}
+            }
+            " "
+            div class="form-group" {
+                input class="form-control" type="text" name="header_value" placeholder="Any value" {}
+            }
+            " "
+            button type="button" class="btn btn-default tailer-restart" { "Restart" }
+        }
+        div class="topic_tailer" data-cluster=(cluster_id) data-topic=(topic) data-tailer=(tailer_id) data-stream-port=(stream_port) {
             "Tailing recent messages..."
         }
     };
     layout::panel(panel_head, panel_body)
 }
 
-#[get("/clusters/<cluster_id>/topics/<topic_name>")]
+#[get("/clusters/<cluster_id>/topics/<topic_name>?<window>")]
 pub fn topic_page(
     cluster_id: ClusterId,
     topic_name: &RawStr,
+    window: Option<RateWindow>,
     cache: State<Cache>,
     config: State<Config>,
 ) -> Markup {
@@ -78,12 +176,24 @@ pub fn topic_page(
         .get(&cluster_id)
         .expect("Cluster should exist"); // TODO: handle better
 
-    let metrics = cache
+    let topic_metrics = cache
         .metrics
         .get(&(cluster_id.clone(), topic_name.to_string()))
-        .unwrap_or_default()
-        .aggregate_broker_metrics();
+        .unwrap_or_default();
+    let metrics = topic_metrics.aggregate_broker_metrics();
+    let diverging_partitions = partitions
+        .iter()
+        .filter(|p| {
+            topic_metrics
+                .follower_sizes(p)
+                .iter()
+                .any(|replica| replica.is_divergent(cluster_config.replica_size_divergence))
+        })
+        .count();
 
+    let partition_ids = partitions.iter().map(|p| p.id).collect::<Vec<_>>();
+    let window = window.unwrap_or_default();
+    let page_url = format!("/clusters/{}/topics/{}", cluster_id, topic_name);
     let cluster_link = format!("/clusters/{}/", cluster_id.name());
     let content = html! {
         h3 style="margin-top: 0px" {"General information"}
@@ -92,8 +202,15 @@ pub fn topic_page(
             dt { "Topic name " dd { (topic_name) } }
             dt { "Number of partitions " dd { (partitions.len()) } }
             dt { "Number of replicas " dd { (partitions[0].replicas.len()) } }
-            dt { "Traffic last 15 minutes" }
-            dd { ( format!("{:.1}   KB/s {:.0} msg/s", metrics.b_rate_15 / 1000f64, metrics.m_rate_15)) }
+            dt { (format!("Traffic last {}", window.description())) }
+            dd { ( format!("{:.1}   KB/s {:.0} msg/s", metrics.b_rate(window) / 1000f64, metrics.m_rate(window))) }
+            dt { "Rate window" } dd { (layout::rate_window_selector(&page_url, window)) }
+            dt { "Replica sizes" }
+            @if diverging_partitions == 0 {
+                dd { "No diverging replicas" }
+            } @else {
+                dd { (format!("{} partitions with followers differing from the leader by more than {:.0}%", diverging_partitions, cluster_config.replica_size_divergence)) }
+            }
             @if cluster_config.graph_url.is_some() {
                 dt { "Traffic chart" } dd { (graph_link(cluster_config.graph_url.as_ref().unwrap(), topic_name)) }
             }
@@ -102,12 +219,30 @@ pub fn topic_page(
         (topic_table(&cluster_id, topic_name))
         h3 {"Consumer groups"}
         (consumer_groups_table(&cluster_id, topic_name))
+        h3 { "ACLs" }
+        @if cluster_config.zookeeper.is_some() {
+            (topic_acls_table(&cluster_id, topic_name))
+        } @else {
+            p { "ACLs are read from ZooKeeper, which is not configured for this cluster." }
+        }
         h3 { "Tailer" }
         @if cluster_config.enable_tailing {
-            (topic_tailer_panel(&cluster_id, topic_name, random::<u64>()))
+            (topic_tailer_panel(&cluster_id, topic_name, random::<u64>(), config.stream_port))
         } @else {
             p { "Topic tailing is disabled in this cluster." }
         }
+        h3 { "Message browser" }
+        @if cluster_config.enable_tailing {
+            (message_browser_form(&cluster_id, topic_name, &partition_ids, None))
+        } @else {
+            p { "Reading messages is disabled in this cluster." }
+        }
+        h3 { "Message search" }
+        @if cluster_config.enable_tailing {
+            (message_search_form(&cluster_id, topic_name))
+        } @else {
+            p { "Reading messages is disabled in this cluster." }
+        }
     };
 
     layout::page(&format!("Topic: {}", topic_name), content)
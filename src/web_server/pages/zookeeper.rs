@@ -0,0 +1,162 @@
+use maud::{html, Markup, PreEscaped};
+use rocket::http::uri::Uri;
+use rocket::State;
+use serde_json::{self, Value};
+
+use cache::Cache;
+use config::Config;
+use metadata::ClusterId;
+use utils::format_millis;
+use web_server::pages;
+use web_server::view::layout;
+use zk::{is_znode_denied, normalize_znode_path, Znode, ZK_SESSIONS};
+
+use std::str;
+
+fn znode_url(cluster_id: &ClusterId, path: &str) -> String {
+    format!(
+        "/clusters/{}/zookeeper?path={}",
+        cluster_id,
+        Uri::percent_encode(path)
+    )
+}
+
+fn child_path(parent: &str, child: &str) -> String {
+    if parent == "/" {
+        format!("/{}", child)
+    } else {
+        format!("{}/{}", parent, child)
+    }
+}
+
+/// Pretty prints JSON data, and shows binary data as hex.
+fn format_data(data: &[u8]) -> String {
+    match str::from_utf8(data) {
+        Ok(text) => serde_json::from_str::<Value>(text)
+            .ok()
+            .and_then(|value| serde_json::to_string_pretty(&value).ok())
+            .unwrap_or_else(|| text.to_owned()),
+        Err(_) => data
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<Vec<_>>()
+            .join(" "),
+    }
+}
+
+fn breadcrumbs(cluster_id: &ClusterId, path: &str) -> PreEscaped<String> {
+    let mut ancestors = vec![("/".to_owned(), "/".to_owned())];
+    let mut current = String::new();
+    for name in path.split('/').filter(|name| !name.is_empty()) {
+        current = format!("{}/{}", current, name);
+        ancestors.push((current.clone(), name.to_owned()));
+    }
+    html! {
+        ol class="breadcrumb" {
+            @for (ancestor, name) in ancestors {
+                li { a href=(znode_url(cluster_id, &ancestor)) { (name) } }
+            }
+        }
+    }
+}
+
+fn stat_table(znode: &Znode) -> PreEscaped<String> {
+    let stat = &znode.stat;
+    html! {
+        dl class="dl-horizontal" {
+            dt { "Created" } dd { (format_millis(stat.ctime)) " (zxid " (format!("0x{:x}", stat.czxid)) ")" }
+            dt { "Modified" } dd { (format_millis(stat.mtime)) " (zxid " (format!("0x{:x}", stat.mzxid)) ")" }
+            dt { "Data version" } dd { (stat.version) }
+            dt { "Children version" } dd { (stat.cversion) }
+            dt { "ACL version" } dd { (stat.aversion) }
+            dt { "Data length" } dd { (stat.data_length) " bytes" }
+            dt { "Children" } dd { (stat.num_children) }
+            @if stat.ephemeral_owner != 0 {
+                dt { "Ephemeral owner" } dd { (format!("0x{:x}", stat.ephemeral_owner)) }
+            }
+        }
+    }
+}
+
+#[get("/clusters/<cluster_id>/zookeeper?<path>")]
+pub fn zookeeper_page(
+    cluster_id: ClusterId,
+    path: Option<String>,
+    cache: State<Cache>,
+    config: State<Config>,
+) -> Markup {
+    let cluster_config = match config.cluster(&cluster_id) {
+        Some(cluster_config) => cluster_config,
+        None => {
+            return pages::warning_page(
+                &format!("ZooKeeper: {}", cluster_id),
+                "The specified cluster doesn't exist.",
+            )
+        }
+    };
+    let zookeeper = match cluster_config.zookeeper {
+        Some(ref zookeeper) => zookeeper,
+        None => {
+            return pages::warning_page(
+                &format!("ZooKeeper: {}", cluster_id),
+                "ZooKeeper is not configured for this cluster.",
+            )
+        }
+    };
+    let path = match normalize_znode_path(path.as_ref().map(String::as_str).unwrap_or("/")) {
+        Some(path) => path,
+        None => return pages::warning_page("ZooKeeper", "Invalid znode path."),
+    };
+    if is_znode_denied(&path, &config.zk_browser_denylist) {
+        return pages::warning_page(&path, "This znode is hidden by the configuration.");
+    }
+
+    let znode = ZK_SESSIONS
+        .get_or_init(&cluster_id, cluster_config, &cache)
+        .and_then(|zk| zk.znode(&path));
+    let znode = match znode {
+        Ok(Some(znode)) => znode,
+        Ok(None) => return pages::warning_page(&path, "The znode doesn't exist."),
+        Err(e) => {
+            format_error_chain!(e);
+            return pages::warning_page(&path, "Failed to read the znode from ZooKeeper.");
+        }
+    };
+
+    let (visible, hidden): (Vec<String>, Vec<String>) = znode
+        .children
+        .iter()
+        .map(|child| child_path(&path, child))
+        .partition(|child| !is_znode_denied(child, &config.zk_browser_denylist));
+    let cluster_link = format!("/clusters/{}/", cluster_id.name());
+    let content = html! {
+        h3 style="margin-top: 0px" { "Information" }
+        dl class="dl-horizontal" {
+            dt { "Cluster name" } dd { a href=(cluster_link) { (cluster_id) } }
+            dt { "Zookeeper" } dd { (zookeeper) }
+        }
+        (breadcrumbs(&cluster_id, &path))
+        h3 { "Data" }
+        @if znode.data.is_empty() {
+            p { "No data" }
+        } @else {
+            pre { (format_data(&znode.data)) }
+        }
+        h3 { "Stat" }
+        (stat_table(&znode))
+        h3 { "Children" }
+        @if visible.is_empty() {
+            p { "No children" }
+        } @else {
+            ul {
+                @for child in &visible {
+                    li { a href=(znode_url(&cluster_id, child)) { (child.rsplit('/').next().unwrap_or("")) } }
+                }
+            }
+        }
+        @if !hidden.is_empty() {
+            p { (format!("{} children hidden by the configuration.", hidden.len())) }
+        }
+    };
+    layout::page(&format!("ZooKeeper: {}", path), content)
+}
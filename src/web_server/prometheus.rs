@@ -0,0 +1,416 @@
+use rocket::response::content;
+use rocket::State;
+
+use cache::Cache;
+use metadata::ClusterId;
+use metrics::RateWindow;
+use offsets::{OffsetStore, OFFSET_CONSUMERS};
+use stats::STATS;
+use throughput::WATERMARKS;
+
+use std::cmp;
+use std::collections::HashSet;
+use std::f64;
+use std::fmt::Write;
+
+/// Builds a response in the Prometheus text exposition format.
+struct PrometheusWriter {
+    output: String,
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_owned()
+    } else if value == f64::INFINITY {
+        "+Inf".to_owned()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_owned()
+    } else {
+        value.to_string()
+    }
+}
+
+impl PrometheusWriter {
+    fn new() -> PrometheusWriter {
+        PrometheusWriter {
+            output: String::new(),
+        }
+    }
+
+    fn header(&mut self, name: &str, metric_type: &str, help: &str) {
+        let _ = writeln!(self.output, "# HELP {} {}", name, help);
+        let _ = writeln!(self.output, "# TYPE {} {}", name, metric_type);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let labels = labels
+            .iter()
+            .map(|&(label, value)| format!("{}=\"{}\"", label, escape_label_value(value)))
+            .collect::<Vec<_>>()
+            .join(",");
+        let _ = writeln!(
+            self.output,
+            "{}{{{}}} {}",
+            name,
+            labels,
+            format_value(value)
+        );
+    }
+}
+
+fn write_topic_metrics(writer: &mut PrometheusWriter, cache: &Cache) {
+    let metrics = cache.metrics.filter_clone(|_| true);
+
+    writer.header(
+        "kafka_view_broker_bytes_in_per_second",
+        "gauge",
+        "Incoming byte rate of the broker, averaged over the window",
+    );
+    for &((ref cluster_id, ref topic), ref topic_metrics) in &metrics {
+        if topic != "__TOTAL__" {
+            continue;
+        }
+        for (broker_id, broker_metrics) in &topic_metrics.brokers {
+            for &window in &RateWindow::all() {
+                writer.sample(
+                    "kafka_view_broker_bytes_in_per_second",
+                    &[
+                        ("cluster", cluster_id.name()),
+                        ("broker", &broker_id.to_string()),
+                        ("window", window.as_str()),
+                    ],
+                    broker_metrics.b_rate(window),
+                );
+            }
+        }
+    }
+
+    writer.header(
+        "kafka_view_broker_messages_in_per_second",
+        "gauge",
+        "Incoming message rate of the broker, averaged over the window",
+    );
+    for &((ref cluster_id, ref topic), ref topic_metrics) in &metrics {
+        if topic != "__TOTAL__" {
+            continue;
+        }
+        for (broker_id, broker_metrics) in &topic_metrics.brokers {
+            for &window in &RateWindow::all() {
+                writer.sample(
+                    "kafka_view_broker_messages_in_per_second",
+                    &[
+                        ("cluster", cluster_id.name()),
+                        ("broker", &broker_id.to_string()),
+                        ("window", window.as_str()),
+                    ],
+                    broker_metrics.m_rate(window),
+                );
+            }
+        }
+    }
+
+    writer.header(
+        "kafka_view_topic_bytes_in_per_second",
+        "gauge",
+        "Incoming byte rate of the topic, averaged over the window",
+    );
+    for &((ref cluster_id, ref topic), ref topic_metrics) in &metrics {
+        if topic == "__TOTAL__" {
+            continue;
+        }
+        let aggregated = topic_metrics.aggregate_broker_metrics();
+        for &window in &RateWindow::all() {
+            writer.sample(
+                "kafka_view_topic_bytes_in_per_second",
+                &[
+                    ("cluster", cluster_id.name()),
+                    ("topic", topic),
+                    ("window", window.as_str()),
+                ],
+                aggregated.b_rate(window),
+            );
+        }
+    }
+
+    writer.header(
+        "kafka_view_topic_messages_in_per_second",
+        "gauge",
+        "Incoming message rate of the topic, averaged over the window",
+    );
+    for &((ref cluster_id, ref topic), ref topic_metrics) in &metrics {
+        if topic == "__TOTAL__" {
+            continue;
+        }
+        let aggregated = topic_metrics.aggregate_broker_metrics();
+        for &window in &RateWindow::all() {
+            writer.sample(
+                "kafka_view_topic_messages_in_per_second",
+                &[
+                    ("cluster", cluster_id.name()),
+                    ("topic", topic),
+                    ("window", window.as_str()),
+                ],
+                aggregated.m_rate(window),
+            );
+        }
+    }
+}
+
+fn write_extra_metrics(writer: &mut PrometheusWriter, cache: &Cache) {
+    writer.header(
+        "kafka_view_jmx_metric",
+        "gauge",
+        "Additional broker metric read from the configured Jolokia MBeans",
+    );
+    for ((cluster_id, topic), topic_metrics) in cache.metrics.filter_clone(|_| true) {
+        for (broker_id, broker_metrics) in &topic_metrics.brokers {
+            for (metric, value) in &broker_metrics.extra {
+                writer.sample(
+                    "kafka_view_jmx_metric",
+                    &[
+                        ("cluster", cluster_id.name()),
+                        ("broker", &broker_id.to_string()),
+                        ("topic", &topic),
+                        ("metric", metric),
+                    ],
+                    *value,
+                );
+            }
+        }
+    }
+}
+
+fn write_partition_metrics(writer: &mut PrometheusWriter, cache: &Cache) {
+    let topics = cache.topics.filter_clone(|_| true);
+
+    writer.header(
+        "kafka_view_topic_partitions",
+        "gauge",
+        "Number of partitions of the topic",
+    );
+    for &((ref cluster_id, ref topic), ref partitions) in &topics {
+        writer.sample(
+            "kafka_view_topic_partitions",
+            &[("cluster", cluster_id.name()), ("topic", topic)],
+            partitions.len() as f64,
+        );
+    }
+
+    writer.header(
+        "kafka_view_topic_under_replicated_partitions",
+        "gauge",
+        "Number of partitions of the topic with replicas out of the ISR",
+    );
+    for &((ref cluster_id, ref topic), ref partitions) in &topics {
+        let under_replicated = partitions
+            .iter()
+            .filter(|p| p.isr.len() < p.replicas.len())
+            .count();
+        writer.sample(
+            "kafka_view_topic_under_replicated_partitions",
+            &[("cluster", cluster_id.name()), ("topic", topic)],
+            under_replicated as f64,
+        );
+    }
+
+    writer.header(
+        "kafka_view_partition_size_bytes",
+        "gauge",
+        "Size of the partition on the leader",
+    );
+    for &((ref cluster_id, ref topic), ref partitions) in &topics {
+        let topic_metrics = match cache.metrics.get(&(cluster_id.clone(), topic.clone())) {
+            Some(topic_metrics) => topic_metrics,
+            None => continue,
+        };
+        for p in partitions {
+            if let Some(size_bytes) = topic_metrics.partition_size(p.leader, p.id) {
+                writer.sample(
+                    "kafka_view_partition_size_bytes",
+                    &[
+                        ("cluster", cluster_id.name()),
+                        ("topic", topic),
+                        ("partition", &p.id.to_string()),
+                    ],
+                    size_bytes,
+                );
+            }
+        }
+    }
+}
+
+fn write_group_metrics(writer: &mut PrometheusWriter, cache: &Cache) {
+    writer.header(
+        "kafka_view_consumergroup_members",
+        "gauge",
+        "Number of members of the consumer group",
+    );
+    for ((cluster_id, group_name), group) in cache.groups.filter_clone(|_| true) {
+        writer.sample(
+            "kafka_view_consumergroup_members",
+            &[("cluster", cluster_id.name()), ("group", &group_name)],
+            group.members.len() as f64,
+        );
+    }
+
+    writer.header(
+        "kafka_view_consumergroup_lag",
+        "gauge",
+        "Number of messages between the committed offset and the high watermark",
+    );
+    let cluster_ids = cache
+        .offsets
+        .keys()
+        .into_iter()
+        .chain(cache.zk_offsets.keys())
+        .map(|(cluster_id, _, _)| cluster_id)
+        .collect::<HashSet<ClusterId>>();
+    for cluster_id in cluster_ids {
+        for (storage, (_, group, topic), partitions) in cache.offsets_by_cluster(&cluster_id) {
+            for (partition_id, &offset) in partitions.iter().enumerate() {
+                // The watermarks are sampled in the background: a scrape never waits for Kafka
+                let high = match WATERMARKS.high_watermark(&cluster_id, &topic, partition_id as i32)
+                {
+                    Some(high) if offset >= 0 => high,
+                    _ => continue,
+                };
+                writer.sample(
+                    "kafka_view_consumergroup_lag",
+                    &[
+                        ("cluster", cluster_id.name()),
+                        ("group", &group),
+                        ("topic", &topic),
+                        ("partition", &partition_id.to_string()),
+                        ("storage", &storage.to_string()),
+                    ],
+                    cmp::max(high - offset, 0) as f64,
+                );
+            }
+        }
+    }
+}
+
+fn write_internal_metrics(writer: &mut PrometheusWriter, cache: &Cache) {
+    writer.header(
+        "kafka_view_cache_items",
+        "gauge",
+        "Number of items in the kafka-view cache",
+    );
+    let cache_sizes = [
+        ("metrics", cache.metrics.keys().len()),
+        ("offsets", cache.offsets.keys().len()),
+        ("zk_offsets", cache.zk_offsets.keys().len()),
+        ("brokers", cache.brokers.keys().len()),
+        ("topics", cache.topics.keys().len()),
+        ("groups", cache.groups.keys().len()),
+        ("internal_offsets", cache.internal_offsets.keys().len()),
+        ("disk_usage", cache.disk_usage.keys().len()),
+        ("zk_state", cache.zk_state.keys().len()),
+        ("cluster_info", cache.cluster_info.keys().len()),
+        ("acls", cache.acls.keys().len()),
+        ("quotas", cache.quotas.keys().len()),
+    ];
+    for &(name, size) in &cache_sizes {
+        writer.sample("kafka_view_cache_items", &[("cache", name)], size as f64);
+    }
+
+    let fetches = STATS.fetches();
+    writer.header(
+        "kafka_view_fetch_duration_seconds",
+        "gauge",
+        "Duration of the last execution of the fetch task",
+    );
+    for &(ref task, ref stats) in &fetches {
+        let broker_id = task.broker_id.map(|id| id.to_string()).unwrap_or_default();
+        let duration = stats.last_duration.as_secs() as f64
+            + f64::from(stats.last_duration.subsec_nanos()) / 1e9;
+        writer.sample(
+            "kafka_view_fetch_duration_seconds",
+            &[
+                ("task", task.name),
+                ("cluster", task.cluster_id.name()),
+                ("broker", &broker_id),
+            ],
+            duration,
+        );
+    }
+    writer.header(
+        "kafka_view_fetch_executions_total",
+        "counter",
+        "Number of executions of the fetch task",
+    );
+    for &(ref task, ref stats) in &fetches {
+        let broker_id = task.broker_id.map(|id| id.to_string()).unwrap_or_default();
+        writer.sample(
+            "kafka_view_fetch_executions_total",
+            &[
+                ("task", task.name),
+                ("cluster", task.cluster_id.name()),
+                ("broker", &broker_id),
+            ],
+            stats.executions as f64,
+        );
+    }
+    writer.header(
+        "kafka_view_fetch_errors_total",
+        "counter",
+        "Number of failed executions of the fetch task",
+    );
+    for &(ref task, ref stats) in &fetches {
+        let broker_id = task.broker_id.map(|id| id.to_string()).unwrap_or_default();
+        writer.sample(
+            "kafka_view_fetch_errors_total",
+            &[
+                ("task", task.name),
+                ("cluster", task.cluster_id.name()),
+                ("broker", &broker_id),
+            ],
+            stats.errors as f64,
+        );
+    }
+
+    writer.header(
+        "kafka_view_offset_consumer_lag",
+        "gauge",
+        "Number of messages the offset consumer is behind __consumer_offsets",
+    );
+    for (cluster_id, status) in OFFSET_CONSUMERS.statuses() {
+        if let Some(lag) = status.lag {
+            writer.sample(
+                "kafka_view_offset_consumer_lag",
+                &[("cluster", cluster_id.name())],
+                lag as f64,
+            );
+        }
+    }
+    writer.header(
+        "kafka_view_offset_consumer_restarts_total",
+        "counter",
+        "Number of times the offset consumer was restarted",
+    );
+    for (cluster_id, status) in OFFSET_CONSUMERS.statuses() {
+        writer.sample(
+            "kafka_view_offset_consumer_restarts_total",
+            &[("cluster", cluster_id.name())],
+            f64::from(status.restarts),
+        );
+    }
+}
+
+#[get("/metrics")]
+pub fn metrics(cache: State<Cache>) -> content::Plain<String> {
+    let mut writer = PrometheusWriter::new();
+    write_topic_metrics(&mut writer, &cache);
+    write_extra_metrics(&mut writer, &cache);
+    write_partition_metrics(&mut writer, &cache);
+    write_group_metrics(&mut writer, &cache);
+    write_internal_metrics(&mut writer, &cache);
+    content::Plain(writer.output)
+}
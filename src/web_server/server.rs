@@ -1,7 +1,8 @@
 use rocket;
-use rocket::http::RawStr;
-use rocket::request::{FromParam, Request};
+use rocket::http::{RawStr, Status};
+use rocket::request::{self, FromFormValue, FromParam, FromRequest, Request};
 use rocket::response::{self, NamedFile, Redirect, Responder};
+use rocket::Outcome;
 use scheduled_executor::ThreadPoolExecutor;
 
 use cache::Cache;
@@ -9,9 +10,13 @@ use config::Config;
 use error::*;
 use live_consumer::{self, LiveConsumerStore};
 use metadata::ClusterId;
+use metrics::RateWindow;
+use search::{self, SearchJobStore};
 use utils::{GZip, RequestLogger};
 use web_server::api;
 use web_server::pages;
+use web_server::prometheus;
+use web_server::stream;
 
 use std;
 use std::path::{Path, PathBuf};
@@ -30,6 +35,53 @@ impl<'a> FromParam<'a> for ClusterId {
     }
 }
 
+// Make RateWindow a valid query parameter
+impl<'v> FromFormValue<'v> for RateWindow {
+    type Error = &'v RawStr;
+
+    fn from_form_value(form_value: &'v RawStr) -> std::result::Result<Self, Self::Error> {
+        RateWindow::parse(form_value.as_str()).ok_or(form_value)
+    }
+}
+
+/// Request guard of the requests that change the state of the clusters or of kafka-view: they
+/// can only be sent by the kafka-view pages, not by other web sites.
+pub struct SameOrigin;
+
+/// The `host[:port]` part of an origin or URL.
+fn url_authority(url: &str) -> &str {
+    let without_scheme = match url.find("://") {
+        Some(index) => &url[index + 3..],
+        None => url,
+    };
+    without_scheme.split('/').next().unwrap_or(without_scheme)
+}
+
+fn is_same_origin(origin: &str, host: &str) -> bool {
+    url_authority(origin).eq_ignore_ascii_case(host)
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for SameOrigin {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<SameOrigin, ()> {
+        let headers = request.headers();
+        // Some browsers only send the referer with same-origin requests
+        let origin = headers
+            .get_one("Origin")
+            .or_else(|| headers.get_one("Referer"));
+        match (origin, headers.get_one("Host")) {
+            (Some(origin), Some(host)) if is_same_origin(origin, host) => {
+                Outcome::Success(SameOrigin)
+            }
+            _ => {
+                warn!("Cross-origin request rejected: {}", request.uri());
+                Outcome::Failure((Status::Forbidden, ()))
+            }
+        }
+    }
+}
+
 #[get("/public/<file..>")]
 fn files(file: PathBuf) -> Option<CachedFile> {
     NamedFile::open(Path::new("resources/web_server/public/").join(file))
@@ -88,12 +140,17 @@ pub fn run_server(executor: &ThreadPoolExecutor, cache: Cache, config: &Config)
         .finalize()
         .chain_err(|| "Invalid rocket configuration")?;
 
+    let live_consumer_store = LiveConsumerStore::new(executor.clone());
+    stream::start_stream_server(config, live_consumer_store.clone())
+        .chain_err(|| "Failed to start the stream server")?;
+
     rocket::custom(rocket_config)
         .attach(GZip)
         .attach(RequestLogger)
         .manage(cache)
         .manage(config.clone())
-        .manage(LiveConsumerStore::new(executor.clone()))
+        .manage(live_consumer_store)
+        .manage(SearchJobStore::new(executor.clone()))
         .mount(
             "/",
             routes![
@@ -106,31 +163,71 @@ pub fn run_server(executor: &ThreadPoolExecutor, cache: Cache, config: &Config)
                 pages::group::group_page,
                 pages::internals::caches_page,
                 pages::internals::live_consumers_page,
+                pages::internals::offset_consumers_page,
+                pages::messages::message_browser_page,
+                pages::messages::message_page,
                 pages::omnisearch::consumer_search,
                 pages::omnisearch::consumer_search_p,
                 pages::omnisearch::omnisearch,
                 pages::omnisearch::omnisearch_p,
+                pages::omnisearch::principal_search,
+                pages::omnisearch::principal_search_p,
                 pages::omnisearch::topic_search,
                 pages::omnisearch::topic_search_p,
                 pages::topic::topic_page,
+                pages::zookeeper::zookeeper_page,
                 api::brokers,
                 api::cache_brokers,
                 api::cache_metrics,
                 api::cache_offsets,
+                api::cache_zk_offsets,
                 api::cluster_reassignment,
                 api::live_consumers,
+                api::offset_consumers,
                 api::cluster_groups,
                 api::cluster_topics,
                 api::consumer_search,
                 api::group_members,
                 api::group_offsets,
+                api::group_offsets_reset,
+                api::group_offsets_reset_preview,
                 api::topic_groups,
                 api::topic_search,
                 api::topic_topology,
+                api::diverging_replicas,
+                api::topic_acls,
+                api::group_acls,
+                api::principal_search,
+                api::cluster_quotas,
+                api::controller_changes,
                 live_consumer::topic_tailer_api,
+                search::topic_search_start,
+                search::topic_search_status,
+                search::topic_search_cancel,
+                prometheus::metrics,
             ],
         )
         .launch();
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_same_origin() {
+        assert!(is_same_origin("http://kafka-view:8080", "kafka-view:8080"));
+        assert!(is_same_origin(
+            "https://Kafka-View/clusters/prod?x=1",
+            "kafka-view"
+        ));
+        assert!(!is_same_origin("http://kafka-view:8081", "kafka-view:8080"));
+        assert!(!is_same_origin(
+            "http://evil.com/kafka-view:8080",
+            "kafka-view:8080"
+        ));
+        assert!(!is_same_origin("null", "kafka-view:8080"));
+    }
+}
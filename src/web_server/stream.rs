@@ -0,0 +1,334 @@
+//! Server-Sent Events server for the topic tailers. Rocket holds a request worker for the whole
+//! duration of a response, so the streams are served on a separate port, each by its own thread.
+
+use config::Config;
+use error::*;
+use live_consumer::{LiveConsumerStore, TailOptions};
+use metadata::ClusterId;
+use utils::percent_decode;
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Maximum size of the request line and headers.
+const MAX_REQUEST_SIZE: u64 = 16 * 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// A client not reading the stream for this long is disconnected.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A request for `/tailer/<cluster_id>/<topic>?start=<start>&value=<value>`, with the
+/// optional `header` and `header_value` filter.
+#[derive(Debug, PartialEq)]
+struct StreamRequest {
+    cluster_id: ClusterId,
+    topic: String,
+    start: Option<String>,
+    value: Option<String>,
+    header: Option<String>,
+    header_value: Option<String>,
+}
+
+/// Decodes the `%XX` escapes and the `+` of URL components.
+fn form_decode(text: &str) -> String {
+    percent_decode(&text.replace('+', " "))
+}
+
+fn parse_request_path(path: &str) -> Result<StreamRequest> {
+    let mut path_and_query = path.splitn(2, '?');
+    let segments = path_and_query
+        .next()
+        .unwrap_or("")
+        .split('/')
+        .map(form_decode)
+        .collect::<Vec<_>>();
+    let (cluster_id, topic) = match segments.as_slice() {
+        [empty, tailer, cluster_id, topic] if empty.is_empty() && tailer == "tailer" => {
+            (cluster_id.as_str(), topic.clone())
+        }
+        _ => bail!("Unknown path: {}", path),
+    };
+
+    let mut params = HashMap::new();
+    for param in path_and_query.next().unwrap_or("").split('&') {
+        let mut key_value = param.splitn(2, '=');
+        if let (Some(key), Some(value)) = (key_value.next(), key_value.next()) {
+            params.insert(form_decode(key), form_decode(value));
+        }
+    }
+    Ok(StreamRequest {
+        cluster_id: cluster_id.into(),
+        topic,
+        start: params.remove("start"),
+        value: params.remove("value"),
+        header: params.remove("header"),
+        header_value: params.remove("header_value"),
+    })
+}
+
+/// Reads the request line and the headers, with lowercase names.
+fn read_request(stream: &TcpStream) -> Result<(String, HashMap<String, String>)> {
+    let mut reader = BufReader::new(stream.take(MAX_REQUEST_SIZE));
+    let mut request_line = String::new();
+    reader
+        .read_line(&mut request_line)
+        .chain_err(|| "Failed to read the request")?;
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        let len = reader
+            .read_line(&mut line)
+            .chain_err(|| "Failed to read the request headers")?;
+        if len == 0 || line.trim().is_empty() {
+            break;
+        }
+        let mut name_value = line.splitn(2, ':');
+        if let (Some(name), Some(value)) = (name_value.next(), name_value.next()) {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_owned());
+        }
+    }
+    let mut parts = request_line.split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some("GET"), Some(path)) => Ok((path.to_owned(), headers)),
+        _ => bail!("Invalid request: {}", request_line.trim()),
+    }
+}
+
+/// The host of a `Host` header or of an origin URL, without scheme and port.
+fn host_name(host: &str) -> &str {
+    let host = match host.find("://") {
+        Some(index) => &host[index + 3..],
+        None => host,
+    };
+    if host.starts_with('[') {
+        // IPv6 address
+        return host.split(']').next().map(|h| &h[1..]).unwrap_or(host);
+    }
+    host.split(':').next().unwrap_or(host)
+}
+
+/// The streams can only be opened by the kafka-view pages, which are served by the same host on
+/// a different port: other web sites are not allowed to read the messages.
+fn allowed_origin(headers: &HashMap<String, String>) -> Option<String> {
+    match (headers.get("origin"), headers.get("host")) {
+        (Some(origin), Some(host)) if host_name(origin) == host_name(host) => Some(origin.clone()),
+        _ => None,
+    }
+}
+
+fn write_error(mut stream: &TcpStream, status: &str, message: &str) {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        message.len(),
+        message
+    );
+    if let Err(e) = stream.write_all(response.as_bytes()) {
+        debug!("Failed to write the stream error response: {}", e);
+    }
+}
+
+fn serve_stream(
+    stream: TcpStream,
+    config: &Config,
+    live_consumers: &LiveConsumerStore,
+) -> Result<()> {
+    stream
+        .set_read_timeout(Some(REQUEST_TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(WRITE_TIMEOUT)))
+        .chain_err(|| "Failed to set the socket timeouts")?;
+    let (path, headers) = read_request(&stream)?;
+    let origin = match allowed_origin(&headers) {
+        Some(origin) => origin,
+        None => {
+            write_error(&stream, "403 Forbidden", "Origin not allowed");
+            return Ok(());
+        }
+    };
+    let request = match parse_request_path(&path) {
+        Ok(request) => request,
+        Err(e) => {
+            write_error(&stream, "404 Not Found", &e.to_string());
+            return Ok(());
+        }
+    };
+    let cluster_config = match config.cluster(&request.cluster_id) {
+        Some(cluster_config) if cluster_config.enable_tailing => cluster_config,
+        _ => {
+            write_error(&stream, "404 Not Found", "Tailing is not available");
+            return Ok(());
+        }
+    };
+    let options = match TailOptions::parse(
+        request.start.as_ref().map(String::as_str),
+        request.value.as_ref().map(String::as_str),
+        request.header.as_ref().map(String::as_str),
+        request.header_value.as_ref().map(String::as_str),
+    ) {
+        Ok(options) => options,
+        Err(e) => {
+            write_error(&stream, "400 Bad Request", &e.to_string());
+            return Ok(());
+        }
+    };
+
+    let mut writer = &stream;
+    writer
+        .write_all(
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\
+                 Access-Control-Allow-Origin: {}\r\nConnection: close\r\n\r\n",
+                origin
+            )
+            .as_bytes(),
+        )
+        .chain_err(|| "Failed to write the response headers")?;
+    let result = live_consumers.stream_messages(
+        cluster_config,
+        &request.topic,
+        options,
+        config.stream_max_rate,
+        |batch, positions| {
+            // The positions are sent every second, and also keep the connection alive.
+            let mut event = match batch {
+                Some(batch) => format!("data: {}\n\n", batch),
+                None => String::new(),
+            };
+            event.push_str(&format!("event: positions\ndata: {}\n\n", positions));
+            writer
+                .write_all(event.as_bytes())
+                .and_then(|_| writer.flush())
+        },
+    );
+    if let Err(ref e) = result {
+        // The error is shown by the tailer, which stops instead of reconnecting.
+        let event = format!("event: failure\ndata: {}\n\n", e);
+        let _ = writer.write_all(event.as_bytes());
+    }
+    result
+}
+
+/// Decrements the number of open streams when dropped.
+struct ConnectionGuard(Arc<AtomicUsize>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Starts the stream server on `stream_port`, if configured.
+pub fn start_stream_server(config: &Config, live_consumers: LiveConsumerStore) -> Result<()> {
+    let port = match config.stream_port {
+        Some(port) => port,
+        None => {
+            info!("No stream_port configured: the topic tailers will poll the API.");
+            return Ok(());
+        }
+    };
+    let listener = TcpListener::bind((config.listen_host.as_str(), port))
+        .chain_err(|| format!("Failed to listen on {}:{}", config.listen_host, port))?;
+    info!(
+        "Streaming the topic tailers on {}:{}.",
+        config.listen_host, port
+    );
+
+    let config = Arc::new(config.clone());
+    let open_streams = Arc::new(AtomicUsize::new(0));
+    thread::Builder::new()
+        .name("stream-server".to_owned())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("Failed to accept stream connection: {}", e);
+                        continue;
+                    }
+                };
+                if open_streams.fetch_add(1, Ordering::SeqCst) >= config.stream_max_connections {
+                    open_streams.fetch_sub(1, Ordering::SeqCst);
+                    write_error(&stream, "503 Service Unavailable", "Too many open streams");
+                    continue;
+                }
+                let guard = ConnectionGuard(open_streams.clone());
+                let config = config.clone();
+                let live_consumers = live_consumers.clone();
+                let spawned = thread::Builder::new()
+                    .name("stream".to_owned())
+                    .spawn(move || {
+                        let _guard = guard;
+                        if let Err(e) = serve_stream(stream, &config, &live_consumers) {
+                            format_error_chain!(e);
+                        }
+                    });
+                if let Err(e) = spawned {
+                    error!("Failed to start stream thread: {}", e);
+                }
+            }
+        })
+        .chain_err(|| "Failed to start the stream server thread")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request_path() {
+        assert_eq!(
+            parse_request_path("/tailer/prod%20eu/orders?start=offsets&value=0%3A12%2C1%3A7")
+                .unwrap(),
+            StreamRequest {
+                cluster_id: "prod eu".into(),
+                topic: "orders".to_owned(),
+                start: Some("offsets".to_owned()),
+                value: Some("0:12,1:7".to_owned()),
+                header: None,
+                header_value: None,
+            }
+        );
+        assert_eq!(parse_request_path("/tailer/c/t").unwrap().start, None);
+        assert_eq!(
+            parse_request_path("/tailer/c/t?header=trace-id")
+                .unwrap()
+                .header,
+            Some("trace-id".to_owned())
+        );
+        assert!(parse_request_path("/tailer/c/t/1").is_err());
+        assert!(parse_request_path("/api/tailer/c/t").is_err());
+        assert_eq!(form_decode("100%"), "100%");
+        assert_eq!(form_decode("a+b%2B"), "a b+");
+    }
+
+    #[test]
+    fn test_allowed_origin() {
+        let headers = |origin: &str, host: &str| {
+            vec![
+                ("origin".to_owned(), origin.to_owned()),
+                ("host".to_owned(), host.to_owned()),
+            ]
+            .into_iter()
+            .collect::<HashMap<_, _>>()
+        };
+        assert_eq!(
+            allowed_origin(&headers("http://kafka-view:8080", "kafka-view:8081")),
+            Some("http://kafka-view:8080".to_owned())
+        );
+        assert_eq!(
+            allowed_origin(&headers("https://[::1]:8080", "[::1]:8081")),
+            Some("https://[::1]:8080".to_owned())
+        );
+        assert_eq!(
+            allowed_origin(&headers("http://evil.com", "kafka-view:8081")),
+            None
+        );
+        assert_eq!(allowed_origin(&HashMap::new()), None);
+    }
+}
@@ -1,6 +1,8 @@
 use crate::RUST_VERSION;
 use maud::{self, html, PreEscaped};
 
+use metrics::RateWindow;
+
 pub fn search_form(
     action: &str,
     placeholder: &str,
@@ -50,6 +52,30 @@ pub fn search_form(
     }
 }
 
+/// Buttons to change the rate window of the page. The other query parameters of the url are kept.
+pub fn rate_window_selector(url: &str, current: RateWindow) -> PreEscaped<String> {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    let buttons = RateWindow::all()
+        .iter()
+        .map(|&window| {
+            let link = format!("{}{}window={}", url, separator, window);
+            let class = if window == current {
+                "btn btn-default active"
+            } else {
+                "btn btn-default"
+            };
+            (window, link, class)
+        })
+        .collect::<Vec<_>>();
+    html! {
+        div class="btn-group btn-group-xs" role="group" {
+            @for &(window, ref link, class) in &buttons {
+                a class=(class) href=(link) { (window) }
+            }
+        }
+    }
+}
+
 pub fn notification(n_type: &str, content: PreEscaped<String>) -> PreEscaped<String> {
     let alert_class = format!("alert alert-{}", n_type);
     html! {
@@ -183,6 +209,7 @@ fn navbar_side() -> PreEscaped<String> {
                     li { a href="/clusters/" style="font-size: 12pt" { i class="fa fa-server fa-fw" {}  " Clusters" } }
                     li { a href="/topics/" style="font-size: 12pt" { i class="fa fa-cubes fa-fw" {}  " Topics" } }
                     li { a href="/consumers/" style="font-size: 12pt" { i class="fa fa-exchange fa-fw" {}  " Consumers" } }
+                    li { a href="/principals/" style="font-size: 12pt" { i class="fa fa-user fa-fw" {}  " Principals" } }
                     li {
                         a href="#" style="font-size: 12pt" {
                             i class="fa fa-gear fa-fw" {} " Internals"
@@ -199,6 +226,11 @@ fn navbar_side() -> PreEscaped<String> {
                                     i class="fa fa-microchip fa-fw" {}  " Live consumers"
                                 }
                             }
+                            li {
+                                a href="/internals/offset_consumers" {
+                                    i class="fa fa-microchip fa-fw" {}  " Offset consumers"
+                                }
+                            }
                             // li {
                             //     a href="#" { "Third Level" span class="fa arrow" {} }
                             //     ul class="nav nav-third-level" {
@@ -1,41 +1,589 @@
+use scheduled_executor::TaskGroup;
+use serde::de::DeserializeOwned;
 use serde_json;
-use zookeeper::{WatchedEvent, Watcher, ZkError, ZooKeeper};
+use zookeeper::{KeeperState, Stat, WatchedEvent, Watcher, ZkError, ZkState, ZooKeeper};
 
+use acls::{Acl, PatternType};
+use cache::Cache;
+use config::{ClusterConfig, Config};
 use error::*;
-use metadata::Reassignment;
+use metadata::{update_cluster_info, BrokerId, ClusterId, Reassignment, TopicName};
+use quotas::Quota;
+use utils::{insert_at, percent_decode};
 
+use std::collections::HashMap;
 use std::str;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, RwLock, Weak};
+use std::thread;
 use std::time::Duration;
 
 const REASSIGN_PARTITIONS: &str = "/admin/reassign_partitions";
+const CONTROLLER: &str = "/controller";
+const CLUSTER_ID: &str = "/cluster/id";
+const BROKER_IDS: &str = "/brokers/ids";
+const CONSUMERS: &str = "/consumers";
+const ACLS: &str = "/kafka-acl";
+const PREFIXED_ACLS: &str = "/kafka-acl-extended/prefixed";
+const CLIENT_CONFIGS: &str = "/config/clients";
+const USER_CONFIGS: &str = "/config/users";
 
-pub struct ZK {
-    client: ZooKeeper,
+const SESSION_TIMEOUT: Duration = Duration::from_secs(15);
+/// How often the watch thread checks if its session is still alive, when idle.
+const WATCH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    pub static ref ZK_SESSIONS: ZkSessionCache = ZkSessionCache::new();
+}
+
+/// What ZooKeeper knows about a cluster, kept up to date by watches.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ZkClusterState {
+    pub reassignment: Option<Reassignment>,
+    pub controller: Option<BrokerId>,
+    pub brokers: Vec<BrokerId>,
+}
+
+#[derive(Deserialize)]
+struct ControllerNode {
+    brokerid: BrokerId,
+}
+
+#[derive(Deserialize)]
+struct ClusterIdNode {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct EntityConfigNode {
+    config: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct AclNode {
+    acls: Vec<AclNodeEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AclNodeEntry {
+    principal: String,
+    permission_type: String,
+    operation: String,
+    host: String,
+}
+
+/// The nodes that are watched for each cluster.
+#[derive(Debug, Clone, Copy)]
+enum WatchedNode {
+    Reassignment,
+    Controller,
+    Brokers,
+}
+
+/// Marks the session as expired, so that it will be replaced by a new one.
+struct SessionWatcher {
+    expired: Arc<AtomicBool>,
+}
+
+impl Watcher for SessionWatcher {
+    fn handle(&self, event: WatchedEvent) {
+        if let KeeperState::Expired = event.keeper_state {
+            self.expired.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A znode, as shown by the ZooKeeper browser.
+pub struct Znode {
+    pub path: String,
+    pub data: Vec<u8>,
+    pub children: Vec<String>,
+    pub stat: Stat,
+}
+
+/// Validates a znode path, returning it without the trailing slash.
+pub fn normalize_znode_path(path: &str) -> Option<String> {
+    if path == "/" {
+        return Some(path.to_owned());
+    }
+    let path = path.trim_end_matches('/');
+    if !path.starts_with('/') {
+        return None;
+    }
+    let valid = path[1..]
+        .split('/')
+        .all(|name| !name.is_empty() && name != "." && name != "..");
+    if valid {
+        Some(path.to_owned())
+    } else {
+        None
+    }
 }
 
-struct NullWatcher;
-impl Watcher for NullWatcher {
-    fn handle(&self, _: WatchedEvent) {}
+/// True if the path or one of its ancestors is in the denylist.
+pub fn is_znode_denied(path: &str, denylist: &[String]) -> bool {
+    denylist.iter().any(|denied| {
+        let denied = denied.trim_end_matches('/');
+        denied.is_empty()
+            || path == denied
+            || (path.starts_with(denied) && path[denied.len()..].starts_with('/'))
+    })
+}
+
+pub struct ZK {
+    client: ZooKeeper,
+    expired: Arc<AtomicBool>,
 }
 
 impl ZK {
     pub fn new(url: &str) -> Result<ZK> {
-        ZooKeeper::connect(url, Duration::from_secs(15), NullWatcher)
-            .map(|client| ZK { client })
-            .chain_err(|| "Unable to connect to Zookeeper") // TODO: show url?
-    }
-
-    pub fn pending_reassignment(&self) -> Option<Reassignment> {
-        let data = match self.client.get_data(REASSIGN_PARTITIONS, false) {
-            Ok((data, _)) => data,
-            Err(ZkError::NoNode) => return None, // no pending reassignment node
-            Err(error) => {
-                println!("Error fetching reassignment: {:?}", error);
-                return None;
+        let expired = Arc::new(AtomicBool::new(false));
+        let client = ZooKeeper::connect(
+            url,
+            SESSION_TIMEOUT,
+            SessionWatcher {
+                expired: expired.clone(),
+            },
+        )
+        .chain_err(|| format!("Unable to connect to ZooKeeper {}", url))?;
+        let listener_expired = expired.clone();
+        client.add_listener(move |state| {
+            if let ZkState::Closed = state {
+                listener_expired.store(true, Ordering::Relaxed);
+            }
+        });
+        Ok(ZK { client, expired })
+    }
+
+    /// True if the session expired or was closed. The client can't be used anymore.
+    pub fn is_expired(&self) -> bool {
+        self.expired.load(Ordering::Relaxed)
+    }
+
+    /// Returns the offsets committed to ZooKeeper by legacy consumers, as
+    /// `(group, topic, offsets)`, reading `/consumers/<group>/offsets/<topic>/<partition>`.
+    /// Partitions without an offset are set to `-1`. Only the partitions below the
+    /// `partition_count` of each topic are read, and the unknown topics are skipped.
+    pub fn consumer_offsets<F>(
+        &self,
+        partition_count: F,
+    ) -> Result<Vec<(String, TopicName, Vec<i64>)>>
+    where
+        F: Fn(&str) -> Option<usize>,
+    {
+        let mut result = Vec::new();
+        for group in self.children(CONSUMERS)? {
+            let group_path = format!("{}/{}/offsets", CONSUMERS, group);
+            for topic in self.children(&group_path)? {
+                let topic_path = format!("{}/{}", group_path, topic);
+                let partition_count = match partition_count(&topic) {
+                    Some(count) => count,
+                    None => continue,
+                };
+                let mut offsets = Vec::new();
+                for partition in self.children(&topic_path)? {
+                    let partition_path = format!("{}/{}", topic_path, partition);
+                    let partition = match partition.parse::<usize>() {
+                        Ok(partition) if partition < partition_count => partition,
+                        _ => {
+                            warn!("Invalid consumer offset partition {}", partition_path);
+                            continue;
+                        }
+                    };
+                    let offset = match self.client.get_data(&partition_path, false) {
+                        Ok((data, _)) => data,
+                        Err(ZkError::NoNode) => continue, // deleted while walking the tree
+                        Err(e) => {
+                            return Err(e)
+                                .chain_err(|| format!("Failed to read {}", partition_path));
+                        }
+                    };
+                    let parsed = str::from_utf8(&offset)
+                        .ok()
+                        .and_then(|offset| offset.trim().parse::<i64>().ok());
+                    match parsed {
+                        Some(offset) => insert_at(&mut offsets, partition, offset, -1),
+                        None => warn!("Invalid consumer offset found in {}", partition_path),
+                    }
+                }
+                if !offsets.is_empty() {
+                    result.push((group.clone(), topic, offsets));
+                }
             }
+        }
+        Ok(result)
+    }
+
+    /// Reads a znode and the names of its children, without setting any watch.
+    pub fn znode(&self, path: &str) -> Result<Option<Znode>> {
+        let (data, stat) = match self.client.get_data(path, false) {
+            Ok(result) => result,
+            Err(ZkError::NoNode) => return Ok(None),
+            Err(e) => return Err(e).chain_err(|| format!("Failed to read {}", path)),
         };
+        let mut children = self.children(path)?;
+        children.sort();
+        Ok(Some(Znode {
+            path: path.to_owned(),
+            data,
+            children,
+            stat,
+        }))
+    }
+
+    /// Returns the ACLs stored by the Kafka authorizer, reading
+    /// `/kafka-acl/<resource type>/<resource name>` and the prefixed ACLs in
+    /// `/kafka-acl-extended/prefixed`.
+    pub fn acls(&self) -> Result<Vec<Acl>> {
+        let mut result = Vec::new();
+        for &(root, pattern_type) in &[
+            (ACLS, PatternType::Literal),
+            (PREFIXED_ACLS, PatternType::Prefixed),
+        ] {
+            for resource_type in self.children(root)? {
+                let type_path = format!("{}/{}", root, resource_type);
+                for resource_name in self.children(&type_path)? {
+                    let resource_path = format!("{}/{}", type_path, resource_name);
+                    let data = match self.client.get_data(&resource_path, false) {
+                        Ok((data, _)) => data,
+                        Err(ZkError::NoNode) => continue, // deleted while walking the tree
+                        Err(e) => {
+                            return Err(e)
+                                .chain_err(|| format!("Failed to read {}", resource_path));
+                        }
+                    };
+                    let node = match parse_node::<AclNode>(&data) {
+                        Some(node) => node,
+                        None => {
+                            warn!("Invalid ACLs found in {}", resource_path);
+                            continue;
+                        }
+                    };
+                    result.extend(node.acls.into_iter().map(|entry| Acl {
+                        resource_type: resource_type.clone(),
+                        resource_name: resource_name.clone(),
+                        pattern_type,
+                        principal: entry.principal,
+                        operation: entry.operation,
+                        permission_type: entry.permission_type,
+                        host: entry.host,
+                    }));
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Returns the quotas of the users and client ids, reading `/config/clients/<client id>`,
+    /// `/config/users/<user>` and `/config/users/<user>/clients/<client id>`.
+    pub fn quotas(&self) -> Result<Vec<Quota>> {
+        // The node names are the user and client ids sanitized (URL-encoded) by Kafka
+        let mut entities = Vec::new();
+        for client_id in self.children(CLIENT_CONFIGS)? {
+            let path = format!("{}/{}", CLIENT_CONFIGS, client_id);
+            entities.push((path, None, Some(percent_decode(&client_id))));
+        }
+        for user in self.children(USER_CONFIGS)? {
+            let user_path = format!("{}/{}", USER_CONFIGS, user);
+            for client_id in self.children(&format!("{}/clients", user_path))? {
+                let path = format!("{}/clients/{}", user_path, client_id);
+                entities.push((
+                    path,
+                    Some(percent_decode(&user)),
+                    Some(percent_decode(&client_id)),
+                ));
+            }
+            entities.push((user_path, Some(percent_decode(&user)), None));
+        }
+
+        let mut result = Vec::new();
+        for (path, user, client_id) in entities {
+            let data = match self.client.get_data(&path, false) {
+                Ok((data, _)) => data,
+                Err(ZkError::NoNode) => continue, // deleted while walking the tree
+                Err(e) => return Err(e).chain_err(|| format!("Failed to read {}", path)),
+            };
+            match parse_node::<EntityConfigNode>(&data) {
+                Some(node) => result.extend(Quota::from_config(user, client_id, &node.config)),
+                None => warn!("Invalid config found in {}", path),
+            }
+        }
+        Ok(result)
+    }
+
+    /// Returns the id Kafka generated for the cluster, stored in `/cluster/id`.
+    pub fn kafka_cluster_id(&self) -> Result<Option<String>> {
+        Ok(self
+            .data(CLUSTER_ID)?
+            .and_then(|data| parse_node::<ClusterIdNode>(&data))
+            .map(|node| node.id))
+    }
+
+    /// Returns the data of the node, or nothing if the node doesn't exist.
+    fn data(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        match self.client.get_data(path, false) {
+            Ok((data, _)) => Ok(Some(data)),
+            Err(ZkError::NoNode) => Ok(None),
+            Err(e) => Err(e).chain_err(|| format!("Failed to read {}", path)),
+        }
+    }
+
+    /// Returns the children of the node, or nothing if the node doesn't exist.
+    fn children(&self, path: &str) -> Result<Vec<String>> {
+        match self.client.get_children(path, false) {
+            Ok(children) => Ok(children),
+            Err(ZkError::NoNode) => Ok(Vec::new()),
+            Err(e) => Err(e).chain_err(|| format!("Failed to list children of {}", path)),
+        }
+    }
+
+    /// Reads the data of the node, if it exists. The node is sent to `changes` the next time it
+    /// is created, deleted or updated.
+    fn watch_data(
+        &self,
+        path: &str,
+        node: WatchedNode,
+        changes: &Sender<WatchedNode>,
+    ) -> Result<Option<Vec<u8>>> {
+        let changes = changes.clone();
+        let stat = self
+            .client
+            .exists_w(path, move |_| {
+                let _ = changes.send(node);
+            })
+            .chain_err(|| format!("Failed to watch {}", path))?;
+        if stat.is_none() {
+            return Ok(None);
+        }
+        match self.client.get_data(path, false) {
+            Ok((data, _)) => Ok(Some(data)),
+            Err(ZkError::NoNode) => Ok(None), // deleted in the meantime, the watch will fire
+            Err(e) => Err(e).chain_err(|| format!("Failed to read {}", path)),
+        }
+    }
+
+    /// Returns the children of the node. The node is sent to `changes` the next time its
+    /// children change.
+    fn watch_children(
+        &self,
+        path: &str,
+        node: WatchedNode,
+        changes: &Sender<WatchedNode>,
+    ) -> Result<Vec<String>> {
+        let changes = changes.clone();
+        match self.client.get_children_w(path, move |_| {
+            let _ = changes.send(node);
+        }) {
+            Ok(children) => Ok(children),
+            Err(ZkError::NoNode) => Ok(Vec::new()),
+            Err(e) => Err(e).chain_err(|| format!("Failed to watch children of {}", path)),
+        }
+    }
+}
+
+fn parse_node<T: DeserializeOwned>(data: &[u8]) -> Option<T> {
+    str::from_utf8(data)
+        .ok()
+        .and_then(|raw| serde_json::from_str(raw).ok())
+}
+
+/// Reads the watched node again, setting a new watch, and stores its content in the cache.
+fn update_cluster_state(
+    zk: &ZK,
+    cluster_id: &ClusterId,
+    cache: &Cache,
+    node: WatchedNode,
+    changes: &Sender<WatchedNode>,
+) -> Result<()> {
+    let mut state = cache.zk_state.get(cluster_id).unwrap_or_default();
+    match node {
+        WatchedNode::Reassignment => {
+            state.reassignment = zk
+                .watch_data(REASSIGN_PARTITIONS, node, changes)?
+                .and_then(|data| parse_node(&data));
+        }
+        WatchedNode::Controller => {
+            state.controller = zk
+                .watch_data(CONTROLLER, node, changes)?
+                .and_then(|data| parse_node::<ControllerNode>(&data))
+                .map(|controller| controller.brokerid);
+            let kafka_cluster_id = zk
+                .kafka_cluster_id()
+                .chain_err(|| "Failed to read the cluster id from ZooKeeper")?;
+            update_cluster_info(cache, cluster_id, state.controller, kafka_cluster_id)?;
+        }
+        WatchedNode::Brokers => {
+            let mut brokers = zk
+                .watch_children(BROKER_IDS, node, changes)?
+                .iter()
+                .filter_map(|id| id.parse::<BrokerId>().ok())
+                .collect::<Vec<_>>();
+            brokers.sort();
+            state.brokers = brokers;
+        }
+    }
+    cache
+        .zk_state
+        .insert(cluster_id.clone(), state)
+        .chain_err(|| "Failed to insert ZooKeeper state in cache")
+}
+
+/// Handles the watch notifications of a session, until the session expires or is dropped.
+fn run_watch_loop(
+    zk: Weak<ZK>,
+    cluster_id: ClusterId,
+    cache: Cache,
+    changes: Sender<WatchedNode>,
+    receiver: Receiver<WatchedNode>,
+) {
+    loop {
+        let node = match receiver.recv_timeout(WATCH_CHECK_INTERVAL) {
+            Ok(node) => Some(node),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        let session = match zk.upgrade() {
+            Some(ref session) if !session.is_expired() => session.clone(),
+            _ => break,
+        };
+        if let Some(node) = node {
+            if let Err(e) = update_cluster_state(&session, &cluster_id, &cache, node, &changes) {
+                format_error_chain!(e);
+            }
+        }
+    }
+    debug!("Stopped ZooKeeper watches for {}", cluster_id);
+}
+
+/// A ZooKeeper session for each cluster, similar to `MetadataConsumerCache`. Each session
+/// watches the reassignment, controller and broker nodes of its cluster and stores them in
+/// `Cache::zk_state`. Expired sessions are replaced on the next `get_or_init`.
+pub struct ZkSessionCache {
+    sessions: RwLock<HashMap<ClusterId, Arc<ZK>>>,
+}
+
+impl ZkSessionCache {
+    pub fn new() -> ZkSessionCache {
+        ZkSessionCache {
+            sessions: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, cluster_id: &ClusterId) -> Option<Arc<ZK>> {
+        match self.sessions.read() {
+            Ok(sessions) => (*sessions)
+                .get(cluster_id)
+                .filter(|zk| !zk.is_expired())
+                .cloned(),
+            Err(_) => panic!("Poison error while reading ZooKeeper session from cache"),
+        }
+    }
+
+    pub fn get_or_init(
+        &self,
+        cluster_id: &ClusterId,
+        config: &ClusterConfig,
+        cache: &Cache,
+    ) -> Result<Arc<ZK>> {
+        if let Some(zk) = self.get(cluster_id) {
+            return Ok(zk);
+        }
+
+        let mut sessions = match self.sessions.write() {
+            Ok(sessions) => sessions,
+            Err(_) => panic!("Poison error while writing ZooKeeper session to cache"),
+        };
+        // Another thread might have created the session in the meantime
+        if let Some(zk) = sessions.get(cluster_id).filter(|zk| !zk.is_expired()) {
+            return Ok(zk.clone());
+        }
+
+        let zookeeper = match config.zookeeper {
+            Some(ref zookeeper) => zookeeper,
+            None => bail!("ZooKeeper is not configured for {}", cluster_id),
+        };
+        debug!("Creating ZooKeeper session for {}", cluster_id);
+        let zk = Arc::new(
+            ZK::new(zookeeper)
+                .chain_err(|| format!("ZooKeeper session creation failed for {}", cluster_id))?,
+        );
+        let (changes, receiver) = mpsc::channel();
+        for &node in &[
+            WatchedNode::Reassignment,
+            WatchedNode::Controller,
+            WatchedNode::Brokers,
+        ] {
+            let _ = changes.send(node);
+        }
+        let weak_zk = Arc::downgrade(&zk);
+        let cluster_id_clone = cluster_id.clone();
+        let cache_alias = cache.alias();
+        let _ = thread::Builder::new()
+            .name("zk-watch".to_owned())
+            .spawn(move || {
+                run_watch_loop(weak_zk, cluster_id_clone, cache_alias, changes, receiver)
+            })
+            .chain_err(|| "Failed to start ZooKeeper watch thread")?;
+
+        sessions.insert(cluster_id.clone(), zk.clone());
+        Ok(zk)
+    }
+}
+
+/// Makes sure every cluster using ZooKeeper has a live session, reconnecting the expired ones.
+pub struct ZkSessionTaskGroup {
+    cache: Cache,
+    config: Config,
+}
+
+impl ZkSessionTaskGroup {
+    pub fn new(cache: &Cache, config: &Config) -> ZkSessionTaskGroup {
+        ZkSessionTaskGroup {
+            cache: cache.alias(),
+            config: config.clone(),
+        }
+    }
+}
+
+impl TaskGroup for ZkSessionTaskGroup {
+    type TaskId = ClusterId;
+
+    fn get_tasks(&self) -> Vec<ClusterId> {
+        self.config.zookeeper_clusters()
+    }
+
+    fn execute(&self, cluster_id: ClusterId) {
+        let cluster_config = self.config.cluster(&cluster_id).unwrap();
+        if let Err(e) = ZK_SESSIONS.get_or_init(&cluster_id, cluster_config, &self.cache) {
+            format_error_chain!(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_znode_path() {
+        assert_eq!(normalize_znode_path("/"), Some("/".to_owned()));
+        assert_eq!(
+            normalize_znode_path("/brokers/ids/"),
+            Some("/brokers/ids".to_owned())
+        );
+        assert_eq!(normalize_znode_path("brokers"), None);
+        assert_eq!(normalize_znode_path("/brokers//ids"), None);
+        assert_eq!(normalize_znode_path("/config/../brokers"), None);
+    }
 
-        let raw = str::from_utf8(&data).ok()?;
-        serde_json::from_str(raw).ok()
+    #[test]
+    fn test_is_znode_denied() {
+        let denylist = vec!["/config/users".to_owned()];
+        assert!(is_znode_denied("/config/users", &denylist));
+        assert!(is_znode_denied("/config/users/alice", &denylist));
+        assert!(!is_znode_denied("/config/users2", &denylist));
+        assert!(!is_znode_denied("/config", &denylist));
     }
 }